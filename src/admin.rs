@@ -0,0 +1,794 @@
+//!
+//! Define `MQAdmin`, which inspects and manages clusters through name servers and brokers, as ops tooling does.
+//!
+use crate::client::{Client, ClientOption};
+use crate::error::ClientError;
+use crate::filter;
+use crate::frame::{Frame, RequestCode, ResponseCode};
+use crate::message::{self, MessageExt, MessageQueue};
+use crate::protocol::{
+    self, BrokerRuntimeInfo, ClusterInfo, ConsumeStats, ConsumerConnection, SubscriptionGroupConfig,
+};
+use crate::validator;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Group on whose behalf messages are pulled for inspection, as the Java tools do.
+const TOOLS_CONSUMER_GROUP: &str = "TOOLS_CONSUMER";
+
+/// Most messages each broker is asked for when looking up a unique key, which messages sent more than once share.
+const UNIQUE_KEY_QUERY_MAX_NUM: i32 = 32;
+
+/// How brokers filter messages of a topic by tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TopicFilterType {
+    /// Each message carries a single tag.
+    #[default]
+    SingleTag,
+
+    MultiTag,
+}
+
+impl TopicFilterType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TopicFilterType::SingleTag => "SINGLE_TAG",
+            TopicFilterType::MultiTag => "MULTI_TAG",
+        }
+    }
+}
+
+/// Configuration of a topic as created on each broker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicConfig {
+    pub topic_name: String,
+    pub read_queue_nums: i32,
+    pub write_queue_nums: i32,
+
+    /// Bitwise or of `protocol::PERM_READ`, `protocol::PERM_WRITE` and `protocol::PERM_INHERIT`.
+    pub perm: i32,
+    pub topic_filter_type: TopicFilterType,
+
+    /// Whether messages of the topic are ordered within each queue.
+    pub order: bool,
+}
+
+impl TopicConfig {
+    /// Configure a readable and writable topic of 16 queues, as brokers do by default.
+    pub fn new(topic_name: &str) -> Self {
+        Self {
+            topic_name: topic_name.to_owned(),
+            read_queue_nums: 16,
+            write_queue_nums: 16,
+            perm: protocol::PERM_READ | protocol::PERM_WRITE,
+            topic_filter_type: TopicFilterType::default(),
+            order: false,
+        }
+    }
+}
+
+/// Administrative client of RocketMQ clusters.
+pub struct MQAdmin {
+    client: Client,
+}
+
+impl MQAdmin {
+    pub fn new(option: ClientOption) -> Result<Self, ClientError> {
+        Ok(Self {
+            client: Client::new(option)?,
+        })
+    }
+
+    /// Brokers registered with name servers, grouped by cluster.
+    ///
+    /// # Errors
+    /// Raise ClientError if no name server responds, or if it fails.
+    pub async fn cluster_info(&self) -> Result<ClusterInfo, ClientError> {
+        let response = self
+            .client
+            .invoke_name_server(|| {
+                Frame::request(RequestCode::GetBrokerClusterInfo, HashMap::new())
+            })
+            .await?;
+        response.ensure_success()?;
+        response.json_body()
+    }
+
+    /// Create the topic on the master of each broker of the cluster, or update its configuration where it exists
+    /// already. Brokers register the topic with name servers along with their next heartbeat.
+    ///
+    /// # Errors
+    /// Raise ClientError::ClusterNotFound if the cluster has no master, or ClientError if the topic name is invalid or
+    /// any broker fails, in which case brokers before it keep the change.
+    pub async fn create_topic(
+        &self,
+        cluster: &str,
+        config: &TopicConfig,
+    ) -> Result<(), ClientError> {
+        validator::check_topic(&config.topic_name)?;
+        for addr in self.master_addrs(cluster).await? {
+            let header = protocol::CreateTopicRequestHeader {
+                topic: config.topic_name.clone(),
+                default_topic: validator::AUTO_CREATE_TOPIC_KEY_TOPIC.to_owned(),
+                read_queue_nums: config.read_queue_nums,
+                write_queue_nums: config.write_queue_nums,
+                perm: config.perm,
+                topic_filter_type: config.topic_filter_type.as_str().to_owned(),
+                topic_sys_flag: 0,
+                order: config.order,
+            };
+            let frame = Frame::request(RequestCode::UpdateAndCreateTopic, header);
+            self.client
+                .invoke_vip(&addr, frame)
+                .await?
+                .ensure_success()?;
+        }
+        Ok(())
+    }
+
+    /// Delete the topic from the masters of the cluster, then its route within the cluster from all name servers.
+    ///
+    /// # Errors
+    /// Raise ClientError::ClusterNotFound if the cluster has no master, or ClientError if any broker or name server
+    /// fails.
+    pub async fn delete_topic(&self, cluster: &str, topic: &str) -> Result<(), ClientError> {
+        for addr in self.master_addrs(cluster).await? {
+            let header = protocol::DeleteTopicRequestHeader {
+                topic: topic.to_owned(),
+                cluster_name: None,
+            };
+            let frame = Frame::request(RequestCode::DeleteTopicInBroker, header);
+            self.client
+                .invoke_vip(&addr, frame)
+                .await?
+                .ensure_success()?;
+        }
+        for addr in self.client.routes.endpoints() {
+            let header = protocol::DeleteTopicRequestHeader {
+                topic: topic.to_owned(),
+                cluster_name: Some(cluster.to_owned()),
+            };
+            let frame = Frame::request(RequestCode::DeleteTopicInNamesrv, header);
+            self.client.invoke(&addr, frame).await?.ensure_success()?;
+        }
+        Ok(())
+    }
+
+    /// Names of all topics known by name servers, sorted, including those brokers use internally.
+    ///
+    /// # Errors
+    /// Raise ClientError if no name server responds, or if it fails.
+    pub async fn topic_list(&self) -> Result<Vec<String>, ClientError> {
+        let response = self
+            .client
+            .invoke_name_server(|| {
+                Frame::request(RequestCode::GetAllTopicListFromNameServer, HashMap::new())
+            })
+            .await?;
+        response.ensure_success()?;
+        let mut topics: Vec<String> = response
+            .json_body::<protocol::TopicList>()?
+            .topic_list
+            .into_iter()
+            .collect();
+        topics.sort_unstable();
+        Ok(topics)
+    }
+
+    /// Progress of the consumer group on each queue of the topic, merged from all brokers serving it.
+    ///
+    /// # Errors
+    /// Raise ClientError::RouteNotFound if the topic does not exist, or ClientError if any broker fails.
+    pub async fn consume_stats(
+        &self,
+        group: &str,
+        topic: &str,
+    ) -> Result<ConsumeStats, ClientError> {
+        let route = self
+            .client
+            .routes
+            .query(
+                topic,
+                &self.client.connections,
+                self.client.option.request_timeout,
+            )
+            .await?;
+        let mut stats = ConsumeStats::default();
+        for addr in route.broker_datas.iter().filter_map(|b| b.master_addr()) {
+            let header = protocol::GetConsumeStatsRequestHeader {
+                consumer_group: group.to_owned(),
+                topic: topic.to_owned(),
+            };
+            let frame = Frame::request(RequestCode::GetConsumeStats, header);
+            let response = self.client.invoke_vip(addr, frame).await?;
+            response.ensure_success()?;
+            stats.merge(response.json_body()?);
+        }
+        Ok(stats)
+    }
+
+    /// Members of the consumer group online and their subscriptions, as known by a broker serving its retry topic,
+    /// which every member of the group heartbeats to.
+    ///
+    /// # Errors
+    /// Raise ClientError::RouteNotFound if the group has never consumed, or ClientError::ServerError with code
+    /// CONSUMER_NOT_ONLINE if no member is online.
+    pub async fn consumer_connection_list(
+        &self,
+        group: &str,
+    ) -> Result<ConsumerConnection, ClientError> {
+        let retry_topic = message::retry_topic(group);
+        let route = self
+            .client
+            .routes
+            .query(
+                &retry_topic,
+                &self.client.connections,
+                self.client.option.request_timeout,
+            )
+            .await?;
+        let addr = route
+            .broker_datas
+            .iter()
+            .find_map(|broker_data| broker_data.master_addr())
+            .ok_or_else(|| ClientError::RouteNotFound(retry_topic.clone()))?;
+        let header = protocol::GetConsumerListByGroupRequestHeader {
+            consumer_group: group.to_owned(),
+        };
+        let frame = Frame::request(RequestCode::GetConsumerConnectionList, header);
+        let response = self.client.invoke_vip(addr, frame).await?;
+        response.ensure_success()?;
+        response.json_body()
+    }
+
+    /// Fetch the message by the offset message id brokers assigned it, which locates it in the commit log of the
+    /// broker storing it.
+    ///
+    /// # Errors
+    /// Raise ClientError::InvalidMessageId if the id is malformed, or ClientError::ServerError if the broker does not
+    /// store the message.
+    pub async fn view_message_by_id(&self, offset_msg_id: &str) -> Result<MessageExt, ClientError> {
+        let (store_host, offset) = message::decode_offset_msg_id(offset_msg_id)?;
+        let frame = Frame::request(
+            RequestCode::ViewMessageById,
+            protocol::ViewMessageRequestHeader { offset },
+        );
+        let response = self
+            .client
+            .invoke_vip(&store_host.to_string(), frame)
+            .await?;
+        response.ensure_success()?;
+        MessageExt::decode_batch(response.body())?
+            .into_iter()
+            .next()
+            .ok_or_else(|| ClientError::InvalidFrame("Response carries no message".to_owned()))
+    }
+
+    /// Messages of the topic carrying the key, stored between `begin` and `end`, oldest first and at most `max_num`
+    /// of them, looked up in the index of every broker serving the topic.
+    ///
+    /// # Errors
+    /// Raise ClientError::RouteNotFound if the topic does not exist, or ClientError if any broker fails.
+    pub async fn query_message(
+        &self,
+        topic: &str,
+        key: &str,
+        begin: SystemTime,
+        end: SystemTime,
+        max_num: i32,
+    ) -> Result<Vec<MessageExt>, ClientError> {
+        let millis = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or_default()
+        };
+        self.query(topic, key, millis(begin), millis(end), max_num, false)
+            .await
+    }
+
+    /// Message of the topic with the unique key, that is the message id clients assign, or `None` if none is
+    /// found. Of messages sent more than once, the one stored first is returned.
+    ///
+    /// # Errors
+    /// Raise ClientError::RouteNotFound if the topic does not exist, or ClientError if any broker fails.
+    pub async fn query_by_unique_key(
+        &self,
+        topic: &str,
+        msg_id: &str,
+    ) -> Result<Option<MessageExt>, ClientError> {
+        let messages = self
+            .query(topic, msg_id, 0, i64::MAX, UNIQUE_KEY_QUERY_MAX_NUM, true)
+            .await?;
+        Ok(messages.into_iter().next())
+    }
+
+    /// Message stored at the offset of the queue, or `None` if the offset is out of range, fetched by a single pull.
+    ///
+    /// # Errors
+    /// Raise ClientError::BrokerNotFound if the broker has no master, or ClientError if it fails.
+    pub async fn view_message(
+        &self,
+        mq: &MessageQueue,
+        offset: i64,
+    ) -> Result<Option<MessageExt>, ClientError> {
+        let addr = self.client.find_master(&mq.topic, &mq.broker_name).await?;
+        let header = protocol::PullMessageRequestHeader {
+            consumer_group: TOOLS_CONSUMER_GROUP.to_owned(),
+            topic: mq.topic.clone(),
+            queue_id: mq.queue_id,
+            queue_offset: offset,
+            max_msg_nums: 1,
+            sys_flag: protocol::PULL_FLAG_SUBSCRIPTION,
+            commit_offset: 0,
+            suspend_timeout_millis: 0,
+            subscription: Some(filter::SUB_ALL.to_owned()),
+            sub_version: 0,
+            expression_type: None,
+        };
+        let response = self
+            .client
+            .invoke(&addr, Frame::request(RequestCode::PullMessage, header))
+            .await?;
+        if response.code == ResponseCode::PullNotFound as i32
+            || response.code == ResponseCode::PullOffsetMoved as i32
+            || response.code == ResponseCode::PullRetryImmediately as i32
+        {
+            return Ok(None);
+        }
+        response.ensure_success()?;
+        let message = MessageExt::decode_batch(response.body())?
+            .into_iter()
+            .find(|message| message.queue_offset == offset)
+            .map(|message| MessageExt {
+                broker_name: mq.broker_name.clone(),
+                ..message
+            });
+        Ok(message)
+    }
+
+    /// Look up the key in the index of every broker serving the topic. Messages are filtered by key again, as
+    /// indexes are hashed, and returned oldest first.
+    async fn query(
+        &self,
+        topic: &str,
+        key: &str,
+        begin_timestamp: i64,
+        end_timestamp: i64,
+        max_num: i32,
+        unique_key_query: bool,
+    ) -> Result<Vec<MessageExt>, ClientError> {
+        let route = self
+            .client
+            .routes
+            .query(
+                topic,
+                &self.client.connections,
+                self.client.option.request_timeout,
+            )
+            .await?;
+        let mut messages = vec![];
+        for broker_data in &route.broker_datas {
+            // Slaves serve queries while the master is down.
+            let Some((addr, _)) = broker_data.select_addr(protocol::MASTER_ID) else {
+                continue;
+            };
+            let header = protocol::QueryMessageRequestHeader {
+                topic: topic.to_owned(),
+                key: key.to_owned(),
+                max_num,
+                begin_timestamp,
+                end_timestamp,
+                unique_key_query,
+            };
+            let frame = Frame::request(RequestCode::QueryMessage, header);
+            let response = self.client.invoke_vip(addr, frame).await?;
+            if response.code == ResponseCode::QueryNotFound as i32 {
+                continue;
+            }
+            response.ensure_success()?;
+            messages.extend(MessageExt::decode_batch(response.body())?.into_iter().map(
+                |message| MessageExt {
+                    broker_name: broker_data.broker_name.clone(),
+                    ..message
+                },
+            ));
+        }
+        messages.retain(|message| {
+            if unique_key_query {
+                message.msg_id == key
+            } else {
+                message.topic == topic && message.keys.iter().any(|k| k == key)
+            }
+        });
+        messages.sort_by_key(|message| message.store_timestamp);
+        messages.truncate(max_num.max(0) as usize);
+        Ok(messages)
+    }
+
+    /// Create the consumer group on the master of each broker of the cluster, or update its configuration where it
+    /// exists already.
+    ///
+    /// # Errors
+    /// Raise ClientError::ClusterNotFound if the cluster has no master, or ClientError if the group name is invalid or
+    /// any broker fails, in which case brokers before it keep the change.
+    pub async fn create_subscription_group(
+        &self,
+        cluster: &str,
+        config: &SubscriptionGroupConfig,
+    ) -> Result<(), ClientError> {
+        validator::check_group(&config.group_name)?;
+        for addr in self.master_addrs(cluster).await? {
+            let mut frame = Frame::request(
+                RequestCode::UpdateAndCreateSubscriptionGroup,
+                HashMap::new(),
+            );
+            frame.set_json_body(config)?;
+            self.client
+                .invoke_vip(&addr, frame)
+                .await?
+                .ensure_success()?;
+        }
+        Ok(())
+    }
+
+    /// Delete the consumer group from the masters of the cluster, along with its committed offsets if
+    /// `clean_offset`.
+    ///
+    /// # Errors
+    /// Raise ClientError::ClusterNotFound if the cluster has no master, or ClientError if any broker fails.
+    pub async fn delete_subscription_group(
+        &self,
+        cluster: &str,
+        group: &str,
+        clean_offset: bool,
+    ) -> Result<(), ClientError> {
+        for addr in self.master_addrs(cluster).await? {
+            let header = protocol::DeleteSubscriptionGroupRequestHeader {
+                group_name: group.to_owned(),
+                clean_offset,
+            };
+            let frame = Frame::request(RequestCode::DeleteSubscriptionGroup, header);
+            self.client
+                .invoke_vip(&addr, frame)
+                .await?
+                .ensure_success()?;
+        }
+        Ok(())
+    }
+
+    /// Configuration of every consumer group known by the broker at `addr`, keyed by group name.
+    ///
+    /// # Errors
+    /// Raise ClientError if the broker fails.
+    pub async fn subscription_groups(
+        &self,
+        addr: &str,
+    ) -> Result<HashMap<String, SubscriptionGroupConfig>, ClientError> {
+        let frame = Frame::request(RequestCode::GetAllSubscriptionGroupConfig, HashMap::new());
+        let response = self.client.invoke_vip(addr, frame).await?;
+        response.ensure_success()?;
+        Ok(response
+            .json_body::<protocol::SubscriptionGroupWrapper>()?
+            .subscription_group_table)
+    }
+
+    /// Configuration of the broker at `addr`, as loaded from its properties file and updated since.
+    ///
+    /// # Errors
+    /// Raise ClientError if the broker fails.
+    pub async fn broker_config(&self, addr: &str) -> Result<HashMap<String, String>, ClientError> {
+        let frame = Frame::request(RequestCode::GetBrokerConfig, HashMap::new());
+        let response = self.client.invoke_vip(addr, frame).await?;
+        response.ensure_success()?;
+        Ok(protocol::decode_config(&String::from_utf8_lossy(
+            &response.body(),
+        )))
+    }
+
+    /// Update entries of the configuration of the broker at `addr`, which it persists and applies at runtime where
+    /// supported. Entries not given are left unchanged.
+    ///
+    /// # Errors
+    /// Raise ClientError if the broker fails.
+    pub async fn update_broker_config(
+        &self,
+        addr: &str,
+        config: &HashMap<String, String>,
+    ) -> Result<(), ClientError> {
+        let mut frame = Frame::request(RequestCode::UpdateBrokerConfig, HashMap::new());
+        frame.body = bytes::Bytes::from(protocol::encode_config(config));
+        self.client.invoke_vip(addr, frame).await?.ensure_success()
+    }
+
+    /// Runtime statistics of the broker at `addr`, such as its version, throughput and disk usage.
+    ///
+    /// # Errors
+    /// Raise ClientError if the broker fails.
+    pub async fn broker_runtime_info(&self, addr: &str) -> Result<BrokerRuntimeInfo, ClientError> {
+        let frame = Frame::request(RequestCode::GetBrokerRuntimeInfo, HashMap::new());
+        let response = self.client.invoke_vip(addr, frame).await?;
+        response.ensure_success()?;
+        response.json_body()
+    }
+
+    /// Addresses of the masters of all brokers of the cluster.
+    async fn master_addrs(&self, cluster: &str) -> Result<Vec<String>, ClientError> {
+        let cluster_info = self.cluster_info().await?;
+        let addrs: Vec<String> = cluster_info
+            .master_addrs(cluster)
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        if addrs.is_empty() {
+            return Err(ClientError::ClusterNotFound(cluster.to_owned()));
+        }
+        Ok(addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::tests::mock_cluster;
+    use crate::message::tests::encode_stored;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    async fn new_admin(name_server: &str) -> Result<MQAdmin, ClientError> {
+        MQAdmin::new(ClientOption {
+            name_server: name_server.to_owned(),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_cluster_info() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let name_server = mock_cluster(2, requests, |_, _| true).await;
+        // The first name server is down, so the second one answers.
+        let admin = new_admin(&format!("127.0.0.1:1;{}", name_server)).await?;
+        let cluster_info = admin.cluster_info().await?;
+        assert_eq!(cluster_info.broker_names("C1"), vec!["b1"]);
+        assert_eq!(cluster_info.master_addr("b1"), Some(name_server.as_str()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_manage_topics() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let name_server = mock_cluster(2, Arc::clone(&requests), |request, response| {
+            if request.code == 206 {
+                response.body = bytes::Bytes::from(r#"{"topicList":["T2","T1"]}"#);
+            }
+            true
+        })
+        .await;
+        let admin = new_admin(&name_server).await?;
+
+        let config = TopicConfig {
+            write_queue_nums: 8,
+            order: true,
+            ..TopicConfig::new("T1")
+        };
+        admin.create_topic("C1", &config).await?;
+        assert!(matches!(
+            admin.create_topic("C2", &config).await,
+            Err(ClientError::ClusterNotFound(_))
+        ));
+        assert!(matches!(
+            admin.create_topic("C1", &TopicConfig::new("T 1")).await,
+            Err(ClientError::IllegalTopic(_))
+        ));
+        admin.delete_topic("C1", "T1").await?;
+        assert_eq!(admin.topic_list().await?, vec!["T1", "T2"]);
+
+        let requests = requests.lock().unwrap();
+        let create = requests.iter().find(|r| r.code == 17).unwrap();
+        assert_eq!(create.ext_fields["topic"], "T1");
+        assert_eq!(create.ext_fields["readQueueNums"], "16");
+        assert_eq!(create.ext_fields["writeQueueNums"], "8");
+        assert_eq!(create.ext_fields["perm"], "6");
+        assert_eq!(create.ext_fields["topicFilterType"], "SINGLE_TAG");
+        assert_eq!(create.ext_fields["order"], "true");
+        let codes: Vec<i32> = requests
+            .iter()
+            .map(|r| r.code)
+            .filter(|code| [215, 216].contains(code))
+            .collect();
+        assert_eq!(codes, vec![215, 216]);
+        let delete = requests.iter().find(|r| r.code == 216).unwrap();
+        assert_eq!(delete.ext_fields["clusterName"], "C1");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_consume_stats_and_connections() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let name_server = mock_cluster(2, Arc::clone(&requests), |request, response| {
+            match request.code {
+                208 => {
+                    response.body = bytes::Bytes::from(
+                        r#"{"consumeTps":2.5,"offsetTable":{{"brokerName":"b1","queueId":0,"topic":"T1"}:{"brokerOffset":10,"consumerOffset":4,"lastTimestamp":1000}}}"#,
+                    );
+                }
+                203 if request.ext_fields["consumerGroup"] == "G1" => {
+                    response.body = bytes::Bytes::from(
+                        r#"{"connectionSet":[{"clientAddr":"10.0.0.2:5000","clientId":"10.0.0.2@1","language":"JAVA","version":453}],"consumeFromWhere":"CONSUME_FROM_LAST_OFFSET","consumeType":"CONSUME_PASSIVELY","messageModel":"CLUSTERING","subscriptionTable":{"T1":{"classFilterMode":false,"codeSet":[],"expressionType":"TAG","subString":"*","subVersion":1,"tagsSet":[],"topic":"T1"}}}"#,
+                    );
+                }
+                // CONSUMER_NOT_ONLINE
+                203 => response.code = 206,
+                _ => {}
+            }
+            true
+        })
+        .await;
+        let admin = new_admin(&name_server).await?;
+
+        let stats = admin.consume_stats("G1", "T1").await?;
+        assert_eq!(stats.diff_total(), 6);
+        assert_eq!(stats.consume_tps, 2.5);
+
+        let connection = admin.consumer_connection_list("G1").await?;
+        assert_eq!(connection.connections[0].client_id, "10.0.0.2@1");
+        assert_eq!(connection.subscriptions["T1"].sub_string, "*");
+        assert_eq!(connection.message_model.as_deref(), Some("CLUSTERING"));
+        assert!(matches!(
+            admin.consumer_connection_list("G2").await,
+            Err(ClientError::ServerError { code: 206, .. })
+        ));
+
+        let requests = requests.lock().unwrap();
+        let stats_request = requests.iter().find(|r| r.code == 208).unwrap();
+        assert_eq!(stats_request.ext_fields["consumerGroup"], "G1");
+        assert_eq!(stats_request.ext_fields["topic"], "T1");
+        // Members of the group are looked up through its retry topic.
+        assert!(requests
+            .iter()
+            .any(|r| r.code == 105 && r.ext_fields["topic"] == "%RETRY%G1"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_messages() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let name_server = mock_cluster(2, Arc::clone(&requests), |request, response| {
+            match request.code {
+                33 => {
+                    let offset: i64 = request.ext_fields["offset"].parse().unwrap();
+                    response.body = encode_stored("T1", offset, &[("KEYS", "k1")], b"viewed");
+                }
+                12 if request.ext_fields["_UNIQUE_KEY_QUERY"] == "true" => {
+                    let mut body = bytes::BytesMut::new();
+                    for (offset, id) in [(0, "ID2"), (1, "ID1")] {
+                        body.extend(encode_stored("T1", offset, &[("UNIQ_KEY", id)], b"body"));
+                    }
+                    response.body = body.freeze();
+                }
+                12 if request.ext_fields["key"] == "k1" => {
+                    // Key `k2` collides with `k1` in the index.
+                    let mut body = bytes::BytesMut::new();
+                    for (offset, keys) in [(0, "k2"), (1, "k0 k1"), (2, "k1")] {
+                        body.extend(encode_stored("T1", offset, &[("KEYS", keys)], b"body"));
+                    }
+                    response.body = body.freeze();
+                }
+                12 => response.code = 22,
+                11 => {
+                    let offset: i64 = request.ext_fields["queueOffset"].parse().unwrap();
+                    if offset < 2 {
+                        response.body = encode_stored("T1", offset, &[], b"pulled");
+                    } else {
+                        response.code = 21;
+                    }
+                }
+                _ => {}
+            }
+            true
+        })
+        .await;
+        let admin = new_admin(&name_server).await?;
+
+        let store_host: SocketAddr = name_server.parse().unwrap();
+        let ip = match store_host.ip() {
+            std::net::IpAddr::V4(ip) => u32::from(ip),
+            _ => unreachable!(),
+        };
+        let offset_msg_id = format!("{:08X}{:08X}{:016X}", ip, store_host.port(), 1024);
+        let message = admin.view_message_by_id(&offset_msg_id).await?;
+        assert_eq!(message.body, "viewed");
+        assert!(admin.view_message_by_id("ID1").await.is_err());
+
+        let end = SystemTime::now();
+        let messages = admin
+            .query_message("T1", "k1", end - Duration::from_secs(3600), end, 1)
+            .await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].keys, vec!["k0", "k1"]);
+        assert_eq!(messages[0].broker_name, "b1");
+        assert!(admin
+            .query_message("T1", "k3", end - Duration::from_secs(3600), end, 1)
+            .await?
+            .is_empty());
+
+        let message = admin.query_by_unique_key("T1", "ID1").await?.unwrap();
+        assert_eq!(message.queue_offset, 1);
+        assert!(admin.query_by_unique_key("T1", "ID3").await?.is_none());
+
+        let mq = MessageQueue {
+            topic: "T1".to_owned(),
+            broker_name: "b1".to_owned(),
+            queue_id: 1,
+        };
+        let message = admin.view_message(&mq, 1).await?.unwrap();
+        assert_eq!(
+            (message.queue_offset, message.body.as_ref()),
+            (1, &b"pulled"[..])
+        );
+        assert!(admin.view_message(&mq, 2).await?.is_none());
+
+        let requests = requests.lock().unwrap();
+        let view = requests.iter().find(|r| r.code == 33).unwrap();
+        assert_eq!(view.ext_fields["offset"], "1024");
+        let pull = requests.iter().find(|r| r.code == 11).unwrap();
+        assert_eq!(pull.ext_fields["maxMsgNums"], "1");
+        assert_eq!(pull.ext_fields["consumerGroup"], "TOOLS_CONSUMER");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_manage_groups_and_broker_config() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let name_server = mock_cluster(2, Arc::clone(&requests), |request, response| {
+            match request.code {
+                201 => {
+                    response.body = bytes::Bytes::from(
+                        r#"{"subscriptionGroupTable":{"G1":{"groupName":"G1","retryMaxTimes":3}}}"#,
+                    );
+                }
+                26 => {
+                    response.body = bytes::Bytes::from("brokerName=b1\nflushDiskType=ASYNC_FLUSH\n")
+                }
+                28 => {
+                    response.body =
+                        bytes::Bytes::from(r#"{"table":{"brokerVersionDesc":"V4_9_4"}}"#);
+                }
+                _ => {}
+            }
+            true
+        })
+        .await;
+        let admin = new_admin(&name_server).await?;
+
+        let config = SubscriptionGroupConfig {
+            retry_max_times: 3,
+            consume_message_orderly: true,
+            ..SubscriptionGroupConfig::new("G1")
+        };
+        admin.create_subscription_group("C1", &config).await?;
+        admin.delete_subscription_group("C1", "G1", true).await?;
+        let groups = admin.subscription_groups(&name_server).await?;
+        assert_eq!(groups["G1"].retry_max_times, 3);
+
+        let broker_config = admin.broker_config(&name_server).await?;
+        assert_eq!(broker_config["flushDiskType"], "ASYNC_FLUSH");
+        let update = [("deleteWhen".to_owned(), "04".to_owned())]
+            .into_iter()
+            .collect();
+        admin.update_broker_config(&name_server, &update).await?;
+        let info = admin.broker_runtime_info(&name_server).await?;
+        assert_eq!(info.version(), Some("V4_9_4"));
+
+        let requests = requests.lock().unwrap();
+        let create = requests.iter().find(|r| r.code == 200).unwrap();
+        let body: SubscriptionGroupConfig = create.json_body()?;
+        assert_eq!(body, config);
+        let delete = requests.iter().find(|r| r.code == 207).unwrap();
+        assert_eq!(delete.ext_fields["groupName"], "G1");
+        assert_eq!(delete.ext_fields["cleanOffset"], "true");
+        let update = requests.iter().find(|r| r.code == 25).unwrap();
+        assert_eq!(update.body(), "deleteWhen=04\n");
+        Ok(())
+    }
+}
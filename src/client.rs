@@ -0,0 +1,354 @@
+//!
+//! Define options and shared facilities of a client instance, which publishers and consumers are built upon.
+//!
+use crate::connection::{ChannelKind, ConnectionManager};
+use crate::error::ClientError;
+use crate::frame::{Frame, RequestCode};
+use crate::message::MessageQueue;
+use crate::protocol;
+use crate::route::{RouteManager, TopicSubscribeInfo};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Options shared by publishers and consumers.
+#[derive(Debug, Clone)]
+pub struct ClientOption {
+    /// Semicolon separated name server addresses, for example `127.0.0.1:9876;127.0.0.2:9876`.
+    pub name_server: String,
+
+    /// Distinguish multiple clients within the same process. Process id is used if empty.
+    pub instance_name: String,
+
+    /// Timeout of an individual request to name servers or brokers.
+    pub request_timeout: Duration,
+
+    /// Interval between heartbeats sent to brokers.
+    pub heartbeat_interval: Duration,
+
+    /// Interval between queries of name servers for routes that may have changed, such as those of topics being
+    /// created automatically.
+    pub poll_name_server_interval: Duration,
+
+    /// Send messages and administrative requests to the VIP channel of brokers, listening on the port 2 below the
+    /// one advertised, so that they are not held up by long polling pulls.
+    pub vip_channel_enabled: bool,
+}
+
+impl Default for ClientOption {
+    fn default() -> Self {
+        Self {
+            name_server: "127.0.0.1:9876".to_owned(),
+            instance_name: String::new(),
+            request_timeout: Duration::from_secs(3),
+            heartbeat_interval: Duration::from_secs(30),
+            poll_name_server_interval: Duration::from_secs(30),
+            vip_channel_enabled: false,
+        }
+    }
+}
+
+/// Client wires up connections and routes shared by the publisher and consumer built on top of it.
+pub(crate) struct Client {
+    pub(crate) option: ClientOption,
+    pub(crate) client_id: String,
+    pub(crate) connections: ConnectionManager,
+    pub(crate) routes: RouteManager,
+
+    /// Broker id of the node to pull each queue from, as suggested by the last pull response.
+    pull_from_which_node: Mutex<HashMap<MessageQueue, i64>>,
+}
+
+impl Client {
+    pub(crate) fn new(option: ClientOption) -> Result<Self, ClientError> {
+        let routes = RouteManager::new(&option.name_server)?;
+        let instance_name = if option.instance_name.is_empty() {
+            std::process::id().to_string()
+        } else {
+            option.instance_name.clone()
+        };
+        let client_id = format!("{}@{}", local_ip(), instance_name);
+        Ok(Self {
+            option,
+            client_id,
+            connections: ConnectionManager::new(),
+            routes,
+            pull_from_which_node: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub(crate) async fn invoke(&self, addr: &str, frame: Frame) -> Result<Frame, ClientError> {
+        self.connections
+            .invoke(addr, frame, self.option.request_timeout)
+            .await
+    }
+
+    /// Send a request to the broker over its VIP channel if `vip_channel_enabled`, as sending messages and
+    /// administrative requests do.
+    pub(crate) async fn invoke_vip(&self, addr: &str, frame: Frame) -> Result<Frame, ClientError> {
+        let kind = if self.option.vip_channel_enabled {
+            ChannelKind::Vip
+        } else {
+            ChannelKind::Normal
+        };
+        self.connections
+            .invoke_on(addr, kind, frame, self.option.request_timeout)
+            .await
+    }
+
+    /// Send the request built by `request` to name servers in turn, until one of them responds.
+    pub(crate) async fn invoke_name_server<F>(&self, request: F) -> Result<Frame, ClientError>
+    where
+        F: Fn() -> Frame,
+    {
+        self.routes
+            .invoke(&self.connections, self.option.request_timeout, request)
+            .await
+    }
+
+    /// Route of the topic, from cache if available.
+    pub(crate) async fn route(
+        &self,
+        topic: &str,
+    ) -> Result<Arc<protocol::TopicRouteData>, ClientError> {
+        self.routes
+            .get_or_query(topic, &self.connections, self.option.request_timeout)
+            .await
+    }
+
+    pub(crate) async fn find_master(
+        &self,
+        topic: &str,
+        broker_name: &str,
+    ) -> Result<String, ClientError> {
+        self.route(topic)
+            .await?
+            .master_addr(broker_name)
+            .map(str::to_owned)
+            .ok_or_else(|| ClientError::BrokerNotFound(broker_name.to_owned()))
+    }
+
+    /// Address of the node to pull messages of the queue from, which is the one brokers last suggested, or the
+    /// master at first, along with whether it is a slave.
+    ///
+    /// Slaves missing from the route, as they are once they go down, are replaced by the master.
+    pub(crate) async fn find_pull_addr(
+        &self,
+        mq: &MessageQueue,
+    ) -> Result<(String, bool), ClientError> {
+        let broker_id = self
+            .pull_from_which_node
+            .lock()
+            .map_err(|_e| ClientError::Unknown)?
+            .get(mq)
+            .copied()
+            .unwrap_or(protocol::MASTER_ID);
+        self.route(&mq.topic)
+            .await?
+            .select_addr(&mq.broker_name, broker_id)
+            .map(|(addr, slave)| (addr.to_owned(), slave))
+            .ok_or_else(|| ClientError::BrokerNotFound(mq.broker_name.clone()))
+    }
+
+    /// Remember the node to pull messages of the queue from next, as suggested by `suggestWhichBrokerId` of pull
+    /// responses.
+    pub(crate) fn suggest_pull_node(&self, mq: &MessageQueue, broker_id: i64) {
+        if let Ok(mut map) = self.pull_from_which_node.lock() {
+            map.insert(mq.clone(), broker_id);
+        }
+    }
+
+    /// Query the latest route of the topic and list its readable queues served by a master, sorted so that all
+    /// members of a group see them in the same order.
+    pub(crate) async fn subscribe_queues(
+        &self,
+        topic: &str,
+    ) -> Result<Vec<MessageQueue>, ClientError> {
+        let route = self
+            .routes
+            .query(topic, &self.connections, self.option.request_timeout)
+            .await?;
+        Ok(TopicSubscribeInfo::new(topic, &route)
+            .message_queues()
+            .to_vec())
+    }
+
+    /// Client ids of members of the group consuming the topic, as known by one of its brokers.
+    pub(crate) async fn consumer_id_list(
+        &self,
+        topic: &str,
+        group: &str,
+    ) -> Result<Vec<String>, ClientError> {
+        let route = self.route(topic).await?;
+        let addr = route
+            .broker_datas
+            .iter()
+            .find_map(|broker_data| broker_data.master_addr())
+            .ok_or_else(|| ClientError::RouteNotFound(topic.to_owned()))?;
+        let header = protocol::GetConsumerListByGroupRequestHeader {
+            consumer_group: group.to_owned(),
+        };
+        let frame = Frame::request(RequestCode::GetConsumerListByGroup, header);
+        let response = self.invoke(addr, frame).await?;
+        response.ensure_success()?;
+        let body: protocol::GetConsumerListByGroupResponseBody = response.json_body()?;
+        Ok(body.consumer_id_list)
+    }
+
+    /// Offset of the oldest message still stored in the queue.
+    pub(crate) async fn min_offset(&self, mq: &MessageQueue) -> Result<i64, ClientError> {
+        let header = protocol::GetMinOffsetRequestHeader {
+            topic: mq.topic.clone(),
+            queue_id: mq.queue_id,
+        };
+        self.query_offset(mq, Frame::request(RequestCode::GetMinOffset, header))
+            .await
+    }
+
+    /// Offset one past the newest message of the queue.
+    pub(crate) async fn max_offset(&self, mq: &MessageQueue) -> Result<i64, ClientError> {
+        let header = protocol::GetMaxOffsetRequestHeader {
+            topic: mq.topic.clone(),
+            queue_id: mq.queue_id,
+        };
+        self.query_offset(mq, Frame::request(RequestCode::GetMaxOffset, header))
+            .await
+    }
+
+    /// Offset of the first message of the queue stored at or after `timestamp`, in milliseconds since the Unix epoch.
+    pub(crate) async fn search_offset(
+        &self,
+        mq: &MessageQueue,
+        timestamp: i64,
+    ) -> Result<i64, ClientError> {
+        let header = protocol::SearchOffsetRequestHeader {
+            topic: mq.topic.clone(),
+            queue_id: mq.queue_id,
+            timestamp,
+        };
+        self.query_offset(
+            mq,
+            Frame::request(RequestCode::SearchOffsetByTimestamp, header),
+        )
+        .await
+    }
+
+    /// Addresses of all brokers serving the topics.
+    pub(crate) async fn broker_addrs(&self, topics: &[String]) -> HashSet<String> {
+        let mut addrs = HashSet::new();
+        for topic in topics {
+            match self.route(topic).await {
+                Ok(route) => route.broker_datas.iter().for_each(|broker_data| {
+                    addrs.extend(broker_data.broker_addrs.values().cloned());
+                }),
+                Err(e) => eprintln!("Failed to query route of topic {}. Cause: {}", topic, e),
+            }
+        }
+        addrs
+    }
+
+    /// Announce the consumer and its subscriptions to all brokers serving the subscribed topics.
+    pub(crate) async fn send_heartbeat(&self, consumer_data: protocol::ConsumerData) {
+        let topics: Vec<String> = consumer_data
+            .subscription_data_set
+            .iter()
+            .map(|subscription| subscription.topic.clone())
+            .collect();
+        let heartbeat = protocol::HeartbeatData {
+            client_id: self.client_id.clone(),
+            producer_data_set: vec![],
+            consumer_data_set: vec![consumer_data],
+        };
+        self.heartbeat(&heartbeat, &topics).await;
+    }
+
+    /// Register the publisher of the group with brokers serving the topics, so that they may push to it. Return
+    /// whether all of them, and at least one, accepted the registration.
+    pub(crate) async fn send_producer_heartbeat(&self, group: &str, topics: &[String]) -> bool {
+        let heartbeat = protocol::HeartbeatData {
+            client_id: self.client_id.clone(),
+            producer_data_set: vec![protocol::ProducerData {
+                group_name: group.to_owned(),
+            }],
+            consumer_data_set: vec![],
+        };
+        self.heartbeat(&heartbeat, topics).await
+    }
+
+    async fn heartbeat(&self, heartbeat: &protocol::HeartbeatData, topics: &[String]) -> bool {
+        let addrs = self.broker_addrs(topics).await;
+        let mut accepted = !addrs.is_empty();
+        for addr in addrs {
+            let mut frame = Frame::request(RequestCode::HeartBeat, HashMap::new());
+            let result = match frame.set_json_body(heartbeat) {
+                Ok(_) => self
+                    .invoke(&addr, frame)
+                    .await
+                    .and_then(|response| response.ensure_success()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                eprintln!("Failed to send heartbeat to {}. Cause: {}", addr, e);
+                accepted = false;
+            }
+        }
+        accepted
+    }
+
+    /// Remove the consumer of the group from brokers serving the topics.
+    pub(crate) async fn unregister_consumer(&self, group: &str, topics: &[String]) {
+        for addr in self.broker_addrs(topics).await {
+            let header = protocol::UnregisterClientRequestHeader {
+                client_id: self.client_id.clone(),
+                consumer_group: Some(group.to_owned()),
+            };
+            let frame = Frame::request(RequestCode::UnregisterClient, header);
+            if let Err(e) = self.invoke(&addr, frame).await {
+                eprintln!("Failed to unregister from {}. Cause: {}", addr, e);
+            }
+        }
+    }
+
+    async fn query_offset(&self, mq: &MessageQueue, frame: Frame) -> Result<i64, ClientError> {
+        let addr = self.find_master(&mq.topic, &mq.broker_name).await?;
+        let response = self.invoke_vip(&addr, frame).await?;
+        response.ensure_success()?;
+        Ok(protocol::OffsetResponseHeader::try_from(&response.ext_fields)?.offset)
+    }
+}
+
+pub(crate) fn current_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Best-effort discovery of the IP address this host uses for outbound traffic.
+fn local_ip() -> IpAddr {
+    // Connecting a UDP socket does not send any packet; it only selects the outbound interface.
+    UdpSocket::bind("0.0.0.0:0")
+        .and_then(|socket| {
+            socket.connect("8.8.8.8:80")?;
+            socket.local_addr()
+        })
+        .map(|addr| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_id() -> Result<(), ClientError> {
+        let option = ClientOption {
+            instance_name: "test".to_owned(),
+            ..Default::default()
+        };
+        let client = Client::new(option)?;
+        assert!(client.client_id.ends_with("@test"));
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//!
+//! Compress message bodies before publishing and decompress them once received, as flagged in `sys_flag`.
+//!
+use crate::error::ClientError;
+use bytes::Bytes;
+use std::io::{Read, Write};
+
+/// The body of the message is compressed.
+pub(crate) const COMPRESSED_FLAG: i32 = 0x1;
+
+/// Bits of `sys_flag` holding the compression type, introduced by RocketMQ 5. Bodies compressed by earlier clients
+/// leave them unset and are zlib compressed.
+const COMPRESSION_TYPE_MASK: i32 = 0x7 << 8;
+
+/// Algorithm compressing message bodies.
+///
+/// Zlib is understood by clients of all versions, while the others need RocketMQ 5 clients on the consumer side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionType {
+    Zlib,
+
+    #[cfg(feature = "zstd")]
+    Zstd,
+
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+impl CompressionType {
+    /// Bits of `sys_flag` identifying the algorithm.
+    fn flag(&self) -> i32 {
+        match self {
+            #[cfg(feature = "lz4")]
+            CompressionType::Lz4 => 1 << 8,
+            #[cfg(feature = "zstd")]
+            CompressionType::Zstd => 2 << 8,
+            CompressionType::Zlib => 3 << 8,
+        }
+    }
+}
+
+/// How the body of a message is compressed, overriding the policy of the publisher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Compress the body if it exceeds the threshold of the publisher, with its algorithm.
+    #[default]
+    Default,
+
+    /// Send the body as is.
+    Disabled,
+
+    /// Compress the body with the given algorithm, whatever its size.
+    Always(CompressionType),
+}
+
+/// Compress the body, returning it along with the bits to set in `sys_flag`.
+///
+/// # Errors
+/// Raise ClientError::Compression if the algorithm fails.
+pub(crate) fn compress(
+    body: &[u8],
+    compression_type: CompressionType,
+    level: u32,
+) -> Result<(Bytes, i32), ClientError> {
+    let compressed = match compression_type {
+        CompressionType::Zlib => {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::new(level));
+            encoder
+                .write_all(body)
+                .and_then(|_| encoder.finish())
+                .map_err(|e| ClientError::Compression(e.to_string()))?
+        }
+        #[cfg(feature = "zstd")]
+        CompressionType::Zstd => zstd::encode_all(body, level as i32)
+            .map_err(|e| ClientError::Compression(e.to_string()))?,
+        #[cfg(feature = "lz4")]
+        CompressionType::Lz4 => {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+            encoder
+                .write_all(body)
+                .map_err(|e| ClientError::Compression(e.to_string()))?;
+            encoder
+                .finish()
+                .map_err(|e| ClientError::Compression(e.to_string()))?
+        }
+    };
+    Ok((
+        Bytes::from(compressed),
+        COMPRESSED_FLAG | compression_type.flag(),
+    ))
+}
+
+/// Decompress the body if `sys_flag` marks it compressed, or return it as is.
+///
+/// # Errors
+/// Raise ClientError::Compression if the body is corrupted, or compressed by an algorithm whose cargo feature is
+/// disabled.
+pub(crate) fn decompress(body: Bytes, sys_flag: i32) -> Result<Bytes, ClientError> {
+    if sys_flag & COMPRESSED_FLAG == 0 {
+        return Ok(body);
+    }
+    let mut decompressed = Vec::new();
+    let result = match (sys_flag & COMPRESSION_TYPE_MASK) >> 8 {
+        0 | 3 => flate2::read::ZlibDecoder::new(&body[..]).read_to_end(&mut decompressed),
+        #[cfg(feature = "zstd")]
+        2 => zstd::stream::read::Decoder::new(&body[..])
+            .and_then(|mut decoder| decoder.read_to_end(&mut decompressed)),
+        #[cfg(feature = "lz4")]
+        1 => lz4_flex::frame::FrameDecoder::new(&body[..]).read_to_end(&mut decompressed),
+        other => {
+            return Err(ClientError::Compression(format!(
+                "compression type {} is not supported",
+                other
+            )))
+        }
+    };
+    result.map_err(|e| ClientError::Compression(e.to_string()))?;
+    Ok(Bytes::from(decompressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zlib() -> Result<(), ClientError> {
+        let body = "hello ".repeat(1000);
+        let (compressed, sys_flag) = compress(body.as_bytes(), CompressionType::Zlib, 5)?;
+        assert!(compressed.len() < body.len());
+        assert_eq!(sys_flag, 0x301);
+        assert_eq!(decompress(compressed.clone(), sys_flag)?, body.as_bytes());
+
+        // Clients before RocketMQ 5 set the compressed flag only.
+        assert_eq!(
+            decompress(compressed.clone(), COMPRESSED_FLAG)?,
+            body.as_bytes()
+        );
+        assert_eq!(decompress(compressed.clone(), 0)?, compressed);
+        assert!(decompress(Bytes::from_static(b"garbage"), sys_flag).is_err());
+        assert!(matches!(
+            decompress(compressed, COMPRESSED_FLAG | 0x700),
+            Err(ClientError::Compression(_))
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() -> Result<(), ClientError> {
+        let body = "hello ".repeat(1000);
+        let (compressed, sys_flag) = compress(body.as_bytes(), CompressionType::Zstd, 5)?;
+        assert_eq!(sys_flag, 0x201);
+        assert_eq!(decompress(compressed, sys_flag)?, body.as_bytes());
+        Ok(())
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_lz4() -> Result<(), ClientError> {
+        let body = "hello ".repeat(1000);
+        let (compressed, sys_flag) = compress(body.as_bytes(), CompressionType::Lz4, 5)?;
+        assert_eq!(sys_flag, 0x101);
+        assert_eq!(decompress(compressed, sys_flag)?, body.as_bytes());
+        Ok(())
+    }
+}
@@ -8,10 +8,12 @@ use crate::frame::{self, Frame};
 use bytes::{self, Buf, BytesMut};
 use std::collections::HashMap;
 use std::io::Cursor;
-use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
 use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
 
 pub struct Connection {
     stream: BufWriter<TcpStream>,
@@ -41,7 +43,7 @@ impl Connection {
     pub async fn new(endpoint: &SocketAddr) -> Result<Self, error::ClientError> {
         let tcp_stream = TcpStream::connect(endpoint)
             .await
-            .map_err(|e| error::ClientError::ConnectTimeout(e))?;
+            .map_err(error::ClientError::ConnectTimeout)?;
 
         Ok(Connection {
             stream: BufWriter::new(tcp_stream),
@@ -49,6 +51,14 @@ impl Connection {
         })
     }
 
+    #[cfg(test)]
+    pub(crate) fn from_stream(stream: TcpStream) -> Self {
+        Connection {
+            stream: BufWriter::new(stream),
+            buffer: BytesMut::with_capacity(1024 * 1024),
+        }
+    }
+
     pub async fn read_frame(&mut self) -> Result<Option<frame::Frame>, ClientError> {
         loop {
             if let Some(frame) = self.parse_frame()? {
@@ -81,38 +91,636 @@ impl Connection {
                 buf.set_position(0);
                 let frame = Frame::parse(&mut buf)?;
                 self.buffer.advance(len);
-                return Ok(frame);
+                Ok(frame)
             }
 
-            Err(frame::Error::Incomplete) => {
-                return Ok(None);
+            Err(frame::Error::Incomplete) => Ok(None),
+
+            Err(frame::Error::Other(e)) => Err(e),
+        }
+    }
+}
+
+/// Split an address in form of `host:port` into host and port. IPv6 hosts may be enclosed in brackets, as in
+/// `[::1]:10911`, or not, as brokers register them, in which case the port follows the last colon.
+///
+/// # Errors
+/// Raise ClientError::BadAddress if the host is empty or the port is missing or invalid.
+pub(crate) fn split_host_port(addr: &str) -> Result<(&str, u16), ClientError> {
+    let bad_address = || ClientError::BadAddress(addr.to_owned());
+    let (host, port) = match addr.strip_prefix('[') {
+        Some(rest) => rest.split_once("]:").ok_or_else(bad_address)?,
+        None => addr.rsplit_once(':').ok_or_else(bad_address)?,
+    };
+    let port = port.parse().map_err(|_e| bad_address())?;
+    if host.is_empty() {
+        return Err(bad_address());
+    }
+    Ok((host, port))
+}
+
+/// Format host and port as `host:port`, enclosing IPv6 hosts in brackets.
+pub(crate) fn join_host_port(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Parse a socket address of literal IP, in any form `split_host_port` accepts.
+pub(crate) fn parse_socket_addr(addr: &str) -> Option<SocketAddr> {
+    let (host, port) = split_host_port(addr).ok()?;
+    let ip: IpAddr = host.parse().ok()?;
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Serve requests initiated by the remote peer of a connection, such as brokers notifying consumers.
+pub(crate) trait RequestProcessor: Send + Sync {
+    /// Handle the request, returning the response to write back unless the request is oneway.
+    ///
+    /// Called by the task driving the connection, so lengthy work should be spawned instead.
+    fn process(&self, request: &Frame) -> Frame;
+}
+
+type Processors = Arc<RwLock<HashMap<i32, Arc<dyn RequestProcessor>>>>;
+
+/// Commands accepted by the background task that owns a connection.
+enum Command {
+    /// Write the frame and deliver the response carrying the same opaque to the sender.
+    Request(Frame, oneshot::Sender<Frame>),
+
+    /// Write the frame without waiting for any response.
+    Oneway(Frame),
+}
+
+/// Handle to a connection whose reads and writes are driven by a dedicated task.
+///
+/// Requests may be issued concurrently; responses are matched back to callers by frame opaque.
+#[derive(Clone)]
+struct Channel {
+    tx: mpsc::UnboundedSender<Command>,
+}
+
+impl Channel {
+    /// Connect to the first of the socket addresses accepting connections, which `addr` resolved to.
+    async fn open(
+        addr: &str,
+        endpoints: &[SocketAddr],
+        processors: Processors,
+    ) -> Result<Self, ClientError> {
+        let mut last_error = ClientError::BadAddress(addr.to_owned());
+        for endpoint in endpoints {
+            match Connection::new(endpoint).await {
+                Ok(connection) => {
+                    let (tx, rx) = mpsc::unbounded_channel();
+                    tokio::spawn(Channel::run(connection, rx, addr.to_owned(), processors));
+                    return Ok(Channel { tx });
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    async fn run(
+        mut connection: Connection,
+        mut rx: mpsc::UnboundedReceiver<Command>,
+        endpoint: String,
+        processors: Processors,
+    ) {
+        let mut inflight: HashMap<i32, oneshot::Sender<Frame>> = HashMap::new();
+        loop {
+            tokio::select! {
+                command = rx.recv() => {
+                    let frame = match command {
+                        Some(Command::Request(frame, tx)) => {
+                            // Drop waiters that have given up, typically due to timeout.
+                            inflight.retain(|_, waiter| !waiter.is_closed());
+                            inflight.insert(frame.opaque, tx);
+                            frame
+                        }
+                        Some(Command::Oneway(frame)) => frame,
+                        None => break,
+                    };
+                    if let Err(e) = connection.write_frame(&frame).await {
+                        eprintln!("Failed to write frame to {}. Cause: {}", endpoint, e);
+                        break;
+                    }
+                }
+
+                frame = connection.read_frame() => {
+                    match frame {
+                        Ok(Some(frame)) => {
+                            if frame.frame_type() == frame::Type::Response {
+                                if let Some(waiter) = inflight.remove(&frame.opaque) {
+                                    let _ = waiter.send(frame);
+                                }
+                                continue;
+                            }
+                            if let Some(response) = Channel::process(&processors, &frame) {
+                                if let Err(e) = connection.write_frame(&response).await {
+                                    eprintln!("Failed to write frame to {}. Cause: {}", endpoint, e);
+                                    break;
+                                }
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            eprintln!("Failed to read frame from {}. Cause: {}", endpoint, e);
+                            break;
+                        }
+                    }
+                }
             }
+        }
+        // Pending waiters are dropped here and observe the connection as reset.
+    }
 
-            Err(frame::Error::Other(e)) => {
-                return Err(e);
+    /// Dispatch a request of the remote peer to the processor registered for its code.
+    fn process(processors: &Processors, request: &Frame) -> Option<Frame> {
+        let processor = processors
+            .read()
+            .ok()
+            .and_then(|map| map.get(&request.code).cloned());
+        let mut response = match processor {
+            Some(processor) => processor.process(request),
+            None => {
+                let mut response = Frame::new();
+                response.code = frame::ResponseCode::RequestCodeNotSupported as i32;
+                response.remark = format!("Request code {} is not supported", request.code);
+                response
             }
+        };
+        if request.is_oneway() {
+            return None;
         }
+        response.opaque = request.opaque;
+        response.mark_response_type();
+        Some(response)
     }
 }
 
+/// Which listening port of a broker a connection goes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum ChannelKind {
+    /// The port brokers advertise in routes.
+    Normal,
+
+    /// The port 2 below, which brokers serve with dedicated threads so that sending and administration are not
+    /// held up by long polling pulls.
+    Vip,
+}
+
+/// ConnectionManager multiplexes requests over one shared connection per remote endpoint and channel kind.
+///
+/// Endpoints are addressed as `host:port`, with hosts resolved on first connect and again once connecting to the
+/// addresses they resolved to fails.
 pub(crate) struct ConnectionManager {
-    connections: Arc<Mutex<HashMap<String, Connection>>>,
+    connections: Arc<Mutex<HashMap<(String, ChannelKind), Channel>>>,
+
+    /// Socket addresses each `host:port` resolved to.
+    resolved: Arc<Mutex<HashMap<String, Vec<SocketAddr>>>>,
+    processors: Processors,
 }
 
 impl ConnectionManager {
     pub(crate) fn new() -> Self {
         Self {
             connections: Arc::new(Mutex::new(HashMap::new())),
+            resolved: Arc::new(Mutex::new(HashMap::new())),
+            processors: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Serve requests of the given code initiated by remote peers over any connection, replacing the processor
+    /// registered before. Requests without a processor are answered with REQUEST_CODE_NOT_SUPPORTED.
+    pub(crate) fn register_processor(
+        &self,
+        code: frame::RequestCode,
+        processor: Arc<dyn RequestProcessor>,
+    ) {
+        if let Ok(mut map) = self.processors.write() {
+            map.insert(code as i32, processor);
+        }
+    }
+
+    async fn channel(&self, addr: &str, kind: ChannelKind) -> Result<Channel, ClientError> {
+        let key = (addr.to_owned(), kind);
+        {
+            let guard = self.connections.lock().map_err(|_e| ClientError::Unknown)?;
+            if let Some(channel) = guard.get(&key) {
+                if !channel.is_closed() {
+                    return Ok(channel.clone());
+                }
+            }
+        }
+
+        let channel = match kind {
+            ChannelKind::Normal => self.open(addr).await?,
+            ChannelKind::Vip => {
+                let (host, port) = split_host_port(addr)?;
+                let port = port
+                    .checked_sub(2)
+                    .ok_or_else(|| ClientError::BadAddress(addr.to_owned()))?;
+                self.open(&join_host_port(host, port)).await?
+            }
+        };
+        let mut guard = self.connections.lock().map_err(|_e| ClientError::Unknown)?;
+        match guard.get(&key) {
+            // Another task won the race to connect; share its channel.
+            Some(existing) if !existing.is_closed() => Ok(existing.clone()),
+            _ => {
+                guard.insert(key, channel.clone());
+                Ok(channel)
+            }
+        }
+    }
+
+    /// Connect to the endpoint, resolving its host unless resolved before. Cached addresses that no longer accept
+    /// connections are resolved anew, as those of brokers rescheduled to other machines.
+    ///
+    /// # Errors
+    /// Raise ClientError::BadAddress if the host may not be resolved, or ClientError::ConnectTimeout if none of its
+    /// addresses accepts connections.
+    async fn open(&self, addr: &str) -> Result<Channel, ClientError> {
+        let cached = self
+            .resolved
+            .lock()
+            .map_err(|_e| ClientError::Unknown)?
+            .get(addr)
+            .cloned();
+        if let Some(endpoints) = cached {
+            match Channel::open(addr, &endpoints, Arc::clone(&self.processors)).await {
+                Ok(channel) => return Ok(channel),
+                Err(e) => eprintln!(
+                    "Failed to connect to {}, resolving it again. Cause: {}",
+                    addr, e
+                ),
+            }
+        }
+
+        let (host, port) = split_host_port(addr)?;
+        let endpoints: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|_e| ClientError::BadAddress(addr.to_owned()))?
+            .collect();
+        let channel = Channel::open(addr, &endpoints, Arc::clone(&self.processors)).await?;
+        if let Ok(mut map) = self.resolved.lock() {
+            map.insert(addr.to_owned(), endpoints);
         }
+        Ok(channel)
+    }
+
+    /// Send a request frame to the given address and wait for its response.
+    ///
+    /// # Errors
+    /// Raise ClientError::RequestTimeout if no response arrives within `timeout`, and
+    /// ClientError::ConnectionReset if the connection is closed before the response arrives.
+    pub(crate) async fn invoke(
+        &self,
+        addr: &str,
+        frame: Frame,
+        timeout: Duration,
+    ) -> Result<Frame, ClientError> {
+        self.invoke_on(addr, ChannelKind::Normal, frame, timeout)
+            .await
+    }
+
+    /// Send a request frame to the given address over the channel of the given kind, and wait for its response.
+    ///
+    /// # Errors
+    /// Raise the same errors as `invoke`.
+    pub(crate) async fn invoke_on(
+        &self,
+        addr: &str,
+        kind: ChannelKind,
+        frame: Frame,
+        timeout: Duration,
+    ) -> Result<Frame, ClientError> {
+        let channel = self.channel(addr, kind).await?;
+        let opaque = frame.opaque;
+        let (tx, rx) = oneshot::channel();
+        channel
+            .tx
+            .send(Command::Request(frame, tx))
+            .map_err(|_e| ClientError::ConnectionReset)?;
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(ClientError::ConnectionReset),
+            Err(_) => Err(ClientError::RequestTimeout(opaque)),
+        }
+    }
+
+    /// Send a request frame to the given address without waiting for any response.
+    pub(crate) async fn invoke_oneway(
+        &self,
+        addr: &str,
+        mut frame: Frame,
+    ) -> Result<(), ClientError> {
+        frame.mark_oneway();
+        let channel = self.channel(addr, ChannelKind::Normal).await?;
+        channel
+            .tx
+            .send(Command::Oneway(frame))
+            .map_err(|_e| ClientError::ConnectionReset)
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use crate::protocol::{SendMessageRequestHeader, TopicRouteData};
 
     use super::*;
     use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+
+    /// Serve frames on a local port, answering each request with what `handler` returns, if anything.
+    pub(crate) async fn mock_server<F>(handler: F) -> String
+    where
+        F: Fn(Frame) -> Option<Frame> + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        serve(listener, handler);
+        addr
+    }
+
+    /// Route of broker `b1` of cluster `C1`, whose nodes listen on `addrs` in order of broker id, serving
+    /// `queue_nums` readable and writable queues.
+    pub(crate) fn route_json(addrs: &[&str], queue_nums: i32) -> String {
+        let broker_addrs: Vec<String> = addrs
+            .iter()
+            .enumerate()
+            .map(|(id, addr)| format!(r#""{}":"{}""#, id, addr))
+            .collect();
+        format!(
+            r#"{{"brokerDatas":[{{"brokerAddrs":{{{}}},"brokerName":"b1","cluster":"C1"}}],"filterServerTable":{{}},"queueDatas":[{{"brokerName":"b1","perm":6,"readQueueNums":{1},"topicSynFlag":0,"writeQueueNums":{1}}}]}}"#,
+            broker_addrs.join(","),
+            queue_nums
+        )
+    }
+
+    /// Serve as both name server and the master of broker `b1` of cluster `C1`, which serves every topic with
+    /// `queue_nums` queues. Requests other than route and cluster queries are answered by `respond`, which returns
+    /// false to hold the request, as brokers hold pulls while no message is available. Requests answered are
+    /// recorded in `requests`.
+    pub(crate) async fn mock_cluster<F>(
+        queue_nums: i32,
+        requests: Arc<Mutex<Vec<Frame>>>,
+        respond: F,
+    ) -> String
+    where
+        F: Fn(&Frame, &mut Frame) -> bool + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let broker_addr = addr.clone();
+        serve(listener, move |request| {
+            let mut response = Frame::new();
+            response.opaque = request.opaque;
+            match request.code {
+                105 => {
+                    response.body = bytes::Bytes::from(route_json(&[&broker_addr], queue_nums));
+                }
+                106 => {
+                    let cluster_info = format!(
+                        r#"{{"brokerAddrTable":{{"b1":{{"brokerAddrs":{{0:"{}"}},"brokerName":"b1","cluster":"C1"}}}},"clusterAddrTable":{{"C1":["b1"]}}}}"#,
+                        broker_addr
+                    );
+                    response.body = bytes::Bytes::from(cluster_info);
+                }
+                _ => {
+                    if !respond(&request, &mut response) {
+                        return None;
+                    }
+                }
+            }
+            requests.lock().unwrap().push(request);
+            Some(response)
+        });
+        addr
+    }
+
+    /// Answer requests arriving at the listener as `mock_server` does.
+    fn serve<F>(listener: TcpListener, handler: F)
+    where
+        F: Fn(Frame) -> Option<Frame> + Send + Sync + 'static,
+    {
+        let handler = Arc::new(handler);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = Arc::clone(&handler);
+                tokio::spawn(async move {
+                    let mut connection = Connection::from_stream(stream);
+                    while let Ok(Some(request)) = connection.read_frame().await {
+                        if let Some(mut response) = handler(request) {
+                            response.mark_response_type();
+                            if connection.write_frame(&response).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_connection_manager_invoke() -> Result<(), ClientError> {
+        let addr = mock_server(|request| {
+            let mut response = Frame::new();
+            response.opaque = request.opaque;
+            response.remark = format!("echo-{}", request.code);
+            // Leave requests of code 0 unanswered.
+            (request.code != 0).then_some(response)
+        })
+        .await;
+
+        let manager = ConnectionManager::new();
+        let timeout = Duration::from_secs(3);
+        let mut frame = Frame::new();
+        frame.code = 11;
+        let response = manager.invoke(&addr, frame, timeout).await?;
+        assert_eq!(response.remark(), "echo-11");
+
+        let (first, second) = tokio::join!(
+            manager.invoke(
+                &addr,
+                Frame::request(frame::RequestCode::HeartBeat, HashMap::new()),
+                timeout
+            ),
+            manager.invoke(
+                &addr,
+                Frame::request(frame::RequestCode::PullMessage, HashMap::new()),
+                timeout
+            ),
+        );
+        assert_eq!(first?.remark(), "echo-34");
+        assert_eq!(second?.remark(), "echo-11");
+
+        let result = manager
+            .invoke(&addr, Frame::new(), Duration::from_millis(100))
+            .await;
+        assert!(matches!(result, Err(ClientError::RequestTimeout(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_port() {
+        assert_eq!(
+            split_host_port("broker-a.svc:10911").unwrap(),
+            ("broker-a.svc", 10911)
+        );
+        assert_eq!(split_host_port("[::1]:10911").unwrap(), ("::1", 10911));
+        assert_eq!(
+            split_host_port("fe80::1:10911").unwrap(),
+            ("fe80::1", 10911)
+        );
+        for addr in ["broker-a", ":10911", "[::1]10911", "host:port"] {
+            assert!(matches!(
+                split_host_port(addr),
+                Err(ClientError::BadAddress(_))
+            ));
+        }
+        assert_eq!(join_host_port("::1", 10909), "[::1]:10909");
+        assert_eq!(join_host_port("broker-a.svc", 10909), "broker-a.svc:10909");
+        assert_eq!(
+            parse_socket_addr("0:0:0:0:0:0:0:1:10911"),
+            Some("[::1]:10911".parse().unwrap())
+        );
+        assert_eq!(parse_socket_addr("broker-a.svc:10911"), None);
+    }
+
+    #[tokio::test]
+    async fn test_connect_by_host_name() -> Result<(), ClientError> {
+        let addr = mock_server(|request| {
+            let mut response = Frame::new();
+            response.opaque = request.opaque;
+            Some(response)
+        })
+        .await;
+        let (_, port) = split_host_port(&addr)?;
+        let manager = ConnectionManager::new();
+        let response = manager
+            .invoke(
+                &format!("localhost:{}", port),
+                Frame::new(),
+                Duration::from_secs(3),
+            )
+            .await?;
+        assert!(response.ensure_success().is_ok());
+
+        let result = manager
+            .invoke(
+                "broker-a.invalid:10911",
+                Frame::new(),
+                Duration::from_secs(3),
+            )
+            .await;
+        assert!(matches!(result, Err(ClientError::BadAddress(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_vip_channel() -> Result<(), ClientError> {
+        // Find a free pair of ports 2 apart, as brokers listen on.
+        let (listener, vip_listener) = loop {
+            let vip_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = vip_listener.local_addr().unwrap().port();
+            let Some(port) = port.checked_add(2) else {
+                continue;
+            };
+            if let Ok(listener) = TcpListener::bind(("127.0.0.1", port)).await {
+                break (listener, vip_listener);
+            }
+        };
+        let addr = listener.local_addr().unwrap().to_string();
+        for (listener, name) in [(listener, "normal"), (vip_listener, "vip")] {
+            serve(listener, move |request| {
+                let mut response = Frame::new();
+                response.opaque = request.opaque;
+                response.remark = name.to_owned();
+                Some(response)
+            });
+        }
+
+        let manager = ConnectionManager::new();
+        let timeout = Duration::from_secs(3);
+        for (kind, expected) in [
+            (ChannelKind::Vip, "vip"),
+            (ChannelKind::Normal, "normal"),
+            (ChannelKind::Vip, "vip"),
+        ] {
+            let response = manager
+                .invoke_on(&addr, kind, Frame::new(), timeout)
+                .await?;
+            assert_eq!(response.remark(), expected);
+        }
+        assert_eq!(manager.connections.lock().unwrap().len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_requests_of_peer() -> Result<(), ClientError> {
+        struct Echo;
+        impl RequestProcessor for Echo {
+            fn process(&self, request: &Frame) -> Frame {
+                let mut response = Frame::new();
+                response.remark = format!("processed-{}", request.code);
+                response
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut connection = Connection::from_stream(stream);
+            let request = connection.read_frame().await.unwrap().unwrap();
+
+            // Push requests of the server's own before answering the client.
+            let mut responses = vec![];
+            for (opaque, code) in [(100, 220), (101, 999)] {
+                let mut push = Frame::new();
+                push.code = code;
+                push.opaque = opaque;
+                connection.write_frame(&push).await.unwrap();
+                responses.push(connection.read_frame().await.unwrap().unwrap());
+            }
+            let mut response = Frame::new();
+            response.opaque = request.opaque;
+            response.mark_response_type();
+            connection.write_frame(&response).await.unwrap();
+            responses
+        });
+
+        let manager = ConnectionManager::new();
+        manager.register_processor(
+            frame::RequestCode::ResetConsumerClientOffset,
+            Arc::new(Echo),
+        );
+        let request = Frame::request(frame::RequestCode::HeartBeat, HashMap::new());
+        manager
+            .invoke(&addr, request, Duration::from_secs(3))
+            .await?
+            .ensure_success()?;
+
+        let responses = server.await.unwrap();
+        assert_eq!(responses[0].frame_type(), frame::Type::Response);
+        assert_eq!(responses[0].opaque, 100);
+        assert_eq!(responses[0].remark(), "processed-220");
+        assert_eq!(responses[1].opaque, 101);
+        assert_eq!(
+            responses[1].code,
+            frame::ResponseCode::RequestCodeNotSupported as i32
+        );
+        Ok(())
+    }
 
     #[tokio::test]
     async fn test_connection_new() -> Result<(), error::ClientError> {
@@ -142,9 +750,9 @@ mod tests {
                 let body = response.body();
                 let topic_route_data: TopicRouteData = serde_json::from_reader(body.reader())
                     .map_err(|_e| {
-                        return crate::error::ClientError::InvalidFrame(
+                        crate::error::ClientError::InvalidFrame(
                             "Response body is invalid JSON".to_owned(),
-                        );
+                        )
                     })?;
                 topic_route_data.broker_datas.iter().for_each(|item| {
                     println!("{:#?}", item);
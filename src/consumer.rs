@@ -0,0 +1,2240 @@
+//!
+//! Define `PushConsumer`, which pulls messages of subscribed topics in background and delivers them to the registered listener.
+//!
+use crate::client::{current_millis, Client, ClientOption};
+use crate::connection::RequestProcessor;
+use crate::error::ClientError;
+use crate::filter::{self, MessageSelector};
+use crate::frame::{Frame, RequestCode, ResponseCode};
+use crate::listener::{
+    ConsumeConcurrentlyContext, ConsumeConcurrentlyStatus, ConsumeOrderlyContext,
+    ConsumeOrderlyStatus, MessageListenerConcurrently, MessageListenerOrderly,
+};
+use crate::message::{self, property, Message, MessageExt, MessageQueue};
+use crate::offset_store::{
+    LocalFileOffsetStore, OffsetStore, ReadOffsetType, RemoteBrokerOffsetStore,
+};
+use crate::process_queue::{ProcessQueue, ProcessQueueStats};
+use crate::protocol;
+use crate::publisher::Publisher;
+use crate::rebalance;
+use futures_core::Stream;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, watch, Semaphore};
+
+/// Brokers hold a long polling pull request for at most this period if no message is available.
+pub(crate) const BROKER_SUSPEND_MAX_TIME: Duration = Duration::from_secs(15);
+
+/// Timeout of a long polling pull request, which must exceed `BROKER_SUSPEND_MAX_TIME`.
+pub(crate) const PULL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Back-off before pulling again after a failure, or while the queue is not locked.
+pub(crate) const PULL_DELAY_WHEN_EXCEPTION: Duration = Duration::from_secs(3);
+
+/// Upper bound of waiting for an in-flight consume attempt before releasing a queue lock.
+const UNLOCK_WAIT_TIME: Duration = Duration::from_secs(1);
+
+/// Back-off before consuming again messages that could neither be consumed nor sent back to broker.
+const CONSUME_LATER_DELAY: Duration = Duration::from_secs(5);
+
+/// Delay level of messages sent back as they exceeded the consume timeout, which is 10 seconds by default.
+const TIMEOUT_DELAY_LEVEL: i32 = 3;
+
+/// Group of the publisher sending back messages that brokers failed to accept through CONSUMER_SEND_MSG_BACK.
+const CLIENT_INNER_PRODUCER_GROUP: &str = "CLIENT_INNER_PRODUCER";
+
+type QueueEntry = (MessageQueue, Arc<ProcessQueue>);
+
+/// How messages of subscribed topics are distributed among members of a consumer group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageModel {
+    /// Members share the queues of each topic, so that each message is consumed by one member only.
+    /// Consume offsets are kept by brokers.
+    Clustering,
+
+    /// Every member consumes all queues of each topic, so that each message is consumed by all members.
+    /// Consume offsets are kept locally and failed messages are never sent back for retry.
+    Broadcasting,
+}
+
+impl MessageModel {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            MessageModel::Clustering => "CLUSTERING",
+            MessageModel::Broadcasting => "BROADCASTING",
+        }
+    }
+}
+
+/// Where a consumer group starts consuming a queue it has never committed an offset of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsumeFromWhere {
+    /// Skip messages stored before, consuming only those arriving from now on.
+    LastOffset,
+
+    /// Consume from the oldest message still stored.
+    FirstOffset,
+
+    /// Consume from the first message stored at or after the given time.
+    Timestamp(SystemTime),
+}
+
+impl ConsumeFromWhere {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ConsumeFromWhere::LastOffset => "CONSUME_FROM_LAST_OFFSET",
+            ConsumeFromWhere::FirstOffset => "CONSUME_FROM_FIRST_OFFSET",
+            ConsumeFromWhere::Timestamp(_) => "CONSUME_FROM_TIMESTAMP",
+        }
+    }
+}
+
+/// Options of a push consumer.
+#[derive(Debug, Clone)]
+pub struct ConsumerOption {
+    /// Consumer group, whose members share consumption progress and load of subscribed topics.
+    pub group: String,
+
+    pub message_model: MessageModel,
+
+    /// Where to start consuming queues the group has never committed an offset of.
+    pub consume_from_where: ConsumeFromWhere,
+
+    /// Directory where offsets of broadcasting consumers are persisted.
+    pub offset_store_dir: PathBuf,
+
+    /// Maximum number of messages fetched by a single pull request.
+    pub pull_batch_size: i32,
+
+    /// Maximum number of messages delivered to the listener at once.
+    pub consume_message_batch_max_size: usize,
+
+    /// Maximum number of batches consumed at the same time by a concurrent listener.
+    pub consume_concurrency: usize,
+
+    /// Pulling a queue pauses while it caches more messages than this.
+    pub pull_threshold_for_queue: usize,
+
+    /// Pulling a queue pauses while the total body size of its cached messages exceeds this many bytes.
+    pub pull_threshold_size_for_queue: usize,
+
+    /// Pulling a queue pauses while offsets of its cached messages span wider than this, as a message stuck in a
+    /// concurrent listener holds back the offset committed for the queue.
+    pub consume_concurrently_max_span: i64,
+
+    /// Back-off before pulling a queue again once its thresholds are exceeded.
+    pub pull_time_delay_when_flow_control: Duration,
+
+    /// Messages consumed concurrently for longer than this are sent back for retry, so that they no longer hold back
+    /// the offset of their queue. Checked at the same interval.
+    pub consume_timeout: Duration,
+
+    /// Messages failing more times than this are sent to the dead letter queue of the group.
+    pub max_reconsume_times: i32,
+
+    /// How long a queue is suspended once the orderly listener asks to consume its messages again.
+    pub suspend_current_queue_time: Duration,
+
+    /// Interval between re-allocations of queues among members of the group.
+    pub rebalance_interval: Duration,
+
+    /// Interval between renewals of the broker side locks of assigned queues.
+    pub lock_interval: Duration,
+
+    /// Interval between persisting consume offsets of assigned queues.
+    pub persist_consumer_offset_interval: Duration,
+}
+
+impl Default for ConsumerOption {
+    fn default() -> Self {
+        Self {
+            group: "DEFAULT_CONSUMER".to_owned(),
+            message_model: MessageModel::Clustering,
+            consume_from_where: ConsumeFromWhere::LastOffset,
+            offset_store_dir: default_offset_store_dir(),
+            pull_batch_size: 32,
+            consume_message_batch_max_size: 1,
+            consume_concurrency: 20,
+            pull_threshold_for_queue: 1000,
+            pull_threshold_size_for_queue: 100 * 1024 * 1024,
+            consume_concurrently_max_span: 2000,
+            pull_time_delay_when_flow_control: Duration::from_millis(50),
+            consume_timeout: Duration::from_secs(15 * 60),
+            max_reconsume_times: 16,
+            suspend_current_queue_time: Duration::from_secs(1),
+            rebalance_interval: Duration::from_secs(20),
+            lock_interval: Duration::from_secs(20),
+            persist_consumer_offset_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Offsets of broadcasting consumers are kept under the home directory by default.
+pub(crate) fn default_offset_store_dir() -> PathBuf {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join(".rocketmq_offsets")
+}
+
+/// Consumer that pulls messages in background and pushes them to the registered listener.
+///
+/// With an orderly listener, each assigned queue is locked at its broker so that no other member of the group
+/// consumes it at the same time, and messages of the queue are delivered strictly serially.
+///
+/// With a concurrent listener, messages failed to consume are sent back to broker and redelivered through the retry
+/// topic of the group after a delay, until they exhaust `max_reconsume_times` and land in its dead letter queue.
+pub struct PushConsumer {
+    inner: Arc<ConsumerInner>,
+    shutdown: watch::Sender<bool>,
+    started: AtomicBool,
+}
+
+struct ConsumerInner {
+    client: Arc<Client>,
+    option: ConsumerOption,
+    offset_store: Box<dyn OffsetStore>,
+    subscriptions: RwLock<HashMap<String, protocol::SubscriptionData>>,
+    listener: RwLock<Option<Listener>>,
+    process_queues: Mutex<HashMap<MessageQueue, Arc<ProcessQueue>>>,
+
+    /// Serialize rebalances triggered periodically and by subscription changes.
+    rebalance_lock: tokio::sync::Mutex<()>,
+    consume_permits: Semaphore,
+    publisher: Publisher,
+    shutdown: watch::Receiver<bool>,
+}
+
+#[derive(Clone)]
+enum Listener {
+    Orderly(Arc<dyn MessageListenerOrderly>),
+    Concurrently(Arc<dyn MessageListenerConcurrently>),
+    Stream(mpsc::UnboundedSender<ReceivedMessage>),
+}
+
+impl PushConsumer {
+    pub fn new(client_option: ClientOption, option: ConsumerOption) -> Result<Self, ClientError> {
+        let (tx, rx) = watch::channel(false);
+        let client = Arc::new(Client::new(client_option)?);
+        let offset_store = new_offset_store(
+            &client,
+            option.message_model,
+            &option.offset_store_dir,
+            &option.group,
+        );
+        let publisher = Publisher::with_client(Arc::clone(&client), CLIENT_INNER_PRODUCER_GROUP);
+        let inner = ConsumerInner {
+            client,
+            consume_permits: Semaphore::new(option.consume_concurrency),
+            option,
+            offset_store,
+            subscriptions: RwLock::new(HashMap::new()),
+            listener: RwLock::new(None),
+            process_queues: Mutex::new(HashMap::new()),
+            rebalance_lock: tokio::sync::Mutex::new(()),
+            publisher,
+            shutdown: rx,
+        };
+        Ok(Self {
+            inner: Arc::new(inner),
+            shutdown: tx,
+            started: AtomicBool::new(false),
+        })
+    }
+
+    /// Subscribe messages of the topic whose tag matches the expression, such as `TagA || TagB`, or `*` for all.
+    ///
+    /// Subscriptions may be added or replaced after the consumer starts; brokers are informed right away.
+    ///
+    /// # Errors
+    /// Raise ClientError::InvalidExpression if the expression names no tag.
+    pub fn subscribe(&self, topic: &str, expression: &str) -> Result<(), ClientError> {
+        self.subscribe_with_selector(topic, &MessageSelector::by_tag(expression))
+    }
+
+    /// Subscribe messages of the topic selected by a tag or SQL92 expression.
+    ///
+    /// # Errors
+    /// Raise ClientError::InvalidExpression if the expression is malformed.
+    pub fn subscribe_with_selector(
+        &self,
+        topic: &str,
+        selector: &MessageSelector,
+    ) -> Result<(), ClientError> {
+        {
+            let mut subscriptions = self
+                .inner
+                .subscriptions
+                .write()
+                .map_err(|_e| ClientError::Unknown)?;
+            // Brokers discard subscriptions whose version is not newer than the one they know.
+            let sub_version = match subscriptions.get(topic) {
+                Some(previous) => current_millis().max(previous.sub_version + 1),
+                None => current_millis(),
+            };
+            let subscription = filter::build_subscription(topic, selector, sub_version)?;
+            subscriptions.insert(topic.to_owned(), subscription);
+        }
+        self.refresh();
+        Ok(())
+    }
+
+    /// Stop consuming messages of the topic.
+    pub fn unsubscribe(&self, topic: &str) -> Result<(), ClientError> {
+        self.inner
+            .subscriptions
+            .write()
+            .map_err(|_e| ClientError::Unknown)?
+            .remove(topic);
+        self.refresh();
+        Ok(())
+    }
+
+    /// Apply changed subscriptions of a started consumer without waiting for the periodic heartbeat and rebalance.
+    fn refresh(&self) {
+        if !self.started.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let inner = Arc::clone(&self.inner);
+            handle.spawn(async move {
+                inner.send_heartbeat().await;
+                inner.rebalance().await;
+            });
+        }
+    }
+
+    /// Register the listener to consume messages of each queue in order.
+    pub fn register_message_listener_orderly(
+        &self,
+        listener: impl MessageListenerOrderly + 'static,
+    ) -> Result<(), ClientError> {
+        *self
+            .inner
+            .listener
+            .write()
+            .map_err(|_e| ClientError::Unknown)? = Some(Listener::Orderly(Arc::new(listener)));
+        Ok(())
+    }
+
+    /// Register the listener to consume messages concurrently.
+    pub fn register_message_listener_concurrently(
+        &self,
+        listener: impl MessageListenerConcurrently + 'static,
+    ) -> Result<(), ClientError> {
+        *self
+            .inner
+            .listener
+            .write()
+            .map_err(|_e| ClientError::Unknown)? = Some(Listener::Concurrently(Arc::new(listener)));
+        Ok(())
+    }
+
+    /// Consume messages as a stream instead of through a listener, which must be called before the consumer starts.
+    ///
+    /// Messages are yielded as they are pulled, subject to the same flow control as listeners: a queue stops being
+    /// pulled while too many of its messages are yielded but not yet acknowledged. Each message must be acknowledged
+    /// by `ReceivedMessage::ack` once consumed, or given up by `ReceivedMessage::nack`, or dropping it, to be
+    /// redelivered later through the retry topic of the group. The stream ends once the consumer shuts down.
+    ///
+    /// The stream yields ClientError::IllegalState and ends right away if a listener is already registered.
+    pub fn stream(&self) -> impl Stream<Item = Result<ReceivedMessage, ClientError>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let error = match self.inner.listener.write() {
+            Ok(mut listener) if listener.is_none() => {
+                *listener = Some(Listener::Stream(tx));
+                None
+            }
+            Ok(_) => Some(ClientError::IllegalState(
+                "Message listener is already registered".to_owned(),
+            )),
+            Err(_e) => Some(ClientError::Unknown),
+        };
+        MessageStream {
+            receiver: rx,
+            error,
+            shutdown: self.inner.shutdown.clone(),
+        }
+    }
+
+    /// Snapshot of each assigned queue, including messages cached and flow control events, for monitoring.
+    pub fn process_queue_stats(&self) -> HashMap<MessageQueue, ProcessQueueStats> {
+        self.inner
+            .process_queues
+            .lock()
+            .map(|map| {
+                map.iter()
+                    .map(|(mq, pq)| (mq.clone(), pq.stats()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Address of the node messages of the queue are pulled from next: a slave if its master suggested so in the
+    /// last pull response, or the master otherwise.
+    ///
+    /// # Errors
+    /// Raise ClientError::BrokerNotFound if the route of the topic lists no node of the broker.
+    pub async fn preferred_broker_addr(&self, mq: &MessageQueue) -> Result<String, ClientError> {
+        Ok(self.inner.client.find_pull_addr(mq).await?.0)
+    }
+
+    /// Fetch up to `max_nums` messages from the dead letter queues of the group, oldest first, without
+    /// affecting consumption.
+    ///
+    /// Return an empty list if no message of the group has ever exhausted its retries.
+    pub async fn peek_dead_letter_messages(
+        &self,
+        max_nums: i32,
+    ) -> Result<Vec<MessageExt>, ClientError> {
+        self.inner.peek_dead_letter_messages(max_nums).await
+    }
+
+    /// Reset consume offsets of queues of the topic assigned to this consumer to those of the first messages stored
+    /// at or after the given time. Messages pulled but not yet consumed are discarded.
+    ///
+    /// Brokers may also reset offsets of all members of the group through RESET_CONSUMER_CLIENT_OFFSET, which is
+    /// handled the same way once the consumer starts.
+    ///
+    /// # Errors
+    /// Raise ClientError if the offset of any queue could not be searched, in which case no queue is reset.
+    pub async fn reset_offset_by_timestamp(
+        &self,
+        topic: &str,
+        timestamp: SystemTime,
+    ) -> Result<(), ClientError> {
+        let millis = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or_default();
+        let mqs: Vec<MessageQueue> = match self.inner.process_queues.lock() {
+            Ok(map) => map.keys().filter(|mq| mq.topic == topic).cloned().collect(),
+            Err(_e) => return Err(ClientError::Unknown),
+        };
+        let mut offset_table = HashMap::new();
+        for mq in mqs {
+            let offset = self.inner.client.search_offset(&mq, millis).await?;
+            offset_table.insert(mq, offset);
+        }
+        self.inner.reset_offsets(topic, offset_table).await;
+        Ok(())
+    }
+
+    /// Start consuming in background.
+    ///
+    /// # Errors
+    /// Raise ClientError::IllegalState if the consumer is started twice or after shutdown, or no listener is
+    /// registered.
+    pub async fn start(&self) -> Result<(), ClientError> {
+        if self.inner.is_shutdown() {
+            return Err(ClientError::IllegalState(
+                "Consumer has already been shut down".to_owned(),
+            ));
+        }
+        if self.inner.listener()?.is_none() {
+            return Err(ClientError::IllegalState(
+                "Message listener is not registered".to_owned(),
+            ));
+        }
+        if self.started.swap(true, Ordering::SeqCst) {
+            return Err(ClientError::IllegalState(
+                "Consumer has already been started".to_owned(),
+            ));
+        }
+
+        if let Err(e) = self.prepare_start().await {
+            // Allow starting again once the cause is fixed.
+            self.started.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+
+        // Brokers must know this client before it may be allocated queues or lock them.
+        self.inner.send_heartbeat().await;
+        self.inner.rebalance().await;
+
+        let option = &self.inner.option;
+        spawn_periodic(
+            &self.inner,
+            &self.inner.shutdown,
+            self.inner.client.option.heartbeat_interval,
+            |inner| async move { inner.send_heartbeat().await },
+        );
+        spawn_periodic(
+            &self.inner,
+            &self.inner.shutdown,
+            option.rebalance_interval,
+            |inner| async move { inner.rebalance().await },
+        );
+        if self.inner.needs_lock() {
+            spawn_periodic(
+                &self.inner,
+                &self.inner.shutdown,
+                option.lock_interval,
+                |inner| async move { inner.lock_all().await },
+            );
+        }
+        if let Ok(Some(Listener::Concurrently(_) | Listener::Stream(_))) = self.inner.listener() {
+            spawn_periodic(
+                &self.inner,
+                &self.inner.shutdown,
+                option.consume_timeout,
+                |inner| async move { inner.clean_expired_messages().await },
+            );
+        }
+        spawn_periodic(
+            &self.inner,
+            &self.inner.shutdown,
+            option.persist_consumer_offset_interval,
+            |inner| async move { inner.persist_offsets().await },
+        );
+        Ok(())
+    }
+
+    /// Load offsets and register what brokers may call back or redeliver, before any queue is consumed.
+    async fn prepare_start(&self) -> Result<(), ClientError> {
+        self.inner.offset_store.load().await?;
+        self.inner.client.connections.register_processor(
+            RequestCode::ResetConsumerClientOffset,
+            Arc::new(ResetOffsetProcessor {
+                inner: Arc::downgrade(&self.inner),
+            }),
+        );
+        if self.inner.is_clustering() {
+            // Messages sent back for retry are redelivered through the retry topic of the group.
+            self.subscribe(
+                &message::retry_topic(&self.inner.option.group),
+                filter::SUB_ALL,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Stop consuming, release locks of assigned queues and unregister from brokers. The consumer cannot be started
+    /// again.
+    pub async fn shutdown(&self) {
+        if !self.started.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let _ = self.shutdown.send(true);
+        self.inner.persist_offsets().await;
+        if let Ok(mut listener) = self.inner.listener.write() {
+            // Dropping the sender ends the message stream.
+            if let Some(Listener::Stream(_)) = *listener {
+                *listener = None;
+            }
+        }
+
+        let process_queues: Vec<_> = match self.inner.process_queues.lock() {
+            Ok(mut map) => map.drain().collect(),
+            Err(_e) => vec![],
+        };
+        for (mq, pq) in process_queues {
+            pq.set_dropped();
+            self.inner.unlock(&mq, &pq).await;
+        }
+        self.inner
+            .client
+            .unregister_consumer(&self.inner.option.group, &self.inner.topics())
+            .await;
+    }
+}
+
+/// Offsets of clustering consumers are kept by brokers, while those of broadcasting consumers are kept in local files.
+pub(crate) fn new_offset_store(
+    client: &Arc<Client>,
+    message_model: MessageModel,
+    offset_store_dir: &Path,
+    group: &str,
+) -> Box<dyn OffsetStore> {
+    match message_model {
+        MessageModel::Clustering => {
+            Box::new(RemoteBrokerOffsetStore::new(Arc::clone(client), group))
+        }
+        MessageModel::Broadcasting => Box::new(LocalFileOffsetStore::new(
+            offset_store_dir,
+            &client.client_id,
+            group,
+        )),
+    }
+}
+
+/// Determine where to start pulling the queue: its committed offset, or the one `consume_from_where` points at if
+/// the group has not consumed it before.
+pub(crate) async fn compute_pull_from_where(
+    client: &Client,
+    offset_store: &dyn OffsetStore,
+    mq: &MessageQueue,
+    consume_from_where: ConsumeFromWhere,
+) -> Result<i64, ClientError> {
+    if let Some(offset) = offset_store
+        .read_offset(mq, ReadOffsetType::ReadFromStore)
+        .await?
+    {
+        if offset >= 0 {
+            return Ok(offset);
+        }
+    }
+
+    // Messages in retry topics are all to be redelivered, however old they are.
+    if mq.topic.starts_with(message::RETRY_GROUP_TOPIC_PREFIX) {
+        return client.min_offset(mq).await;
+    }
+    match consume_from_where {
+        ConsumeFromWhere::LastOffset => client.max_offset(mq).await,
+        ConsumeFromWhere::FirstOffset => client.min_offset(mq).await,
+        ConsumeFromWhere::Timestamp(timestamp) => {
+            let millis = timestamp
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or_default();
+            client.search_offset(mq, millis).await
+        }
+    }
+}
+
+/// Serve RESET_CONSUMER_CLIENT_OFFSET, through which brokers rewind consumers of a group, for instance once an
+/// administrator resets its offsets by timestamp.
+struct ResetOffsetProcessor {
+    /// Connections outlive the consumer otherwise, as they are owned by it in turn.
+    inner: Weak<ConsumerInner>,
+}
+
+impl ResetOffsetProcessor {
+    fn parse(
+        request: &Frame,
+    ) -> Result<
+        (
+            protocol::ResetOffsetRequestHeader,
+            protocol::ResetOffsetBody,
+        ),
+        ClientError,
+    > {
+        let header = protocol::ResetOffsetRequestHeader::try_from(&request.ext_fields)?;
+        let body = request.json_body()?;
+        Ok((header, body))
+    }
+}
+
+impl RequestProcessor for ResetOffsetProcessor {
+    fn process(&self, request: &Frame) -> Frame {
+        let mut response = Frame::new();
+        let (header, body) = match ResetOffsetProcessor::parse(request) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                response.code = ResponseCode::SystemError as i32;
+                response.remark = e.to_string();
+                return response;
+            }
+        };
+        if let Some(inner) = self.inner.upgrade() {
+            if header.group == inner.option.group && !inner.is_shutdown() {
+                tokio::spawn(
+                    async move { inner.reset_offsets(&header.topic, body.offset_table).await },
+                );
+            }
+        }
+        response
+    }
+}
+
+/// Spawn a task running `f` every `period` until `shutdown` is signaled.
+pub(crate) fn spawn_periodic<T, F, Fut>(
+    inner: &Arc<T>,
+    shutdown: &watch::Receiver<bool>,
+    period: Duration,
+    f: F,
+) where
+    T: Send + Sync + 'static,
+    F: Fn(Arc<T>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let inner = Arc::clone(inner);
+    let mut shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => f(Arc::clone(&inner)).await,
+                _ = shutdown.changed() => break,
+            }
+        }
+    });
+}
+
+/// Messages found by a pull, with those not matching the subscription filtered out.
+#[derive(Default)]
+pub(crate) struct PulledMessages {
+    pub(crate) messages: Vec<MessageExt>,
+
+    /// Offset the queue advanced to as messages filtered out are done with, if it did.
+    pub(crate) skipped_to: Option<i64>,
+}
+
+/// Header of a long polling pull of the queue from its next offset on, committing no offset.
+pub(crate) fn pull_request_header(
+    group: &str,
+    max_msg_nums: i32,
+    mq: &MessageQueue,
+    pq: &ProcessQueue,
+    subscription: &protocol::SubscriptionData,
+) -> protocol::PullMessageRequestHeader {
+    protocol::PullMessageRequestHeader {
+        consumer_group: group.to_owned(),
+        topic: mq.topic.clone(),
+        queue_id: mq.queue_id,
+        queue_offset: pq.next_offset(),
+        max_msg_nums,
+        sys_flag: protocol::PULL_FLAG_SUSPEND | protocol::PULL_FLAG_SUBSCRIPTION,
+        commit_offset: 0,
+        suspend_timeout_millis: BROKER_SUSPEND_MAX_TIME.as_millis() as i64,
+        subscription: Some(subscription.sub_string.clone()),
+        sub_version: subscription.sub_version,
+        expression_type: Some(subscription.expression_type.clone()),
+    }
+}
+
+/// Pull messages of the queue from the broker node suggested last, moving the next offset of `pq` on as the broker
+/// tells. Nothing is pulled if `shutdown` is signaled meanwhile.
+pub(crate) async fn pull_messages(
+    client: &Client,
+    mut header: protocol::PullMessageRequestHeader,
+    mq: &MessageQueue,
+    pq: &ProcessQueue,
+    subscription: &protocol::SubscriptionData,
+    shutdown: &watch::Receiver<bool>,
+) -> Result<PulledMessages, ClientError> {
+    let (addr, slave) = client.find_pull_addr(mq).await?;
+    // Slaves do not accept offsets.
+    if slave {
+        header.sys_flag &= !protocol::PULL_FLAG_COMMIT_OFFSET;
+    }
+    let frame = Frame::request(RequestCode::PullMessage, header);
+
+    let mut shutdown = shutdown.clone();
+    let response = tokio::select! {
+        response = client.connections.invoke(&addr, frame, PULL_TIMEOUT) => response,
+        _ = shutdown.changed() => return Ok(PulledMessages::default()),
+    };
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            // The slave may be down, so go back to the master.
+            if slave {
+                client.suggest_pull_node(mq, protocol::MASTER_ID);
+            }
+            return Err(e);
+        }
+    };
+
+    let code = response.code;
+    let mut pulled = PulledMessages::default();
+    if code == ResponseCode::Success as i32 {
+        let header = protocol::PullMessageResponseHeader::try_from(&response.ext_fields)?;
+        client.suggest_pull_node(mq, header.suggest_which_broker_id);
+        let mut messages = MessageExt::decode_batch(response.body())?;
+        let last_offset = messages.last().map(|message| message.queue_offset);
+        messages.retain(|message| filter::is_matched(subscription, message));
+        messages
+            .iter_mut()
+            .for_each(|message| message.broker_name = mq.broker_name.clone());
+        pq.set_next_offset(header.next_begin_offset);
+        // Messages filtered out are done with, which may allow the offset to advance.
+        pulled.skipped_to = last_offset.and_then(|last_offset| pq.skip_to(last_offset));
+        pulled.messages = messages;
+    } else if code == ResponseCode::PullNotFound as i32
+        || code == ResponseCode::PullRetryImmediately as i32
+    {
+        let header = protocol::PullMessageResponseHeader::try_from(&response.ext_fields)?;
+        client.suggest_pull_node(mq, header.suggest_which_broker_id);
+        pq.set_next_offset(header.next_begin_offset);
+    } else if code == ResponseCode::PullOffsetMoved as i32 {
+        let header = protocol::PullMessageResponseHeader::try_from(&response.ext_fields)?;
+        client.suggest_pull_node(mq, header.suggest_which_broker_id);
+        eprintln!(
+            "Pull offset {} of {:?} is illegal, moving to {}",
+            pq.next_offset(),
+            mq,
+            header.next_begin_offset
+        );
+        pq.set_next_offset(header.next_begin_offset);
+    } else {
+        response.ensure_success()?;
+    }
+    Ok(pulled)
+}
+
+impl ConsumerInner {
+    fn listener(&self) -> Result<Option<Listener>, ClientError> {
+        self.listener
+            .read()
+            .map(|listener| listener.clone())
+            .map_err(|_e| ClientError::Unknown)
+    }
+
+    fn topics(&self) -> Vec<String> {
+        self.subscriptions
+            .read()
+            .map(|map| map.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn is_clustering(&self) -> bool {
+        self.option.message_model == MessageModel::Clustering
+    }
+
+    /// Queues must be locked at broker before consumed in order, unless every member consumes all of them.
+    fn needs_lock(&self) -> bool {
+        self.is_clustering() && matches!(self.listener(), Ok(Some(Listener::Orderly(_))))
+    }
+
+    fn is_consumable(&self, pq: &ProcessQueue) -> bool {
+        !self.needs_lock() || pq.is_lock_valid()
+    }
+
+    fn is_shutdown(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Sleep for the given duration, waking up early if the consumer shuts down.
+    async fn sleep(&self, duration: Duration) {
+        let mut shutdown = self.shutdown.clone();
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => {}
+            _ = shutdown.changed() => {}
+        }
+    }
+
+    async fn invoke(&self, addr: &str, frame: Frame) -> Result<Frame, ClientError> {
+        self.client.invoke(addr, frame).await
+    }
+
+    async fn find_master(&self, topic: &str, broker_name: &str) -> Result<String, ClientError> {
+        self.client.find_master(topic, broker_name).await
+    }
+
+    async fn send_heartbeat(&self) {
+        let subscription_data_set = self
+            .subscriptions
+            .read()
+            .map(|map| map.values().cloned().collect())
+            .unwrap_or_default();
+        self.client
+            .send_heartbeat(protocol::ConsumerData {
+                group_name: self.option.group.clone(),
+                consume_type: "CONSUME_PASSIVELY".to_owned(),
+                message_model: self.option.message_model.as_str().to_owned(),
+                consume_from_where: self.option.consume_from_where.as_str().to_owned(),
+                subscription_data_set,
+                unit_mode: false,
+            })
+            .await;
+    }
+
+    async fn rebalance(self: &Arc<Self>) {
+        let _guard = self.rebalance_lock.lock().await;
+        let topics = self.topics();
+        for topic in &topics {
+            if let Err(e) = self.rebalance_topic(topic).await {
+                eprintln!("Failed to rebalance topic {}. Cause: {}", topic, e);
+            }
+        }
+
+        // Release queues of topics no longer subscribed.
+        let unsubscribed: HashSet<String> = match self.process_queues.lock() {
+            Ok(map) => map
+                .keys()
+                .filter(|mq| !topics.contains(&mq.topic))
+                .map(|mq| mq.topic.clone())
+                .collect(),
+            Err(_e) => return,
+        };
+        for topic in unsubscribed {
+            self.update_process_queues(&topic, vec![]).await;
+        }
+    }
+
+    async fn rebalance_topic(self: &Arc<Self>, topic: &str) -> Result<(), ClientError> {
+        let mq_all = self.client.subscribe_queues(topic).await?;
+        if !self.is_clustering() {
+            self.update_process_queues(topic, mq_all).await;
+            return Ok(());
+        }
+
+        let mut cid_all = self
+            .client
+            .consumer_id_list(topic, &self.option.group)
+            .await?;
+        cid_all.sort();
+
+        let allocated = rebalance::allocate_averagely(&self.client.client_id, &mq_all, &cid_all);
+        self.update_process_queues(topic, allocated).await;
+        Ok(())
+    }
+
+    async fn update_process_queues(self: &Arc<Self>, topic: &str, allocated: Vec<MessageQueue>) {
+        let allocated: HashSet<MessageQueue> = allocated.into_iter().collect();
+        let removed: Vec<QueueEntry> = match self.process_queues.lock() {
+            Ok(mut map) => {
+                let keys: Vec<MessageQueue> = map
+                    .keys()
+                    .filter(|mq| mq.topic == topic && !allocated.contains(mq))
+                    .cloned()
+                    .collect();
+                keys.into_iter()
+                    .filter_map(|mq| map.remove(&mq).map(|pq| (mq, pq)))
+                    .collect()
+            }
+            Err(_e) => return,
+        };
+        for (mq, pq) in removed {
+            pq.set_dropped();
+            self.offset_store.persist(&mq).await;
+            self.offset_store.remove_offset(&mq);
+            self.unlock(&mq, &pq).await;
+        }
+
+        for mq in allocated {
+            let exists = self
+                .process_queues
+                .lock()
+                .map(|map| map.contains_key(&mq))
+                .unwrap_or(true);
+            if exists {
+                continue;
+            }
+
+            // Only start pulling a queue once it is exclusively locked, or it may be consumed out of order.
+            if self.needs_lock() && !self.lock(&mq).await {
+                continue;
+            }
+            self.offset_store.remove_offset(&mq);
+            let offset = match compute_pull_from_where(
+                &self.client,
+                self.offset_store.as_ref(),
+                &mq,
+                self.option.consume_from_where,
+            )
+            .await
+            {
+                Ok(offset) => offset,
+                Err(e) => {
+                    eprintln!("Failed to fetch consume offset of {:?}. Cause: {}", mq, e);
+                    continue;
+                }
+            };
+            let pq = Arc::new(ProcessQueue::new(offset));
+            pq.set_locked(true);
+            if let Ok(mut map) = self.process_queues.lock() {
+                map.insert(mq.clone(), Arc::clone(&pq));
+            }
+            tokio::spawn(Arc::clone(self).pull_loop(mq.clone(), Arc::clone(&pq)));
+            if let Ok(Some(Listener::Orderly(_))) = self.listener() {
+                tokio::spawn(Arc::clone(self).consume_loop(mq, pq));
+            }
+        }
+    }
+
+    /// Rewind, or fast-forward, assigned queues of the topic to the given offsets, discarding messages pulled
+    /// but not yet consumed.
+    async fn reset_offsets(
+        self: &Arc<Self>,
+        topic: &str,
+        offset_table: HashMap<MessageQueue, i64>,
+    ) {
+        let mut reset = vec![];
+        if let Ok(mut map) = self.process_queues.lock() {
+            for (mq, offset) in offset_table {
+                if mq.topic != topic {
+                    continue;
+                }
+                let old = match map.get(&mq) {
+                    Some(pq) => Arc::clone(pq),
+                    None => continue,
+                };
+                old.set_dropped();
+                let pq = Arc::new(ProcessQueue::new(offset));
+                // The broker side lock, if any, is held by this client still.
+                pq.set_locked(!self.needs_lock() || old.is_lock_valid());
+                map.insert(mq.clone(), Arc::clone(&pq));
+                reset.push((mq, pq, offset));
+            }
+        }
+
+        for (mq, _, offset) in &reset {
+            self.offset_store.update_offset(mq, *offset, false);
+        }
+        let mqs: HashSet<MessageQueue> = reset.iter().map(|(mq, _, _)| mq.clone()).collect();
+        self.offset_store.persist_all(&mqs).await;
+        for (mq, pq, _) in reset {
+            tokio::spawn(Arc::clone(self).pull_loop(mq.clone(), Arc::clone(&pq)));
+            if let Ok(Some(Listener::Orderly(_))) = self.listener() {
+                tokio::spawn(Arc::clone(self).consume_loop(mq, pq));
+            }
+        }
+    }
+
+    /// Persist consume offsets of all assigned queues.
+    async fn persist_offsets(&self) {
+        let mqs: HashSet<MessageQueue> = match self.process_queues.lock() {
+            Ok(map) => map.keys().cloned().collect(),
+            Err(_e) => return,
+        };
+        self.offset_store.persist_all(&mqs).await;
+    }
+
+    async fn lock_batch(
+        &self,
+        addr: &str,
+        mq_set: Vec<MessageQueue>,
+    ) -> Result<HashSet<MessageQueue>, ClientError> {
+        let body = protocol::LockBatchRequestBody {
+            consumer_group: self.option.group.clone(),
+            client_id: self.client.client_id.clone(),
+            mq_set,
+        };
+        let mut frame = Frame::request(RequestCode::LockBatchMq, HashMap::new());
+        frame.set_json_body(&body)?;
+        let response = self.invoke(addr, frame).await?;
+        response.ensure_success()?;
+        let body: protocol::LockBatchResponseBody = response.json_body()?;
+        Ok(body.lock_ok_mq_set.into_iter().collect())
+    }
+
+    async fn lock(&self, mq: &MessageQueue) -> bool {
+        let result = match self.find_master(&mq.topic, &mq.broker_name).await {
+            Ok(addr) => self.lock_batch(&addr, vec![mq.clone()]).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(locked) => locked.contains(mq),
+            Err(e) => {
+                eprintln!("Failed to lock {:?}. Cause: {}", mq, e);
+                false
+            }
+        }
+    }
+
+    /// Renew locks of all assigned queues, broker by broker.
+    async fn lock_all(&self) {
+        let mut by_broker: HashMap<(String, String), Vec<QueueEntry>> = HashMap::new();
+        if let Ok(map) = self.process_queues.lock() {
+            for (mq, pq) in map.iter() {
+                by_broker
+                    .entry((mq.topic.clone(), mq.broker_name.clone()))
+                    .or_default()
+                    .push((mq.clone(), Arc::clone(pq)));
+            }
+        }
+
+        for ((topic, broker_name), queues) in by_broker {
+            let result = match self.find_master(&topic, &broker_name).await {
+                Ok(addr) => {
+                    let mq_set = queues.iter().map(|(mq, _)| mq.clone()).collect();
+                    self.lock_batch(&addr, mq_set).await
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(locked) => queues
+                    .iter()
+                    .for_each(|(mq, pq)| pq.set_locked(locked.contains(mq))),
+                Err(e) => eprintln!(
+                    "Failed to renew locks on broker {}. Cause: {}",
+                    broker_name, e
+                ),
+            }
+        }
+    }
+
+    /// Release the broker side lock of a dropped queue once its in-flight consume attempt, if any, completes.
+    async fn unlock(&self, mq: &MessageQueue, pq: &ProcessQueue) {
+        if !self.needs_lock() {
+            return;
+        }
+        let _guard = match tokio::time::timeout(UNLOCK_WAIT_TIME, pq.consume_lock.lock()).await {
+            Ok(guard) => guard,
+            // The lock expires at broker side eventually.
+            Err(_) => return,
+        };
+        pq.set_locked(false);
+        let addr = match self.find_master(&mq.topic, &mq.broker_name).await {
+            Ok(addr) => addr,
+            Err(e) => {
+                eprintln!("Failed to unlock {:?}. Cause: {}", mq, e);
+                return;
+            }
+        };
+        let body = protocol::UnlockBatchRequestBody {
+            consumer_group: self.option.group.clone(),
+            client_id: self.client.client_id.clone(),
+            mq_set: vec![mq.clone()],
+        };
+        let mut frame = Frame::request(RequestCode::UnlockBatchMq, HashMap::new());
+        let result = match frame.set_json_body(&body) {
+            Ok(_) => self.client.connections.invoke_oneway(&addr, frame).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to unlock {:?}. Cause: {}", mq, e);
+        }
+    }
+
+    async fn pull_loop(self: Arc<Self>, mq: MessageQueue, pq: Arc<ProcessQueue>) {
+        while !pq.is_dropped() && !self.is_shutdown() {
+            if !self.is_consumable(&pq) {
+                self.sleep(PULL_DELAY_WHEN_EXCEPTION).await;
+                continue;
+            }
+            if self.is_flow_controlled(&pq) {
+                pq.inc_flow_control_times();
+                self.sleep(self.option.pull_time_delay_when_flow_control)
+                    .await;
+                continue;
+            }
+            if let Err(e) = self.pull_once(&mq, &pq).await {
+                eprintln!("Failed to pull messages from {:?}. Cause: {}", mq, e);
+                self.sleep(PULL_DELAY_WHEN_EXCEPTION).await;
+            }
+        }
+    }
+
+    /// Whether the queue caches so many messages that pulling should wait for the listener to catch up.
+    fn is_flow_controlled(&self, pq: &ProcessQueue) -> bool {
+        let stats = pq.stats();
+        if stats.cached_msg_count > self.option.pull_threshold_for_queue
+            || stats.cached_msg_size > self.option.pull_threshold_size_for_queue
+        {
+            return true;
+        }
+        // Orderly listeners consume from the oldest message, so the span never grows while they lag behind.
+        matches!(
+            self.listener(),
+            Ok(Some(Listener::Concurrently(_) | Listener::Stream(_)))
+        ) && stats.max_span > self.option.consume_concurrently_max_span
+    }
+
+    async fn pull_once(
+        self: &Arc<Self>,
+        mq: &MessageQueue,
+        pq: &Arc<ProcessQueue>,
+    ) -> Result<(), ClientError> {
+        let subscription = match self.subscriptions.read() {
+            Ok(map) => match map.get(&mq.topic) {
+                Some(subscription) => subscription.clone(),
+                None => return Ok(()),
+            },
+            Err(_e) => return Err(ClientError::Unknown),
+        };
+
+        let mut header = pull_request_header(
+            &self.option.group,
+            self.option.pull_batch_size,
+            mq,
+            pq,
+            &subscription,
+        );
+        let commit_offset = self
+            .offset_store
+            .read_offset(mq, ReadOffsetType::ReadFromMemory)
+            .await?
+            .unwrap_or_default();
+        // Offsets of broadcasting consumers are private to each client and never committed to brokers.
+        if self.is_clustering() && commit_offset > 0 {
+            header.sys_flag |= protocol::PULL_FLAG_COMMIT_OFFSET;
+            header.commit_offset = commit_offset;
+        }
+
+        let pulled =
+            pull_messages(&self.client, header, mq, pq, &subscription, &self.shutdown).await?;
+        let mut messages = pulled.messages;
+        messages
+            .iter_mut()
+            .for_each(|message| message.reset_retry_topic(&self.option.group));
+        if let Some(offset) = pulled.skipped_to {
+            if messages.is_empty() {
+                self.advance_offset(mq, pq, offset);
+            }
+        }
+        if !messages.is_empty() && !pq.is_dropped() {
+            pq.put_messages(messages.clone());
+            match self.listener() {
+                Ok(Some(Listener::Concurrently(_))) => {
+                    for batch in messages.chunks(self.option.consume_message_batch_max_size.max(1))
+                    {
+                        tokio::spawn(Arc::clone(self).consume_concurrently(
+                            mq.clone(),
+                            Arc::clone(pq),
+                            batch.to_vec(),
+                        ));
+                    }
+                }
+                Ok(Some(Listener::Stream(sender))) => self.deliver(&sender, mq, pq, messages),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn consume_loop(self: Arc<Self>, mq: MessageQueue, pq: Arc<ProcessQueue>) {
+        let mut shutdown = self.shutdown.clone();
+        loop {
+            tokio::select! {
+                _ = pq.notify.notified() => {}
+                _ = shutdown.changed() => break,
+            }
+            if pq.is_dropped() {
+                break;
+            }
+            self.consume_orderly(&mq, &pq).await;
+        }
+    }
+
+    /// Deliver messages of the queue to the listener batch by batch, until none is left or the lock is lost.
+    async fn consume_orderly(&self, mq: &MessageQueue, pq: &ProcessQueue) {
+        let listener = match self.listener() {
+            Ok(Some(Listener::Orderly(listener))) => listener,
+            _ => return,
+        };
+        loop {
+            // Consumption resumes once the lock is renewed, which notifies the consume task.
+            if pq.is_dropped() || self.is_shutdown() || !self.is_consumable(pq) {
+                return;
+            }
+
+            let guard = pq.consume_lock.lock().await;
+            let messages = pq.take_messages(self.option.consume_message_batch_max_size);
+            if messages.is_empty() {
+                return;
+            }
+
+            let listener = Arc::clone(&listener);
+            let mut context = ConsumeOrderlyContext {
+                message_queue: mq.clone(),
+                suspend_current_queue_time: None,
+            };
+            let result = tokio::task::spawn_blocking(move || {
+                let status = panic::catch_unwind(AssertUnwindSafe(|| {
+                    listener.consume_message(&messages, &mut context)
+                }))
+                .unwrap_or(ConsumeOrderlyStatus::SuspendCurrentQueueAMoment);
+                (messages, context, status)
+            })
+            .await;
+            let (mut messages, context, status) = match result {
+                Ok(result) => result,
+                Err(_e) => {
+                    pq.rollback();
+                    return;
+                }
+            };
+
+            match status {
+                ConsumeOrderlyStatus::Success => self.commit(mq, pq),
+                ConsumeOrderlyStatus::SuspendCurrentQueueAMoment => {
+                    if self.check_reconsume_times(&mut messages).await {
+                        pq.make_messages_to_consume_again(messages);
+                        drop(guard);
+                        self.sleep(
+                            context
+                                .suspend_current_queue_time
+                                .unwrap_or(self.option.suspend_current_queue_time),
+                        )
+                        .await;
+                    } else {
+                        self.commit(mq, pq);
+                    }
+                }
+            }
+        }
+    }
+
+    fn commit(&self, mq: &MessageQueue, pq: &ProcessQueue) {
+        if let Some(offset) = pq.commit() {
+            if !pq.is_dropped() {
+                self.offset_store.update_offset(mq, offset, false);
+            }
+        }
+    }
+
+    /// Move the consume offset of the queue forward, unless the queue is dropped, as it may have been rewound since.
+    fn advance_offset(&self, mq: &MessageQueue, pq: &ProcessQueue, offset: i64) {
+        if !pq.is_dropped() {
+            self.offset_store.update_offset(mq, offset, true);
+        }
+    }
+
+    /// Send messages that exhausted their retries to the dead letter queue, counting a retry for the others.
+    /// Broadcasting consumers drop such messages instead.
+    ///
+    /// Return whether the queue should be suspended to consume some of the messages again.
+    async fn check_reconsume_times(&self, messages: &mut [MessageExt]) -> bool {
+        let mut suspend = false;
+        for message in messages.iter_mut() {
+            if message.reconsume_times >= self.option.max_reconsume_times {
+                if !self.is_clustering() {
+                    eprintln!(
+                        "Dropping message {} as it exhausted {} retries",
+                        message.msg_id, self.option.max_reconsume_times
+                    );
+                } else if let Err(e) = self.send_message_back(message, -1).await {
+                    eprintln!(
+                        "Failed to send message {} to dead letter queue. Cause: {}",
+                        message.msg_id, e
+                    );
+                    suspend = true;
+                    message.reconsume_times += 1;
+                }
+            } else {
+                suspend = true;
+                message.reconsume_times += 1;
+            }
+        }
+        suspend
+    }
+
+    /// Deliver a batch to the concurrent listener, sending back messages it fails to consume. Those that cannot be
+    /// sent back are kept in the process queue, holding back its offset, and consumed again locally after a delay.
+    async fn consume_concurrently(
+        self: Arc<Self>,
+        mq: MessageQueue,
+        pq: Arc<ProcessQueue>,
+        mut messages: Vec<MessageExt>,
+    ) {
+        let listener = match self.listener() {
+            Ok(Some(Listener::Concurrently(listener))) => listener,
+            _ => return,
+        };
+        loop {
+            let permit = match self.consume_permits.acquire().await {
+                Ok(permit) => permit,
+                Err(_e) => return,
+            };
+            if pq.is_dropped() || self.is_shutdown() {
+                return;
+            }
+
+            let offsets: Vec<i64> = messages.iter().map(|m| m.queue_offset).collect();
+            pq.start_consuming(&offsets);
+            let start = current_millis().to_string();
+            messages.iter_mut().for_each(|message| {
+                message
+                    .properties
+                    .insert(property::CONSUME_START_TIMESTAMP.to_owned(), start.clone());
+            });
+
+            let listener = Arc::clone(&listener);
+            let mut context = ConsumeConcurrentlyContext {
+                message_queue: mq.clone(),
+                delay_level_when_next_consume: 0,
+            };
+            let result = tokio::task::spawn_blocking(move || {
+                let status = panic::catch_unwind(AssertUnwindSafe(|| {
+                    listener.consume_message(&messages, &mut context)
+                }))
+                .unwrap_or(ConsumeConcurrentlyStatus::ReconsumeLater);
+                (messages, context, status)
+            })
+            .await;
+            drop(permit);
+            let (consumed, context, status) = match result {
+                Ok(result) => result,
+                Err(_e) => return,
+            };
+            // The queue is reassigned and its messages will be delivered to the new owner.
+            if pq.is_dropped() {
+                return;
+            }
+
+            let mut failed = vec![];
+            if status == ConsumeConcurrentlyStatus::ReconsumeLater {
+                for message in &consumed {
+                    if !self.is_clustering() {
+                        eprintln!(
+                            "Dropping message {} as broadcasting consumers never retry",
+                            message.msg_id
+                        );
+                    } else if let Err(e) = self
+                        .send_message_back(message, context.delay_level_when_next_consume)
+                        .await
+                    {
+                        eprintln!(
+                            "Failed to send back message {}. Cause: {}",
+                            message.msg_id, e
+                        );
+                        let mut message = message.clone();
+                        message.reconsume_times += 1;
+                        failed.push(message);
+                    }
+                }
+            }
+
+            let offsets: Vec<i64> = consumed
+                .iter()
+                .map(|message| message.queue_offset)
+                .filter(|offset| !failed.iter().any(|m| m.queue_offset == *offset))
+                .collect();
+            if let Some(offset) = pq.remove_messages(&offsets) {
+                self.advance_offset(&mq, &pq, offset);
+            }
+
+            if failed.is_empty() {
+                return;
+            }
+            messages = failed;
+            self.sleep(CONSUME_LATER_DELAY).await;
+        }
+    }
+
+    /// Yield messages through the stream, each carrying what it takes to acknowledge it.
+    fn deliver(
+        self: &Arc<Self>,
+        sender: &mpsc::UnboundedSender<ReceivedMessage>,
+        mq: &MessageQueue,
+        pq: &Arc<ProcessQueue>,
+        mut messages: Vec<MessageExt>,
+    ) {
+        let offsets: Vec<i64> = messages.iter().map(|m| m.queue_offset).collect();
+        pq.start_consuming(&offsets);
+        let start = current_millis().to_string();
+        for mut message in messages.drain(..) {
+            message
+                .properties
+                .insert(property::CONSUME_START_TIMESTAMP.to_owned(), start.clone());
+            let received = ReceivedMessage {
+                message,
+                acker: Some(Acker {
+                    inner: Arc::clone(self),
+                    mq: mq.clone(),
+                    pq: Arc::clone(pq),
+                }),
+            };
+            // The stream is dropped; its messages are redelivered once the consumer restarts.
+            if sender.send(received).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Mark a message yielded by the stream as done with, which may allow the offset of its queue to advance.
+    fn ack(&self, mq: &MessageQueue, pq: &ProcessQueue, offset: i64) {
+        // The queue is reassigned and its messages will be delivered to the new owner.
+        if pq.is_dropped() {
+            return;
+        }
+        if let Some(offset) = pq.remove_messages(&[offset]) {
+            self.offset_store.update_offset(mq, offset, true);
+        }
+    }
+
+    /// Send back a message yielded by the stream for retry, or yield it again later if the broker cannot be reached.
+    async fn nack(
+        self: Arc<Self>,
+        mq: MessageQueue,
+        pq: Arc<ProcessQueue>,
+        mut message: MessageExt,
+    ) {
+        if pq.is_dropped() || self.is_shutdown() {
+            return;
+        }
+        if !self.is_clustering() {
+            eprintln!(
+                "Dropping message {} as broadcasting consumers never retry",
+                message.msg_id
+            );
+        } else if let Err(e) = self.send_message_back(&message, 0).await {
+            eprintln!(
+                "Failed to send back message {}. Cause: {}",
+                message.msg_id, e
+            );
+            self.sleep(CONSUME_LATER_DELAY).await;
+            if let Ok(Some(Listener::Stream(sender))) = self.listener() {
+                if !pq.is_dropped() {
+                    message.reconsume_times += 1;
+                    self.deliver(&sender, &mq, &pq, vec![message]);
+                }
+            }
+            return;
+        }
+        self.ack(&mq, &pq, message.queue_offset);
+    }
+
+    /// Send back messages stuck in the concurrent listener beyond the consume timeout, so that they are redelivered
+    /// and no longer hold back offsets of their queues.
+    async fn clean_expired_messages(&self) {
+        let process_queues: Vec<QueueEntry> = match self.process_queues.lock() {
+            Ok(map) => map
+                .iter()
+                .map(|(mq, pq)| (mq.clone(), Arc::clone(pq)))
+                .collect(),
+            Err(_e) => return,
+        };
+        for (mq, pq) in process_queues {
+            for message in pq.expired_messages(self.option.consume_timeout) {
+                eprintln!(
+                    "Message {} of {:?} exceeded the consume timeout, sending it back",
+                    message.msg_id, mq
+                );
+                // Broadcasting consumers never retry, so the message is given up.
+                if self.is_clustering() {
+                    if let Err(e) = self.send_message_back(&message, TIMEOUT_DELAY_LEVEL).await {
+                        eprintln!(
+                            "Failed to send back expired message {}. Cause: {}",
+                            message.msg_id, e
+                        );
+                        continue;
+                    }
+                }
+                if let Some(offset) = pq.remove_messages(&[message.queue_offset]) {
+                    self.advance_offset(&mq, &pq, offset);
+                }
+            }
+        }
+    }
+
+    /// Send a message failed to consume back to its broker, which redelivers it through the retry topic of the group
+    /// after the delay of the given level, or moves it to the dead letter queue if it exhausted its retries or the
+    /// level is negative.
+    ///
+    /// If the broker refuses, republish the message to the retry topic instead.
+    async fn send_message_back(
+        &self,
+        message: &MessageExt,
+        delay_level: i32,
+    ) -> Result<(), ClientError> {
+        let result = match self.find_broker_addr(&message.broker_name).await {
+            Ok(addr) => {
+                let header = protocol::ConsumerSendMsgBackRequestHeader {
+                    offset: message.commit_log_offset,
+                    group: self.option.group.clone(),
+                    delay_level,
+                    origin_msg_id: message.msg_id.clone(),
+                    origin_topic: message.topic.clone(),
+                    max_reconsume_times: self.option.max_reconsume_times,
+                };
+                let frame = Frame::request(RequestCode::ConsumerSendMsgBack, header);
+                match self.invoke(&addr, frame).await {
+                    Ok(response) => response.ensure_success(),
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!(
+                "Failed to send back message {}, republishing it to the retry topic. Cause: {}",
+                message.msg_id, e
+            );
+            self.publisher
+                .publish_unchecked(&self.retry_message(message, delay_level))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Build the message republished to the retry topic of the group on behalf of a failed one.
+    fn retry_message(&self, message: &MessageExt, delay_level: i32) -> Message {
+        let mut retry = Message::new(
+            &message::retry_topic(&self.option.group),
+            message.body.clone(),
+        );
+        retry.tag = message.tag.clone();
+        retry.keys = message.keys.clone();
+        retry.attributes = message.attributes.clone();
+        retry.properties = message.properties.clone();
+        retry
+            .properties
+            .entry(property::RETRY_TOPIC.to_owned())
+            .or_insert_with(|| message.topic.clone());
+        retry
+            .properties
+            .entry(property::ORIGIN_MESSAGE_ID.to_owned())
+            .or_insert_with(|| message.msg_id.clone());
+        let reconsume_times = if delay_level < 0 {
+            // Exhausting retries makes broker move the message to the dead letter queue.
+            self.option
+                .max_reconsume_times
+                .max(message.reconsume_times + 1)
+        } else {
+            message.reconsume_times + 1
+        };
+        retry.properties.insert(
+            property::RECONSUME_TIME.to_owned(),
+            reconsume_times.to_string(),
+        );
+        retry.properties.insert(
+            property::MAX_RECONSUME_TIMES.to_owned(),
+            self.option.max_reconsume_times.to_string(),
+        );
+        retry.properties.insert(
+            property::DELAY_TIME_LEVEL.to_owned(),
+            (3 + message.reconsume_times).to_string(),
+        );
+        retry
+    }
+
+    /// Find the master address of the named broker among routes of subscribed topics, retry topic included.
+    async fn find_broker_addr(&self, broker_name: &str) -> Result<String, ClientError> {
+        for topic in self.topics() {
+            if let Ok(addr) = self.find_master(&topic, broker_name).await {
+                return Ok(addr);
+            }
+        }
+        Err(ClientError::BrokerNotFound(broker_name.to_owned()))
+    }
+
+    async fn peek_dead_letter_messages(
+        &self,
+        max_nums: i32,
+    ) -> Result<Vec<MessageExt>, ClientError> {
+        let topic = message::dlq_topic(&self.option.group);
+        let route = match self
+            .client
+            .routes
+            .query(
+                &topic,
+                &self.client.connections,
+                self.client.option.request_timeout,
+            )
+            .await
+        {
+            Ok(route) => route,
+            // Dead letter topics are only created once the first message exhausts its retries.
+            Err(ClientError::RouteNotFound(_)) => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut messages = vec![];
+        for queue_data in &route.queue_datas {
+            let addr = match route.master_addr(&queue_data.broker_name) {
+                Some(addr) => addr,
+                None => continue,
+            };
+            for queue_id in 0..queue_data.read_queue_nums {
+                let remaining = max_nums - messages.len() as i32;
+                if remaining <= 0 {
+                    return Ok(messages);
+                }
+                let mq = MessageQueue {
+                    topic: topic.clone(),
+                    broker_name: queue_data.broker_name.clone(),
+                    queue_id,
+                };
+                let min_offset = self.client.min_offset(&mq).await?;
+
+                let header = protocol::PullMessageRequestHeader {
+                    consumer_group: self.option.group.clone(),
+                    topic: topic.clone(),
+                    queue_id,
+                    queue_offset: min_offset,
+                    max_msg_nums: remaining,
+                    sys_flag: protocol::PULL_FLAG_SUBSCRIPTION,
+                    commit_offset: 0,
+                    suspend_timeout_millis: 0,
+                    subscription: Some(filter::SUB_ALL.to_owned()),
+                    sub_version: 0,
+                    expression_type: None,
+                };
+                let response = self
+                    .invoke(addr, Frame::request(RequestCode::PullMessage, header))
+                    .await?;
+                if response.code == ResponseCode::Success as i32 {
+                    let mut pulled = MessageExt::decode_batch(response.body())?;
+                    pulled
+                        .iter_mut()
+                        .for_each(|message| message.broker_name = queue_data.broker_name.clone());
+                    messages.extend(pulled);
+                }
+            }
+        }
+        Ok(messages)
+    }
+}
+
+/// Message yielded by `PushConsumer::stream`.
+///
+/// Dereference to access the message. Once consumed, acknowledge it by `ack`; otherwise it is sent back for retry,
+/// whether explicitly by `nack` or implicitly when dropped.
+pub struct ReceivedMessage {
+    message: MessageExt,
+    acker: Option<Acker>,
+}
+
+struct Acker {
+    inner: Arc<ConsumerInner>,
+    mq: MessageQueue,
+    pq: Arc<ProcessQueue>,
+}
+
+impl ReceivedMessage {
+    pub fn message_queue(&self) -> Option<&MessageQueue> {
+        self.acker.as_ref().map(|acker| &acker.mq)
+    }
+
+    /// Acknowledge that the message is consumed.
+    pub fn ack(mut self) {
+        if let Some(acker) = self.acker.take() {
+            acker
+                .inner
+                .ack(&acker.mq, &acker.pq, self.message.queue_offset);
+        }
+    }
+
+    /// Give up the message, which is redelivered through the retry topic of the group after a delay decided by
+    /// broker. Broadcasting consumers never retry, so the message is dropped.
+    pub fn nack(mut self) {
+        self.send_back();
+    }
+
+    fn send_back(&mut self) {
+        if let Some(acker) = self.acker.take() {
+            // Outside a runtime, the message is redelivered once its queue is reassigned or pulled again.
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                handle.spawn(acker.inner.nack(acker.mq, acker.pq, self.message.clone()));
+            }
+        }
+    }
+}
+
+impl Deref for ReceivedMessage {
+    type Target = MessageExt;
+
+    fn deref(&self) -> &MessageExt {
+        &self.message
+    }
+}
+
+impl Drop for ReceivedMessage {
+    fn drop(&mut self) {
+        self.send_back();
+    }
+}
+
+struct MessageStream {
+    receiver: mpsc::UnboundedReceiver<ReceivedMessage>,
+    error: Option<ClientError>,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl Stream for MessageStream {
+    type Item = Result<ReceivedMessage, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(e) = self.error.take() {
+            return Poll::Ready(Some(Err(e)));
+        }
+        // Messages still buffered belong to dropped queues and are redelivered once the consumer restarts.
+        if *self.shutdown.borrow() {
+            return Poll::Ready(None);
+        }
+        self.receiver.poll_recv(cx).map(|received| received.map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection;
+    use crate::message::tests::encode_stored;
+    use tokio::sync::mpsc;
+
+    /// Serve as `connection::tests::mock_cluster` does, answering pulls of the two queues of topic `T1`, which hold
+    /// one message each, and offset queries of a group that has committed none.
+    ///
+    /// Requests not handled here are answered by `respond`.
+    async fn mock_pull_cluster<F>(requests: Arc<Mutex<Vec<Frame>>>, respond: F) -> String
+    where
+        F: Fn(&Frame, &mut Frame) + Send + Sync + 'static,
+    {
+        connection::tests::mock_cluster(2, requests, move |request, response| {
+            match request.code {
+                14 => response.code = ResponseCode::QueryNotFound as i32,
+                30 => response.put_ext_field("offset", "0"),
+                11 => {
+                    let offset: i64 = request.ext_fields["queueOffset"].parse().unwrap();
+                    if offset > 0 || request.ext_fields["topic"] != "T1" {
+                        // Hold further pulls, as brokers do when no message is available.
+                        return false;
+                    }
+                    let queue_id = &request.ext_fields["queueId"];
+                    response.body = encode_stored("T1", 0, &[("TAGS", queue_id)], b"body");
+                    response.put_ext_field("suggestWhichBrokerId", "0");
+                    response.put_ext_field("nextBeginOffset", "1");
+                    response.put_ext_field("minOffset", "0");
+                    response.put_ext_field("maxOffset", "1");
+                }
+                _ => respond(request, response),
+            }
+            true
+        })
+        .await
+    }
+
+    /// Serve as `mock_pull_cluster` does for a group whose only member is the client that sent the last heartbeat.
+    async fn mock_group_cluster<F>(requests: Arc<Mutex<Vec<Frame>>>, respond: F) -> String
+    where
+        F: Fn(&Frame, &mut Frame) + Send + Sync + 'static,
+    {
+        let client_id = Mutex::new(serde_json::Value::Null);
+        mock_pull_cluster(requests, move |request, response| {
+            match request.code {
+                34 => {
+                    let heartbeat: serde_json::Value =
+                        serde_json::from_slice(&request.body).unwrap();
+                    *client_id.lock().unwrap() = heartbeat["clientID"].clone();
+                }
+                38 => {
+                    let body =
+                        serde_json::json!({ "consumerIdList": [*client_id.lock().unwrap()] });
+                    response.body = bytes::Bytes::from(body.to_string());
+                }
+                _ => {}
+            }
+            respond(request, response);
+        })
+        .await
+    }
+
+    /// Wait for the offset of the queue to be updated in memory, for up to 5 seconds.
+    async fn wait_for_offset(
+        consumer: &PushConsumer,
+        mq: &MessageQueue,
+    ) -> Result<Option<i64>, ClientError> {
+        for _ in 0..50 {
+            let offset = consumer
+                .inner
+                .offset_store
+                .read_offset(mq, ReadOffsetType::ReadFromMemory)
+                .await?;
+            if offset.is_some() {
+                return Ok(offset);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Ok(None)
+    }
+
+    #[tokio::test]
+    async fn test_broadcasting_consumes_all_queues() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let name_server = mock_pull_cluster(Arc::clone(&requests), |_, _| {}).await;
+        let client_option = ClientOption {
+            name_server,
+            ..Default::default()
+        };
+        let offset_store_dir =
+            std::env::temp_dir().join(format!("broadcasting-{}", std::process::id()));
+        let option = ConsumerOption {
+            message_model: MessageModel::Broadcasting,
+            offset_store_dir: offset_store_dir.clone(),
+            ..Default::default()
+        };
+        let consumer = PushConsumer::new(client_option, option)?;
+        consumer.subscribe("T1", "*")?;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        consumer.register_message_listener_orderly(
+            move |messages: &[MessageExt], _context: &mut ConsumeOrderlyContext| {
+                for message in messages {
+                    tx.send(message.tag.clone()).unwrap();
+                }
+                ConsumeOrderlyStatus::Success
+            },
+        )?;
+        // Offsets cannot be loaded while a file stands in place of their directory, but starting may be retried.
+        let _ = std::fs::remove_dir_all(&offset_store_dir);
+        std::fs::write(&offset_store_dir, b"").unwrap();
+        assert!(matches!(
+            consumer.start().await,
+            Err(ClientError::OffsetStore(_))
+        ));
+        std::fs::remove_file(&offset_store_dir).unwrap();
+        consumer.start().await?;
+
+        let mut tags = vec![];
+        for _ in 0..2 {
+            let tag = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+            tags.push(tag.unwrap().unwrap());
+        }
+        tags.sort();
+        assert_eq!(tags, vec!["0".to_owned(), "1".to_owned()]);
+        consumer.shutdown().await;
+        assert!(matches!(
+            consumer.start().await,
+            Err(ClientError::IllegalState(_))
+        ));
+
+        let requests = requests.lock().unwrap();
+        // Neither group membership nor queue locks are involved.
+        assert!(!requests.iter().any(|r| r.code == 38 || r.code == 41));
+        let heartbeat = requests.iter().find(|r| r.code == 34).unwrap();
+        let heartbeat = String::from_utf8_lossy(&heartbeat.body);
+        assert!(heartbeat.contains(r#""messageModel":"BROADCASTING""#));
+        let _ = std::fs::remove_dir_all(&offset_store_dir);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reconsume_later_sends_message_back() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let name_server = mock_group_cluster(Arc::clone(&requests), |request, response| {
+            match request.code {
+                // Refuse sending back so that messages are republished to the retry topic.
+                36 => {
+                    response.code = 1;
+                    response.remark = "store busy".to_owned();
+                }
+                10 => {
+                    response.put_ext_field("msgId", "0A00000100002A9F0000000000000407");
+                    response.put_ext_field("queueId", &request.ext_fields["queueId"]);
+                    response.put_ext_field("queueOffset", "0");
+                }
+                _ => {}
+            }
+        })
+        .await;
+        let client_option = ClientOption {
+            name_server,
+            ..Default::default()
+        };
+        let option = ConsumerOption {
+            group: "G1".to_owned(),
+            ..Default::default()
+        };
+        let consumer = PushConsumer::new(client_option, option)?;
+        consumer.subscribe("T1", "*")?;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        consumer.register_message_listener_concurrently(
+            move |messages: &[MessageExt], _context: &mut ConsumeConcurrentlyContext| {
+                tx.send(messages.len()).unwrap();
+                ConsumeConcurrentlyStatus::ReconsumeLater
+            },
+        )?;
+        consumer.start().await?;
+
+        for _ in 0..2 {
+            let delivered = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+            assert_eq!(delivered.unwrap(), Some(1));
+        }
+        // Messages republished to the retry topic are done with, so the offset of each queue advances.
+        for queue_id in 0..2 {
+            let mq = MessageQueue {
+                topic: "T1".to_owned(),
+                broker_name: "b1".to_owned(),
+                queue_id,
+            };
+            assert_eq!(wait_for_offset(&consumer, &mq).await?, Some(1));
+        }
+        consumer.shutdown().await;
+
+        let requests = requests.lock().unwrap();
+        let heartbeat = requests.iter().find(|r| r.code == 34).unwrap();
+        assert!(String::from_utf8_lossy(&heartbeat.body).contains("%RETRY%G1"));
+        assert!(!requests.iter().any(|r| r.code == 41));
+        let send_back = requests.iter().find(|r| r.code == 36).unwrap();
+        assert_eq!(send_back.ext_fields["group"], "G1");
+        assert_eq!(send_back.ext_fields["originTopic"], "T1");
+        assert_eq!(send_back.ext_fields["maxReconsumeTimes"], "16");
+        let republished: Vec<&Frame> = requests.iter().filter(|r| r.code == 10).collect();
+        assert_eq!(republished.len(), 2);
+        assert_eq!(republished[0].ext_fields["topic"], "%RETRY%G1");
+        assert_eq!(republished[0].ext_fields["reconsumeTimes"], "1");
+        let properties = message::decode_properties(&republished[0].ext_fields["properties"]);
+        assert_eq!(properties.get("RETRY_TOPIC"), Some(&"T1".to_owned()));
+        assert_eq!(properties.get("DELAY"), Some(&"3".to_owned()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tag_filter() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let name_server = mock_group_cluster(Arc::clone(&requests), |_, _| {}).await;
+        let client_option = ClientOption {
+            name_server,
+            ..Default::default()
+        };
+        let consumer = PushConsumer::new(client_option, ConsumerOption::default())?;
+        consumer.subscribe("T1", "0")?;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        consumer.register_message_listener_concurrently(
+            move |messages: &[MessageExt], _context: &mut ConsumeConcurrentlyContext| {
+                for message in messages {
+                    tx.send(message.tag.clone()).unwrap();
+                }
+                ConsumeConcurrentlyStatus::ConsumeSuccess
+            },
+        )?;
+        consumer.start().await?;
+
+        let tag = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+        assert_eq!(tag.unwrap(), Some("0".to_owned()));
+        // The message of queue 1 is filtered out, yet the offset of its queue advances.
+        let mq = MessageQueue {
+            topic: "T1".to_owned(),
+            broker_name: "b1".to_owned(),
+            queue_id: 1,
+        };
+        assert_eq!(wait_for_offset(&consumer, &mq).await?, Some(1));
+        assert!(rx.try_recv().is_err());
+
+        consumer.subscribe("T1", "0 || 1")?;
+        let mut updated = false;
+        for _ in 0..50 {
+            updated = requests.lock().unwrap().iter().any(|r| {
+                r.code == 34 && String::from_utf8_lossy(&r.body).contains(r#""subString":"0 || 1""#)
+            });
+            if updated {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(updated);
+        consumer.shutdown().await;
+
+        let requests = requests.lock().unwrap();
+        let pull = requests
+            .iter()
+            .find(|r| r.code == 11 && r.ext_fields["topic"] == "T1")
+            .unwrap();
+        assert_eq!(pull.ext_fields["subscription"], "0");
+        assert_eq!(pull.ext_fields["expressionType"], "TAG");
+        let heartbeat =
+            String::from_utf8_lossy(&requests.iter().find(|r| r.code == 34).unwrap().body)
+                .into_owned();
+        assert!(heartbeat.contains(r#""tagsSet":["0"],"codeSet":[48]"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream() -> Result<(), ClientError> {
+        use futures::StreamExt;
+
+        let requests = Arc::new(Mutex::new(vec![]));
+        let name_server = mock_group_cluster(Arc::clone(&requests), |_, _| {}).await;
+        let client_option = ClientOption {
+            name_server,
+            ..Default::default()
+        };
+        let option = ConsumerOption {
+            group: "G1".to_owned(),
+            ..Default::default()
+        };
+        let consumer = PushConsumer::new(client_option, option)?;
+        consumer.subscribe("T1", "*")?;
+        let mut stream = Box::pin(consumer.stream());
+        let mut duplicate = Box::pin(consumer.stream());
+        assert!(matches!(
+            duplicate.next().await,
+            Some(Err(ClientError::IllegalState(_)))
+        ));
+        assert!(duplicate.next().await.is_none());
+        consumer.start().await?;
+
+        for _ in 0..2 {
+            let received = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+            let received = received.unwrap().unwrap()?;
+            // Acknowledge messages of queue 0 and drop those of queue 1 without acknowledging.
+            if received.tag == "0" {
+                received.ack();
+            }
+        }
+        let mq = |queue_id| MessageQueue {
+            topic: "T1".to_owned(),
+            broker_name: "b1".to_owned(),
+            queue_id,
+        };
+        let mut send_back = None;
+        for _ in 0..50 {
+            send_back = requests
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|r| r.code == 36)
+                .map(|r| r.ext_fields.clone());
+            if send_back.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let send_back = send_back.unwrap();
+        assert_eq!(send_back["delayLevel"], "0");
+        assert_eq!(send_back["offset"], "1024");
+        // Messages sent back are done with, so the offset of each queue advances.
+        for queue_id in 0..2 {
+            assert_eq!(wait_for_offset(&consumer, &mq(queue_id)).await?, Some(1));
+        }
+
+        consumer.shutdown().await;
+        let end = tokio::time::timeout(Duration::from_secs(5), stream.next()).await;
+        assert!(end.unwrap().is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_consume_from_timestamp_and_reset_offset() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let name_server = mock_pull_cluster(Arc::clone(&requests), |request, response| {
+            if request.code == 29 {
+                // Only messages stored since the epoch precede the requested time.
+                let offset = if request.ext_fields["timestamp"] == "0" {
+                    "0"
+                } else {
+                    "1"
+                };
+                response.put_ext_field("offset", offset);
+            }
+        })
+        .await;
+        let client_option = ClientOption {
+            name_server,
+            ..Default::default()
+        };
+        let offset_store_dir =
+            std::env::temp_dir().join(format!("consume-from-timestamp-{}", std::process::id()));
+        let option = ConsumerOption {
+            message_model: MessageModel::Broadcasting,
+            consume_from_where: ConsumeFromWhere::Timestamp(SystemTime::now()),
+            offset_store_dir: offset_store_dir.clone(),
+            ..Default::default()
+        };
+        let consumer = PushConsumer::new(client_option, option)?;
+        consumer.subscribe("T1", "*")?;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        consumer.register_message_listener_concurrently(
+            move |messages: &[MessageExt], _context: &mut ConsumeConcurrentlyContext| {
+                for message in messages {
+                    tx.send(message.tag.clone()).unwrap();
+                }
+                ConsumeConcurrentlyStatus::ConsumeSuccess
+            },
+        )?;
+        consumer.start().await?;
+
+        // Both queues start past their only message.
+        let received = tokio::time::timeout(Duration::from_millis(500), rx.recv()).await;
+        assert!(received.is_err());
+        let heartbeat = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|r| r.code == 34)
+            .map(|r| String::from_utf8_lossy(&r.body).to_string())
+            .unwrap();
+        assert!(heartbeat.contains(r#""consumeFromWhere":"CONSUME_FROM_TIMESTAMP""#));
+
+        consumer.reset_offset_by_timestamp("T1", UNIX_EPOCH).await?;
+        let mut tags = vec![];
+        for _ in 0..2 {
+            let tag = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+            tags.push(tag.unwrap().unwrap());
+        }
+        tags.sort();
+        assert_eq!(tags, vec!["0".to_owned(), "1".to_owned()]);
+        consumer.shutdown().await;
+        let _ = std::fs::remove_dir_all(&offset_store_dir);
+        Ok(())
+    }
+
+    /// Start a clustering consumer on the mock cluster with a concurrent listener that blocks until released.
+    async fn start_blocked_consumer(
+        requests: Arc<Mutex<Vec<Frame>>>,
+        option: ConsumerOption,
+    ) -> Result<(PushConsumer, std::sync::mpsc::Sender<()>), ClientError> {
+        let name_server = mock_group_cluster(requests, |_, _| {}).await;
+        let client_option = ClientOption {
+            name_server,
+            ..Default::default()
+        };
+        let consumer = PushConsumer::new(client_option, option)?;
+        consumer.subscribe("T1", "*")?;
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        consumer.register_message_listener_concurrently(
+            move |_messages: &[MessageExt], _context: &mut ConsumeConcurrentlyContext| {
+                let _ = released
+                    .lock()
+                    .unwrap()
+                    .recv_timeout(Duration::from_secs(5));
+                ConsumeConcurrentlyStatus::ConsumeSuccess
+            },
+        )?;
+        consumer.start().await?;
+        Ok((consumer, release))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_flow_control() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let option = ConsumerOption {
+            pull_threshold_for_queue: 0,
+            ..Default::default()
+        };
+        let (consumer, release) = start_blocked_consumer(Arc::clone(&requests), option).await?;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let stats: Vec<ProcessQueueStats> = consumer
+            .process_queue_stats()
+            .into_iter()
+            .filter(|(mq, _)| mq.topic == "T1")
+            .map(|(_, stats)| stats)
+            .collect();
+        assert_eq!(stats.len(), 2);
+        for stats in stats {
+            assert_eq!(stats.cached_msg_count, 1);
+            assert_eq!(stats.cached_msg_size, 4);
+            assert!(stats.flow_control_times > 0);
+        }
+        // The queue caching a message is not pulled again.
+        let pulls = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.code == 11 && r.ext_fields["topic"] == "T1")
+            .count();
+        assert_eq!(pulls, 2);
+
+        drop(release);
+        consumer.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_consume_timeout() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let option = ConsumerOption {
+            consume_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let (consumer, release) = start_blocked_consumer(Arc::clone(&requests), option).await?;
+
+        let mut sent_back = 0;
+        for _ in 0..50 {
+            sent_back = requests
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|r| r.code == 36 && r.ext_fields["delayLevel"] == "3")
+                .count();
+            if sent_back == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(sent_back, 2);
+        // Messages sent back no longer hold back offsets, although the listener is still stuck.
+        for stats in consumer.process_queue_stats().values() {
+            assert_eq!(stats.cached_msg_count, 0);
+        }
+
+        drop(release);
+        consumer.shutdown().await;
+        Ok(())
+    }
+}
@@ -18,6 +18,79 @@ pub enum ClientError {
     #[error("Invalid frame `{0}`")]
     InvalidFrame(String),
 
+    #[error("Timeout when waiting for response of request `{0}`")]
+    RequestTimeout(i32),
+
+    #[error("Server responded with code `{code}`: {remark}")]
+    ServerError { code: i32, remark: String },
+
+    #[error("No route is available for topic `{0}`")]
+    RouteNotFound(String),
+
+    #[error("No master address is available for broker `{0}`")]
+    BrokerNotFound(String),
+
+    #[error("Invalid subscription expression `{expression}`: {reason}")]
+    InvalidExpression { expression: String, reason: String },
+
+    #[error("Offset {offset} is out of range [{min}, {max}] of the queue")]
+    OffsetOutOfRange { offset: i64, min: i64, max: i64 },
+
+    #[error("Failed to read or write offsets: {0}")]
+    OffsetStore(String),
+
+    #[error("Invalid receipt handle `{0}`")]
+    InvalidReceiptHandle(String),
+
+    #[error(
+        "Illegal topic `{0}`: it must be non-empty and made of letters, digits and `%|_-` only"
+    )]
+    IllegalTopic(String),
+
+    #[error("Topic `{topic}` is longer than {max} characters")]
+    TopicTooLong { topic: String, max: usize },
+
+    #[error(
+        "Illegal group `{0}`: it must be non-empty and made of letters, digits and `%|_-` only"
+    )]
+    IllegalGroup(String),
+
+    #[error("Group `{group}` is longer than {max} characters")]
+    GroupTooLong { group: String, max: usize },
+
+    #[error("Topic `{0}` is reserved by the system")]
+    ReservedTopic(String),
+
+    #[error("Message body is empty")]
+    EmptyBody,
+
+    #[error("Message of {size} bytes exceeds the maximum of {max} bytes")]
+    MessageTooLarge { size: usize, max: usize },
+
+    #[error("Invalid message delay: {0}")]
+    InvalidDelay(String),
+
+    #[error("Invalid message batch: {0}")]
+    InvalidBatch(String),
+
+    #[error("Failed to compress or decompress message body: {0}")]
+    Compression(String),
+
+    #[error("Message `{0}` is not a request to reply to")]
+    NotRequest(String),
+
+    #[error("No reply to request `{0}` arrived in time")]
+    ReplyTimeout(String),
+
+    #[error("Invalid message id `{0}`")]
+    InvalidMessageId(String),
+
+    #[error("Cluster `{0}` has no broker")]
+    ClusterNotFound(String),
+
+    #[error("Illegal client state: {0}")]
+    IllegalState(String),
+
     #[error("unknown data store error")]
     Unknown,
 }
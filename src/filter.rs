@@ -0,0 +1,173 @@
+//!
+//! Parse subscription expressions and filter pulled messages against them.
+//!
+use crate::error::ClientError;
+use crate::message::MessageExt;
+use crate::protocol::SubscriptionData;
+use crate::sql92;
+use std::sync::Arc;
+
+/// Expression subscribing all messages of a topic.
+pub(crate) const SUB_ALL: &str = "*";
+
+const TAG_SEPARATOR: &str = "||";
+
+/// Language of a subscription expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpressionType {
+    /// Tags joined by `||`, such as `TagA || TagB`, or `*` for all messages.
+    Tag,
+
+    /// SQL92 conditions over message properties, such as `region = 'eu' AND amount > 100`. Brokers must enable
+    /// property filtering to accept such subscriptions.
+    Sql92,
+}
+
+impl ExpressionType {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ExpressionType::Tag => "TAG",
+            ExpressionType::Sql92 => "SQL92",
+        }
+    }
+}
+
+/// Select messages of a subscribed topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageSelector {
+    pub expression_type: ExpressionType,
+    pub expression: String,
+}
+
+impl MessageSelector {
+    pub fn by_tag(expression: &str) -> Self {
+        Self {
+            expression_type: ExpressionType::Tag,
+            expression: expression.to_owned(),
+        }
+    }
+
+    pub fn by_sql(expression: &str) -> Self {
+        Self {
+            expression_type: ExpressionType::Sql92,
+            expression: expression.to_owned(),
+        }
+    }
+}
+
+/// Build subscription data from a message selector.
+///
+/// # Errors
+/// Raise ClientError::InvalidExpression if a tag expression names no tag or a SQL92 expression is malformed.
+pub(crate) fn build_subscription(
+    topic: &str,
+    selector: &MessageSelector,
+    sub_version: i64,
+) -> Result<SubscriptionData, ClientError> {
+    let expression = selector.expression.trim();
+    let mut subscription = SubscriptionData {
+        class_filter_mode: false,
+        topic: topic.to_owned(),
+        sub_string: SUB_ALL.to_owned(),
+        tags_set: vec![],
+        code_set: vec![],
+        sub_version,
+        expression_type: selector.expression_type.as_str().to_owned(),
+        sql: None,
+    };
+    if selector.expression_type == ExpressionType::Sql92 {
+        subscription.sql = Some(Arc::new(sql92::Expression::parse(expression)?));
+        subscription.sub_string = expression.to_owned();
+        return Ok(subscription);
+    }
+
+    if expression.is_empty() || expression == SUB_ALL {
+        return Ok(subscription);
+    }
+
+    for tag in expression.split(TAG_SEPARATOR).map(str::trim) {
+        if tag.is_empty() || subscription.tags_set.iter().any(|t| t == tag) {
+            continue;
+        }
+        subscription.tags_set.push(tag.to_owned());
+        subscription.code_set.push(java_hash_code(tag));
+    }
+    if subscription.tags_set.is_empty() {
+        return Err(ClientError::InvalidExpression {
+            expression: expression.to_owned(),
+            reason: "no tag is specified".to_owned(),
+        });
+    }
+    subscription.sub_string = expression.to_owned();
+    Ok(subscription)
+}
+
+/// Whether the message matches the subscription.
+///
+/// Brokers filter by tag hash codes only, so messages whose tags collide with subscribed ones are re-checked here.
+pub(crate) fn is_matched(subscription: &SubscriptionData, message: &MessageExt) -> bool {
+    match &subscription.sql {
+        Some(sql) => sql.evaluate(message),
+        None => subscription.tags_set.is_empty() || subscription.tags_set.contains(&message.tag),
+    }
+}
+
+/// Hash code of a string as computed by `java.lang.String#hashCode`, over its UTF-16 code units.
+pub(crate) fn java_hash_code(s: &str) -> i32 {
+    s.encode_utf16()
+        .fold(0i32, |hash, c| hash.wrapping_mul(31).wrapping_add(c as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::tests::encode_stored;
+
+    #[test]
+    fn test_java_hash_code() {
+        assert_eq!(java_hash_code(""), 0);
+        assert_eq!(java_hash_code("TagA"), 2598919);
+        assert_eq!(java_hash_code("hello world"), 1794106052);
+        assert_eq!(java_hash_code("标签"), 857175);
+    }
+
+    #[test]
+    fn test_build_subscription() -> Result<(), ClientError> {
+        let subscription = build_subscription("T1", &MessageSelector::by_tag(" * "), 1)?;
+        assert_eq!(subscription.sub_string, "*");
+        assert!(subscription.tags_set.is_empty());
+
+        let subscription =
+            build_subscription("T1", &MessageSelector::by_tag("TagA || TagB||TagA"), 1)?;
+        assert_eq!(subscription.tags_set, vec!["TagA", "TagB"]);
+        assert_eq!(subscription.code_set, vec![2598919, 2598920]);
+
+        assert!(build_subscription("T1", &MessageSelector::by_tag("||"), 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_matched() -> Result<(), ClientError> {
+        let data = encode_stored("T1", 0, &[("TAGS", "TagB")], b"body");
+        let message = MessageExt::decode_batch(data)?.remove(0);
+        assert!(is_matched(
+            &build_subscription("T1", &MessageSelector::by_tag("*"), 1)?,
+            &message
+        ));
+        assert!(is_matched(
+            &build_subscription("T1", &MessageSelector::by_tag("TagA || TagB"), 1)?,
+            &message
+        ));
+        assert!(!is_matched(
+            &build_subscription("T1", &MessageSelector::by_tag("TagA"), 1)?,
+            &message
+        ));
+
+        let sql = build_subscription("T1", &MessageSelector::by_sql("TAGS = 'TagB'"), 1)?;
+        assert_eq!(sql.expression_type, "SQL92");
+        assert!(sql.tags_set.is_empty());
+        assert!(is_matched(&sql, &message));
+        assert!(build_subscription("T1", &MessageSelector::by_sql("TAGS ="), 1).is_err());
+        Ok(())
+    }
+}
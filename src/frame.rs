@@ -2,6 +2,7 @@
 //! Implement the classic length field based frame codec. Note specific frame are defined in the protocol module.
 //!
 use bytes::{self, Buf, BufMut, Bytes, BytesMut};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::default::Default;
@@ -10,22 +11,68 @@ use std::sync::atomic::{self, Ordering};
 
 use crate::error::{self, ClientError};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
 pub(crate) enum Language {
     JAVA,
     CPP,
+    #[default]
     RUST,
 }
 
-impl Default for Language {
-    fn default() -> Self {
-        Language::RUST
-    }
-}
-
 pub(crate) enum RequestCode {
-    GetRouteInfoByTopic = 105,
     SendMessage = 10,
+    PullMessage = 11,
+    QueryMessage = 12,
+    QueryConsumerOffset = 14,
+    UpdateConsumerOffset = 15,
+    UpdateAndCreateTopic = 17,
+    UpdateBrokerConfig = 25,
+    GetBrokerConfig = 26,
+    GetBrokerRuntimeInfo = 28,
+    SearchOffsetByTimestamp = 29,
+    GetMaxOffset = 30,
+    GetMinOffset = 31,
+    ViewMessageById = 33,
+    HeartBeat = 34,
+    UnregisterClient = 35,
+    ConsumerSendMsgBack = 36,
+    GetConsumerListByGroup = 38,
+    LockBatchMq = 41,
+    UnlockBatchMq = 42,
+    GetRouteInfoByTopic = 105,
+    GetBrokerClusterInfo = 106,
+    UpdateAndCreateSubscriptionGroup = 200,
+    GetAllSubscriptionGroupConfig = 201,
+    GetConsumerConnectionList = 203,
+    GetAllTopicListFromNameServer = 206,
+    DeleteSubscriptionGroup = 207,
+    GetConsumeStats = 208,
+    DeleteTopicInBroker = 215,
+    DeleteTopicInNamesrv = 216,
+    ResetConsumerClientOffset = 220,
+    SendBatchMessage = 320,
+    SendReplyMessage = 324,
+    PushReplyMessageToClient = 326,
+    PopMessage = 200050,
+    AckMessage = 200051,
+    ChangeMessageInvisibleTime = 200053,
+}
+
+pub(crate) enum ResponseCode {
+    Success = 0,
+    SystemError = 1,
+    RequestCodeNotSupported = 3,
+    FlushDiskTimeout = 10,
+    SlaveNotAvailable = 11,
+    FlushSlaveTimeout = 12,
+    TopicNotExist = 17,
+    PullNotFound = 19,
+    PullRetryImmediately = 20,
+    PullOffsetMoved = 21,
+    QueryNotFound = 22,
+    PollingFull = 209,
+    PollingTimeout = 210,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
@@ -89,29 +136,36 @@ impl Frame {
     pub(crate) fn check(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
         // frame-length = 4 + len(header) + len(body)
         // frame-layout |header-length|---header-data---|---body---|
-        let frame_length = Frame::read_i32(src)? as usize;
+        let invalid = |reason: &str| Error::Other(ClientError::InvalidFrame(reason.to_owned()));
+        let frame_length = Frame::read_i32(src)?;
+        if frame_length < 4 {
+            return Err(invalid("Invalid frame length"));
+        }
 
-        if src.remaining() < frame_length {
+        if src.remaining() < frame_length as usize {
             return Err(Error::Incomplete);
         }
 
-        src.advance(frame_length);
+        // Header length must fit in the frame, or parsing it would run past the frame.
+        let header_length = Frame::read_i32(src)?;
+        if header_length < 0 || header_length > frame_length - 4 {
+            return Err(invalid("Invalid frame header length"));
+        }
+
+        src.advance(frame_length as usize - 4);
 
         Ok(())
     }
 
     pub(crate) fn parse(src: &mut Cursor<&[u8]>) -> Result<Option<Self>, ClientError> {
-        let frame_length = Frame::read_i32(src).map_err(|_e| {
-            return ClientError::InvalidFrame("Invalid frame length".to_string());
-        })?;
-        let header_length = Frame::read_i32(src).map_err(|_e| {
-            return ClientError::InvalidFrame("Invalid frame header length".to_string());
-        })?;
+        let frame_length = Frame::read_i32(src)
+            .map_err(|_e| ClientError::InvalidFrame("Invalid frame length".to_string()))?;
+        let header_length = Frame::read_i32(src)
+            .map_err(|_e| ClientError::InvalidFrame("Invalid frame header length".to_string()))?;
 
         let header = src.copy_to_bytes(header_length as usize);
-        let mut frame: Frame = serde_json::from_reader(header.reader()).map_err(|_e| {
-            return ClientError::InvalidFrame("Invalid frame header JSON".to_string());
-        })?;
+        let mut frame: Frame = serde_json::from_reader(header.reader())
+            .map_err(|_e| ClientError::InvalidFrame("Invalid frame header JSON".to_string()))?;
 
         let body_length = frame_length - 4 - header_length;
         if body_length > 0 {
@@ -130,7 +184,7 @@ impl Frame {
 
     pub(crate) fn encode(&self) -> Result<Option<Bytes>, ClientError> {
         let header = serde_json::to_vec(self).map_err(|_e| {
-            return ClientError::InvalidFrame("Failed to JSON serialize frame header".to_string());
+            ClientError::InvalidFrame("Failed to JSON serialize frame header".to_string())
         })?;
         let len = 4 + header.len() + self.body.len();
         let mut buf = BytesMut::with_capacity(len);
@@ -160,6 +214,14 @@ impl Frame {
         self.flag |= 1;
     }
 
+    pub(crate) fn mark_oneway(&mut self) {
+        self.flag |= 1 << 1;
+    }
+
+    pub(crate) fn is_oneway(&self) -> bool {
+        self.flag & (1 << 1) != 0
+    }
+
     pub(crate) fn add_ext_headers(&mut self, header: impl Into<HashMap<String, String>>) {
         let map: HashMap<String, String> = header.into();
         map.iter().for_each(|(k, v)| {
@@ -170,18 +232,148 @@ impl Frame {
     pub(crate) fn body(&self) -> bytes::Bytes {
         self.body.clone()
     }
+
+    /// Build a request frame of the given code, carrying the given custom header.
+    pub(crate) fn request(code: RequestCode, header: impl Into<HashMap<String, String>>) -> Self {
+        let mut frame = Frame::new();
+        frame.code = code as i32;
+        frame.add_ext_headers(header);
+        frame
+    }
+
+    pub(crate) fn set_json_body<T: Serialize>(&mut self, body: &T) -> Result<(), ClientError> {
+        let data = serde_json::to_vec(body).map_err(|_e| {
+            ClientError::InvalidFrame("Failed to JSON serialize frame body".to_string())
+        })?;
+        self.body = Bytes::from(data);
+        Ok(())
+    }
+
+    pub(crate) fn json_body<T: DeserializeOwned>(&self) -> Result<T, ClientError> {
+        let json = normalize_json(&String::from_utf8_lossy(&self.body));
+        serde_json::from_str(&json)
+            .map_err(|_e| ClientError::InvalidFrame("Frame body is invalid JSON".to_string()))
+    }
+
+    /// Turn a response frame carrying a non-success code into an error.
+    pub(crate) fn ensure_success(&self) -> Result<(), ClientError> {
+        if self.code == ResponseCode::Success as i32 {
+            return Ok(());
+        }
+        Err(ClientError::ServerError {
+            code: self.code,
+            remark: self.remark().to_owned(),
+        })
+    }
 }
 
+/// Rewrite JSON produced by fastjson, which Java servers serialize bodies with, into standard JSON. Fastjson leaves
+/// keys of maps keyed by numbers or objects unquoted, as in `{0:"addr"}` or `{{"topic":"T1","queueId":0}:5}`;
+/// such keys are turned into strings. Standard JSON is returned unchanged.
+fn normalize_json(json: &str) -> String {
+    // For each enclosing container: whether it is an object, and whether it expects a key next.
+    let mut containers: Vec<(bool, bool)> = vec![];
+    let mut out = String::with_capacity(json.len());
+    let chars: Vec<char> = json.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let at_key = containers.last() == Some(&(true, true));
+        let end = match c {
+            '"' => string_end(&chars, i),
+            '{' if at_key => object_end(&chars, i),
+            '-' | '0'..='9' if at_key => (i..chars.len())
+                .find(|&j| !matches!(chars[j], '-' | '.' | '0'..='9'))
+                .unwrap_or(chars.len()),
+            _ => i + 1,
+        };
+        if at_key && matches!(c, '{' | '-' | '0'..='9') {
+            let key: String = chars[i..end].iter().collect();
+            out.push_str(&serde_json::to_string(&key).unwrap_or_default());
+            i = end;
+            continue;
+        }
+        match c {
+            '{' => containers.push((true, true)),
+            '[' => containers.push((false, false)),
+            '}' | ']' => {
+                containers.pop();
+            }
+            ',' | ':' => {
+                if let Some((true, expect_key)) = containers.last_mut() {
+                    *expect_key = c == ',';
+                }
+            }
+            _ => {}
+        }
+        out.extend(&chars[i..end]);
+        i = end;
+    }
+    out
+}
+
+/// Index one past the closing quote of the string starting at `start`.
+fn string_end(chars: &[char], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+/// Index one past the brace closing the object starting at `start`.
+fn object_end(chars: &[char], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '"' => {
+                i = string_end(chars, i);
+                continue;
+            }
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+#[cfg(test)]
 mod tests {
     use bytes::{Buf, BufMut, BytesMut};
 
-    use super::{Frame, Language, Type};
+    use super::{normalize_json, Error, Frame, Language, Type};
+
+    #[test]
+    fn test_normalize_json() {
+        let standard = r#"{"a":[1,{"b":"{x}:1"}],"c":{"0":"addr"}}"#;
+        assert_eq!(normalize_json(standard), standard);
+        assert_eq!(
+            normalize_json(r#"{"brokerAddrs":{0:"10.0.0.1:10911",1:"10.0.0.2:10911"}}"#),
+            r#"{"brokerAddrs":{"0":"10.0.0.1:10911","1":"10.0.0.2:10911"}}"#
+        );
+        assert_eq!(
+            normalize_json(r#"{"offsetTable":{{"brokerName":"b1","queueId":0,"topic":"T1"}:5}}"#),
+            r#"{"offsetTable":{"{\"brokerName\":\"b1\",\"queueId\":0,\"topic\":\"T1\"}":5}}"#
+        );
+    }
 
     #[test]
     fn test_new() {
         let frame_0 = Frame::new();
         let frame_1 = Frame::new();
-        assert_eq!(frame_0.opaque < frame_1.opaque, true);
+        assert!(frame_0.opaque < frame_1.opaque);
     }
 
     #[test]
@@ -219,7 +411,7 @@ mod tests {
         assert_eq!(frame.opaque, 0);
         assert_eq!(frame.version, 0);
         assert_eq!(frame.flag, 0);
-        assert_eq!(frame.ext_fields.is_empty(), true);
+        assert!(frame.ext_fields.is_empty());
         Ok(())
     }
 
@@ -239,4 +431,20 @@ mod tests {
         frame.add_ext_headers(header);
         assert_eq!(frame.ext_fields.len(), 1);
     }
+
+    #[test]
+    fn test_check() {
+        let check = |frame_length: i32, header_length: i32, size: usize| {
+            let mut buf = BytesMut::new();
+            buf.put_i32(frame_length);
+            buf.put_i32(header_length);
+            buf.put_bytes(b'0', size - 8);
+            Frame::check(&mut std::io::Cursor::new(&buf[..]))
+        };
+        assert!(check(14, 10, 18).is_ok());
+        assert!(matches!(check(14, 10, 12), Err(Error::Incomplete)));
+        assert!(matches!(check(-1, 10, 18), Err(Error::Other(_))));
+        assert!(matches!(check(14, 11, 18), Err(Error::Other(_))));
+        assert!(matches!(check(14, -1, 18), Err(Error::Other(_))));
+    }
 }
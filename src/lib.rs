@@ -1,9 +1,22 @@
 //! This crate provides APIs to publish messages to and subscribe messages from [Apache RocketMQ](http://rocketmq.apache.org).
 //! At the moment, it is still work-in-progress.
+pub mod admin;
+pub mod client;
+pub mod compression;
 pub mod connection;
+pub mod consumer;
 pub mod error;
+pub mod filter;
 pub mod frame;
+pub mod listener;
+pub mod lite_pull_consumer;
 pub mod message;
+pub mod offset_store;
+pub mod process_queue;
 pub mod protocol;
 pub mod publisher;
+pub mod rebalance;
 pub mod route;
+pub mod simple_consumer;
+pub mod sql92;
+pub mod validator;
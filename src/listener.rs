@@ -0,0 +1,96 @@
+//!
+//! Define listeners that applications implement to consume messages delivered by push consumers.
+//!
+use crate::message::{MessageExt, MessageQueue};
+use std::time::Duration;
+
+/// Result of consuming a batch of messages in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsumeOrderlyStatus {
+    /// Messages are consumed; offset of the queue advances past them.
+    Success,
+
+    /// Messages should be delivered again after a short suspension of the whole queue.
+    SuspendCurrentQueueAMoment,
+}
+
+/// Context of an orderly consume attempt.
+#[derive(Debug, Clone)]
+pub struct ConsumeOrderlyContext {
+    /// The queue all messages of the batch belong to.
+    pub message_queue: MessageQueue,
+
+    /// Override how long the queue is suspended if `SuspendCurrentQueueAMoment` is returned.
+    pub suspend_current_queue_time: Option<Duration>,
+}
+
+/// Listener receiving messages of each queue strictly in order, one batch at a time.
+///
+/// Messages of the same queue are never delivered concurrently and a batch is not delivered until the
+/// previous batch of that queue is consumed.
+pub trait MessageListenerOrderly: Send + Sync {
+    fn consume_message(
+        &self,
+        messages: &[MessageExt],
+        context: &mut ConsumeOrderlyContext,
+    ) -> ConsumeOrderlyStatus;
+}
+
+impl<F> MessageListenerOrderly for F
+where
+    F: Fn(&[MessageExt], &mut ConsumeOrderlyContext) -> ConsumeOrderlyStatus + Send + Sync,
+{
+    fn consume_message(
+        &self,
+        messages: &[MessageExt],
+        context: &mut ConsumeOrderlyContext,
+    ) -> ConsumeOrderlyStatus {
+        self(messages, context)
+    }
+}
+
+/// Result of consuming a batch of messages concurrently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsumeConcurrentlyStatus {
+    /// Messages are consumed.
+    ConsumeSuccess,
+
+    /// Messages should be delivered again later, through the retry topic of the consumer group.
+    ReconsumeLater,
+}
+
+/// Context of a concurrent consume attempt.
+#[derive(Debug, Clone)]
+pub struct ConsumeConcurrentlyContext {
+    /// The queue all messages of the batch belong to.
+    pub message_queue: MessageQueue,
+
+    /// Delay level of redelivery if `ReconsumeLater` is returned. With the default 0, brokers choose the level
+    /// from the number of times the message has been consumed; a negative level sends messages to the dead letter
+    /// queue directly.
+    pub delay_level_when_next_consume: i32,
+}
+
+/// Listener receiving messages of all assigned queues concurrently, without any ordering guarantee.
+pub trait MessageListenerConcurrently: Send + Sync {
+    fn consume_message(
+        &self,
+        messages: &[MessageExt],
+        context: &mut ConsumeConcurrentlyContext,
+    ) -> ConsumeConcurrentlyStatus;
+}
+
+impl<F> MessageListenerConcurrently for F
+where
+    F: Fn(&[MessageExt], &mut ConsumeConcurrentlyContext) -> ConsumeConcurrentlyStatus
+        + Send
+        + Sync,
+{
+    fn consume_message(
+        &self,
+        messages: &[MessageExt],
+        context: &mut ConsumeConcurrentlyContext,
+    ) -> ConsumeConcurrentlyStatus {
+        self(messages, context)
+    }
+}
@@ -0,0 +1,995 @@
+//!
+//! Define `LitePullConsumer`, which prefetches messages of assigned queues in background and hands them out on `poll`.
+//!
+use crate::client::{current_millis, Client, ClientOption};
+use crate::consumer::{self, ConsumeFromWhere, MessageModel, PULL_DELAY_WHEN_EXCEPTION};
+use crate::error::ClientError;
+use crate::filter::{self, MessageSelector};
+use crate::message::{MessageExt, MessageQueue};
+use crate::offset_store::{OffsetStore, ReadOffsetType};
+use crate::process_queue::ProcessQueue;
+use crate::protocol;
+use crate::rebalance;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+
+/// Back-off before checking again a queue that is paused or whose prefetched messages reach the threshold.
+const PULL_DELAY_WHEN_PAUSED: Duration = Duration::from_millis(50);
+
+/// Options of a lite pull consumer.
+#[derive(Debug, Clone)]
+pub struct LitePullConsumerOption {
+    /// Consumer group, whose members share consumption progress and load of subscribed topics.
+    pub group: String,
+
+    pub message_model: MessageModel,
+
+    /// Where to start consuming queues the group has never committed an offset of.
+    pub consume_from_where: ConsumeFromWhere,
+
+    /// Directory where offsets of broadcasting consumers are persisted.
+    pub offset_store_dir: PathBuf,
+
+    /// Maximum number of messages fetched by a single pull request, and returned by a single poll.
+    pub pull_batch_size: i32,
+
+    /// Prefetching a queue pauses while it buffers more messages than this.
+    pub pull_threshold_for_queue: usize,
+
+    /// Whether offsets of polled messages are committed automatically every `auto_commit_interval`. Otherwise they
+    /// are committed by `commit_sync` only.
+    pub auto_commit: bool,
+
+    pub auto_commit_interval: Duration,
+
+    /// Interval between re-allocations of queues of subscribed topics among members of the group.
+    pub rebalance_interval: Duration,
+}
+
+impl Default for LitePullConsumerOption {
+    fn default() -> Self {
+        Self {
+            group: "DEFAULT_CONSUMER".to_owned(),
+            message_model: MessageModel::Clustering,
+            consume_from_where: ConsumeFromWhere::LastOffset,
+            offset_store_dir: consumer::default_offset_store_dir(),
+            pull_batch_size: 10,
+            pull_threshold_for_queue: 1000,
+            auto_commit: true,
+            auto_commit_interval: Duration::from_secs(5),
+            rebalance_interval: Duration::from_secs(20),
+        }
+    }
+}
+
+/// How queues are assigned to a lite pull consumer, which must not be mixed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SubscriptionType {
+    None,
+
+    /// Queues of subscribed topics are allocated among members of the group.
+    Subscribe,
+
+    /// Queues are assigned explicitly by the application.
+    Assign,
+}
+
+/// Consumer that leaves the pace of consumption to the application: messages of assigned queues are prefetched in
+/// background and handed out on `poll`, while the application may seek, pause and commit each queue.
+///
+/// Queues are either assigned explicitly by `assign`, or allocated among members of the group for topics given to
+/// `subscribe`.
+pub struct LitePullConsumer {
+    inner: Arc<LiteConsumerInner>,
+    shutdown: watch::Sender<bool>,
+    started: AtomicBool,
+}
+
+struct LiteConsumerInner {
+    client: Arc<Client>,
+    option: LitePullConsumerOption,
+    offset_store: Box<dyn OffsetStore>,
+    subscription_type: Mutex<SubscriptionType>,
+    subscriptions: RwLock<HashMap<String, protocol::SubscriptionData>>,
+
+    /// Queues assigned by the application, in assign mode.
+    assigned: Mutex<Vec<MessageQueue>>,
+    queues: Mutex<HashMap<MessageQueue, Arc<AssignedQueue>>>,
+    paused: Mutex<HashSet<MessageQueue>>,
+
+    /// Queue messages were last polled from, after which the next poll starts looking, so that every queue is
+    /// served in turn.
+    last_polled: Mutex<Option<MessageQueue>>,
+
+    /// Wake up polls waiting for messages once some are prefetched.
+    available: Notify,
+
+    /// Serialize rebalances triggered periodically and by subscription changes.
+    rebalance_lock: tokio::sync::Mutex<()>,
+    shutdown: watch::Receiver<bool>,
+}
+
+/// Prefetch buffer of an assigned queue, replaced as a whole when the queue is sought.
+struct AssignedQueue {
+    pq: Arc<ProcessQueue>,
+
+    /// Offset following the last message polled, which is the one to commit; `-1` if none is polled.
+    consumed_offset: AtomicI64,
+}
+
+impl LitePullConsumer {
+    pub fn new(
+        client_option: ClientOption,
+        option: LitePullConsumerOption,
+    ) -> Result<Self, ClientError> {
+        let (tx, rx) = watch::channel(false);
+        let client = Arc::new(Client::new(client_option)?);
+        let offset_store = consumer::new_offset_store(
+            &client,
+            option.message_model,
+            &option.offset_store_dir,
+            &option.group,
+        );
+        let inner = LiteConsumerInner {
+            client,
+            option,
+            offset_store,
+            subscription_type: Mutex::new(SubscriptionType::None),
+            subscriptions: RwLock::new(HashMap::new()),
+            assigned: Mutex::new(vec![]),
+            queues: Mutex::new(HashMap::new()),
+            paused: Mutex::new(HashSet::new()),
+            last_polled: Mutex::new(None),
+            available: Notify::new(),
+            rebalance_lock: tokio::sync::Mutex::new(()),
+            shutdown: rx,
+        };
+        Ok(Self {
+            inner: Arc::new(inner),
+            shutdown: tx,
+            started: AtomicBool::new(false),
+        })
+    }
+
+    /// Subscribe messages of the topic whose tag matches the expression, such as `TagA || TagB`, or `*` for all.
+    /// Queues of the topic are allocated among members of the group.
+    ///
+    /// # Errors
+    /// Raise ClientError::IllegalState if queues are assigned explicitly, or ClientError::InvalidExpression if the
+    /// expression names no tag.
+    pub fn subscribe(&self, topic: &str, expression: &str) -> Result<(), ClientError> {
+        self.subscribe_with_selector(topic, &MessageSelector::by_tag(expression))
+    }
+
+    /// Subscribe messages of the topic selected by a tag or SQL92 expression.
+    ///
+    /// # Errors
+    /// Raise ClientError::IllegalState if queues are assigned explicitly, or ClientError::InvalidExpression if the
+    /// expression is malformed.
+    pub fn subscribe_with_selector(
+        &self,
+        topic: &str,
+        selector: &MessageSelector,
+    ) -> Result<(), ClientError> {
+        let subscription = filter::build_subscription(topic, selector, current_millis())?;
+        self.inner
+            .set_subscription_type(SubscriptionType::Subscribe)?;
+        self.inner
+            .subscriptions
+            .write()
+            .map_err(|_e| ClientError::Unknown)?
+            .insert(topic.to_owned(), subscription);
+
+        if self.started.load(Ordering::SeqCst) {
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                let inner = Arc::clone(&self.inner);
+                handle.spawn(async move {
+                    inner.send_heartbeat().await;
+                    inner.rebalance().await;
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Consume exactly the given queues, replacing those assigned before. Offsets of queues no longer assigned are
+    /// committed.
+    ///
+    /// # Errors
+    /// Raise ClientError::IllegalState if topics are subscribed.
+    pub async fn assign(&self, mqs: Vec<MessageQueue>) -> Result<(), ClientError> {
+        self.inner.set_subscription_type(SubscriptionType::Assign)?;
+        *self
+            .inner
+            .assigned
+            .lock()
+            .map_err(|_e| ClientError::Unknown)? = mqs.clone();
+        if self.started.load(Ordering::SeqCst) {
+            self.inner.update_queues(mqs).await;
+        }
+        Ok(())
+    }
+
+    /// Queues currently consumed, either assigned explicitly or allocated by rebalance.
+    pub fn assignment(&self) -> HashSet<MessageQueue> {
+        self.inner
+            .queues
+            .lock()
+            .map(|map| map.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Query the readable queues of the topic, which may be assigned to this consumer.
+    pub async fn fetch_message_queues(
+        &self,
+        topic: &str,
+    ) -> Result<Vec<MessageQueue>, ClientError> {
+        self.inner.client.subscribe_queues(topic).await
+    }
+
+    /// Address of the node messages of the queue are pulled from next: a slave if its master suggested so in the
+    /// last pull response, or the master otherwise.
+    ///
+    /// # Errors
+    /// Raise ClientError::BrokerNotFound if the route of the topic lists no node of the broker.
+    pub async fn preferred_broker_addr(&self, mq: &MessageQueue) -> Result<String, ClientError> {
+        Ok(self.inner.client.find_pull_addr(mq).await?.0)
+    }
+
+    /// Take prefetched messages of one of the assigned queues, waiting up to `timeout` for some to arrive.
+    ///
+    /// Return an empty list if no message arrives in time. Messages returned are considered consumed, and their
+    /// offsets committed automatically with `auto_commit`, or by the next `commit_sync` otherwise.
+    ///
+    /// # Errors
+    /// Raise ClientError::IllegalState if the consumer is not started.
+    pub async fn poll(&self, timeout: Duration) -> Result<Vec<MessageExt>, ClientError> {
+        if !self.started.load(Ordering::SeqCst) {
+            return Err(ClientError::IllegalState(
+                "Consumer is not started".to_owned(),
+            ));
+        }
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let messages = self.inner.take_messages();
+            if !messages.is_empty() {
+                return Ok(messages);
+            }
+            let mut shutdown = self.inner.shutdown.clone();
+            tokio::select! {
+                _ = self.inner.available.notified() => {}
+                _ = tokio::time::sleep_until(deadline) => return Ok(vec![]),
+                _ = shutdown.changed() => return Ok(vec![]),
+            }
+        }
+    }
+
+    /// Discard prefetched messages of the queue and consume it from `offset` on. The offset is committed along with
+    /// those of polled messages.
+    ///
+    /// # Errors
+    /// Raise ClientError::IllegalState if the queue is not assigned, or ClientError::OffsetOutOfRange if the offset is
+    /// not between the min and max offsets of the queue.
+    pub async fn seek(&self, mq: &MessageQueue, offset: i64) -> Result<(), ClientError> {
+        self.inner.ensure_assigned(mq)?;
+        let min = self.inner.client.min_offset(mq).await?;
+        let max = self.inner.client.max_offset(mq).await?;
+        if offset < min || offset > max {
+            return Err(ClientError::OffsetOutOfRange { offset, min, max });
+        }
+        self.inner.reset_queue(mq, offset)
+    }
+
+    /// Consume the queue from its oldest message still stored.
+    pub async fn seek_to_begin(&self, mq: &MessageQueue) -> Result<(), ClientError> {
+        self.inner.ensure_assigned(mq)?;
+        let offset = self.inner.client.min_offset(mq).await?;
+        self.inner.reset_queue(mq, offset)
+    }
+
+    /// Consume the queue from messages arriving from now on.
+    pub async fn seek_to_end(&self, mq: &MessageQueue) -> Result<(), ClientError> {
+        self.inner.ensure_assigned(mq)?;
+        let offset = self.inner.client.max_offset(mq).await?;
+        self.inner.reset_queue(mq, offset)
+    }
+
+    /// Offset of the first message of the queue stored at or after `timestamp`, in milliseconds since the Unix epoch,
+    /// which may be passed to `seek`.
+    pub async fn offset_for_timestamp(
+        &self,
+        mq: &MessageQueue,
+        timestamp: i64,
+    ) -> Result<i64, ClientError> {
+        self.inner.client.search_offset(mq, timestamp).await
+    }
+
+    /// Offset of the queue last committed by the group, or `None` if it never committed one.
+    pub async fn committed(&self, mq: &MessageQueue) -> Result<Option<i64>, ClientError> {
+        self.inner
+            .offset_store
+            .read_offset(mq, ReadOffsetType::ReadFromStore)
+            .await
+    }
+
+    /// Commit offsets of messages polled so far from all assigned queues.
+    pub async fn commit_sync(&self) {
+        self.inner.commit_all().await;
+    }
+
+    /// Stop prefetching and polling messages of the queues, until they are resumed.
+    pub fn pause(&self, mqs: &[MessageQueue]) {
+        if let Ok(mut paused) = self.inner.paused.lock() {
+            paused.extend(mqs.iter().cloned());
+        }
+    }
+
+    pub fn resume(&self, mqs: &[MessageQueue]) {
+        if let Ok(mut paused) = self.inner.paused.lock() {
+            mqs.iter().for_each(|mq| {
+                paused.remove(mq);
+            });
+        }
+        self.inner.available.notify_one();
+    }
+
+    /// Start prefetching messages of assigned queues in background.
+    ///
+    /// # Errors
+    /// Raise ClientError::IllegalState if the consumer is started twice or after shutdown.
+    pub async fn start(&self) -> Result<(), ClientError> {
+        if self.inner.is_shutdown() {
+            return Err(ClientError::IllegalState(
+                "Consumer has already been shut down".to_owned(),
+            ));
+        }
+        if self.started.swap(true, Ordering::SeqCst) {
+            return Err(ClientError::IllegalState(
+                "Consumer has already been started".to_owned(),
+            ));
+        }
+        if let Err(e) = self.inner.offset_store.load().await {
+            // Allow starting again once the cause is fixed.
+            self.started.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+
+        let option = &self.inner.option;
+        if self.inner.subscription_type() == SubscriptionType::Subscribe {
+            // Brokers must know this client before it may be allocated queues.
+            self.inner.send_heartbeat().await;
+            self.inner.rebalance().await;
+            consumer::spawn_periodic(
+                &self.inner,
+                &self.inner.shutdown,
+                self.inner.client.option.heartbeat_interval,
+                |inner| async move { inner.send_heartbeat().await },
+            );
+            consumer::spawn_periodic(
+                &self.inner,
+                &self.inner.shutdown,
+                option.rebalance_interval,
+                |inner| async move { inner.rebalance().await },
+            );
+        } else {
+            let assigned = self
+                .inner
+                .assigned
+                .lock()
+                .map(|mqs| mqs.clone())
+                .unwrap_or_default();
+            self.inner.update_queues(assigned).await;
+        }
+        if option.auto_commit {
+            consumer::spawn_periodic(
+                &self.inner,
+                &self.inner.shutdown,
+                option.auto_commit_interval,
+                |inner| async move { inner.commit_all().await },
+            );
+        }
+        Ok(())
+    }
+
+    /// Stop prefetching, commit offsets of polled messages if `auto_commit` is on, and unregister from brokers. The
+    /// consumer cannot be started again.
+    pub async fn shutdown(&self) {
+        if !self.started.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let _ = self.shutdown.send(true);
+        if self.inner.option.auto_commit {
+            self.inner.commit_all().await;
+        }
+        if let Ok(mut map) = self.inner.queues.lock() {
+            map.drain().for_each(|(_, queue)| queue.pq.set_dropped());
+        }
+        if self.inner.subscription_type() == SubscriptionType::Subscribe {
+            self.inner
+                .client
+                .unregister_consumer(&self.inner.option.group, &self.inner.topics())
+                .await;
+        }
+    }
+}
+
+impl LiteConsumerInner {
+    fn subscription_type(&self) -> SubscriptionType {
+        self.subscription_type
+            .lock()
+            .map(|t| *t)
+            .unwrap_or(SubscriptionType::None)
+    }
+
+    fn set_subscription_type(
+        &self,
+        subscription_type: SubscriptionType,
+    ) -> Result<(), ClientError> {
+        let mut current = self
+            .subscription_type
+            .lock()
+            .map_err(|_e| ClientError::Unknown)?;
+        if *current != SubscriptionType::None && *current != subscription_type {
+            return Err(ClientError::IllegalState(
+                "Subscribing topics and assigning queues are mutually exclusive".to_owned(),
+            ));
+        }
+        *current = subscription_type;
+        Ok(())
+    }
+
+    fn topics(&self) -> Vec<String> {
+        self.subscriptions
+            .read()
+            .map(|map| map.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn is_shutdown(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    fn is_paused(&self, mq: &MessageQueue) -> bool {
+        self.paused
+            .lock()
+            .map(|paused| paused.contains(mq))
+            .unwrap_or_default()
+    }
+
+    /// Sleep for the given duration, waking up early if the consumer shuts down.
+    async fn sleep(&self, duration: Duration) {
+        let mut shutdown = self.shutdown.clone();
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => {}
+            _ = shutdown.changed() => {}
+        }
+    }
+
+    fn ensure_assigned(&self, mq: &MessageQueue) -> Result<(), ClientError> {
+        let assigned = self
+            .queues
+            .lock()
+            .map(|map| map.contains_key(mq))
+            .unwrap_or_default();
+        if !assigned {
+            return Err(ClientError::IllegalState(format!(
+                "{:?} is not assigned to this consumer",
+                mq
+            )));
+        }
+        Ok(())
+    }
+
+    async fn send_heartbeat(&self) {
+        let subscription_data_set = self
+            .subscriptions
+            .read()
+            .map(|map| map.values().cloned().collect())
+            .unwrap_or_default();
+        self.client
+            .send_heartbeat(protocol::ConsumerData {
+                group_name: self.option.group.clone(),
+                consume_type: "CONSUME_ACTIVELY".to_owned(),
+                message_model: self.option.message_model.as_str().to_owned(),
+                consume_from_where: self.option.consume_from_where.as_str().to_owned(),
+                subscription_data_set,
+                unit_mode: false,
+            })
+            .await;
+    }
+
+    async fn rebalance(self: &Arc<Self>) {
+        let _guard = self.rebalance_lock.lock().await;
+        let mut allocated = vec![];
+        for topic in self.topics() {
+            match self.allocate(&topic).await {
+                Ok(mqs) => allocated.extend(mqs),
+                Err(e) => {
+                    eprintln!("Failed to rebalance topic {}. Cause: {}", topic, e);
+                    // Keep consuming queues of the topic until it can be rebalanced.
+                    if let Ok(map) = self.queues.lock() {
+                        allocated.extend(map.keys().filter(|mq| mq.topic == topic).cloned());
+                    }
+                }
+            }
+        }
+        self.update_queues(allocated).await;
+    }
+
+    async fn allocate(&self, topic: &str) -> Result<Vec<MessageQueue>, ClientError> {
+        let mq_all = self.client.subscribe_queues(topic).await?;
+        if self.option.message_model == MessageModel::Broadcasting {
+            return Ok(mq_all);
+        }
+        let mut cid_all = self
+            .client
+            .consumer_id_list(topic, &self.option.group)
+            .await?;
+        cid_all.sort();
+        Ok(rebalance::allocate_averagely(
+            &self.client.client_id,
+            &mq_all,
+            &cid_all,
+        ))
+    }
+
+    /// Stop prefetching queues no longer assigned and start prefetching newly assigned ones from their committed
+    /// offsets.
+    async fn update_queues(self: &Arc<Self>, mqs: Vec<MessageQueue>) {
+        let mqs: HashSet<MessageQueue> = mqs.into_iter().collect();
+        let removed: Vec<(MessageQueue, Arc<AssignedQueue>)> = match self.queues.lock() {
+            Ok(mut map) => {
+                let keys: Vec<MessageQueue> =
+                    map.keys().filter(|mq| !mqs.contains(mq)).cloned().collect();
+                keys.into_iter()
+                    .filter_map(|mq| map.remove(&mq).map(|queue| (mq, queue)))
+                    .collect()
+            }
+            Err(_e) => return,
+        };
+        for (mq, queue) in removed {
+            queue.pq.set_dropped();
+            self.commit(&mq, &queue);
+            self.offset_store.persist(&mq).await;
+            self.offset_store.remove_offset(&mq);
+        }
+
+        for mq in mqs {
+            let exists = self
+                .queues
+                .lock()
+                .map(|map| map.contains_key(&mq))
+                .unwrap_or(true);
+            if exists {
+                continue;
+            }
+            self.offset_store.remove_offset(&mq);
+            let offset = match consumer::compute_pull_from_where(
+                &self.client,
+                self.offset_store.as_ref(),
+                &mq,
+                self.option.consume_from_where,
+            )
+            .await
+            {
+                Ok(offset) => offset,
+                Err(e) => {
+                    eprintln!("Failed to fetch consume offset of {:?}. Cause: {}", mq, e);
+                    continue;
+                }
+            };
+            self.start_queue(mq, offset, -1);
+        }
+    }
+
+    fn start_queue(self: &Arc<Self>, mq: MessageQueue, offset: i64, consumed_offset: i64) {
+        let queue = Arc::new(AssignedQueue {
+            pq: Arc::new(ProcessQueue::new(offset)),
+            consumed_offset: AtomicI64::new(consumed_offset),
+        });
+        if let Ok(mut map) = self.queues.lock() {
+            if let Some(previous) = map.insert(mq.clone(), Arc::clone(&queue)) {
+                previous.pq.set_dropped();
+            }
+        }
+        tokio::spawn(Arc::clone(self).prefetch_loop(mq, Arc::clone(&queue.pq)));
+    }
+
+    /// Replace the prefetch buffer of an assigned queue so that it is consumed from `offset` on.
+    fn reset_queue(self: &Arc<Self>, mq: &MessageQueue, offset: i64) -> Result<(), ClientError> {
+        self.ensure_assigned(mq)?;
+        self.start_queue(mq.clone(), offset, offset);
+        Ok(())
+    }
+
+    /// Record offsets of polled messages in the offset store.
+    fn commit(&self, mq: &MessageQueue, queue: &AssignedQueue) {
+        let offset = queue.consumed_offset.load(Ordering::Relaxed);
+        if offset >= 0 {
+            self.offset_store.update_offset(mq, offset, false);
+        }
+    }
+
+    async fn commit_all(&self) {
+        let queues: Vec<(MessageQueue, Arc<AssignedQueue>)> = match self.queues.lock() {
+            Ok(map) => map
+                .iter()
+                .map(|(mq, queue)| (mq.clone(), Arc::clone(queue)))
+                .collect(),
+            Err(_e) => return,
+        };
+        for (mq, queue) in &queues {
+            self.commit(mq, queue);
+        }
+        let mqs = queues.into_iter().map(|(mq, _)| mq).collect();
+        self.offset_store.persist_all(&mqs).await;
+    }
+
+    /// Take up to `pull_batch_size` prefetched messages of the first queue that has any and is not paused, looking
+    /// from the queue after the one last polled.
+    fn take_messages(&self) -> Vec<MessageExt> {
+        let mut queues: Vec<(MessageQueue, Arc<AssignedQueue>)> = match self.queues.lock() {
+            Ok(map) => map
+                .iter()
+                .map(|(mq, queue)| (mq.clone(), Arc::clone(queue)))
+                .collect(),
+            Err(_e) => return vec![],
+        };
+        queues.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut last_polled = match self.last_polled.lock() {
+            Ok(last_polled) => last_polled,
+            Err(_e) => return vec![],
+        };
+        let start = match last_polled.as_ref() {
+            Some(last) => queues.partition_point(|(mq, _)| mq <= last),
+            None => 0,
+        };
+        queues.rotate_left(start);
+        for (mq, queue) in queues {
+            if self.is_paused(&mq) {
+                continue;
+            }
+            let messages = queue
+                .pq
+                .take_messages(self.option.pull_batch_size.max(1) as usize);
+            if messages.is_empty() {
+                continue;
+            }
+            if let Some(offset) = queue.pq.commit() {
+                queue.consumed_offset.store(offset, Ordering::Relaxed);
+            }
+            *last_polled = Some(mq);
+            // Wake up another poll, as more messages may be buffered.
+            self.available.notify_one();
+            return messages;
+        }
+        vec![]
+    }
+
+    async fn prefetch_loop(self: Arc<Self>, mq: MessageQueue, pq: Arc<ProcessQueue>) {
+        while !pq.is_dropped() && !self.is_shutdown() {
+            if self.is_paused(&mq) {
+                self.sleep(PULL_DELAY_WHEN_PAUSED).await;
+                continue;
+            }
+            if pq.stats().cached_msg_count > self.option.pull_threshold_for_queue {
+                pq.inc_flow_control_times();
+                self.sleep(PULL_DELAY_WHEN_PAUSED).await;
+                continue;
+            }
+            if let Err(e) = self.pull_once(&mq, &pq).await {
+                eprintln!("Failed to pull messages from {:?}. Cause: {}", mq, e);
+                self.sleep(PULL_DELAY_WHEN_EXCEPTION).await;
+            }
+        }
+    }
+
+    fn subscription(&self, topic: &str) -> Result<protocol::SubscriptionData, ClientError> {
+        if let Some(subscription) = self
+            .subscriptions
+            .read()
+            .map_err(|_e| ClientError::Unknown)?
+            .get(topic)
+        {
+            return Ok(subscription.clone());
+        }
+        // Assigned queues are consumed as a whole.
+        filter::build_subscription(topic, &MessageSelector::by_tag(filter::SUB_ALL), 0)
+    }
+
+    async fn pull_once(&self, mq: &MessageQueue, pq: &ProcessQueue) -> Result<(), ClientError> {
+        let subscription = self.subscription(&mq.topic)?;
+        // Offsets are committed by the application rather than along with pulls.
+        let header = consumer::pull_request_header(
+            &self.option.group,
+            self.option.pull_batch_size,
+            mq,
+            pq,
+            &subscription,
+        );
+        let pulled =
+            consumer::pull_messages(&self.client, header, mq, pq, &subscription, &self.shutdown)
+                .await?;
+        if !pulled.messages.is_empty() && !pq.is_dropped() {
+            pq.put_messages(pulled.messages);
+            self.available.notify_one();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection;
+    use crate::connection::tests::{mock_server, route_json};
+    use crate::frame::Frame;
+    use crate::message::tests::encode_stored;
+
+    /// Serve as `connection::tests::mock_cluster` does, answering pulls of the two queues of topic `T1`, which hold
+    /// messages at offsets 0 and 1 tagged with their queue id. The group has committed offset 0 of both queues.
+    async fn mock_pull_cluster(requests: Arc<Mutex<Vec<Frame>>>) -> String {
+        connection::tests::mock_cluster(2, requests, |request, response| {
+            match request.code {
+                14 | 31 => response.put_ext_field("offset", "0"),
+                30 => response.put_ext_field("offset", "2"),
+                11 => {
+                    let offset: i64 = request.ext_fields["queueOffset"].parse().unwrap();
+                    if offset > 1 {
+                        // Hold further pulls, as brokers do when no message is available.
+                        return false;
+                    }
+                    let queue_id = &request.ext_fields["queueId"];
+                    let mut body = bytes::BytesMut::new();
+                    for offset in offset..2 {
+                        body.extend(encode_stored("T1", offset, &[("TAGS", queue_id)], b"body"));
+                    }
+                    response.body = body.freeze();
+                    response.put_ext_field("suggestWhichBrokerId", "0");
+                    response.put_ext_field("nextBeginOffset", "2");
+                    response.put_ext_field("minOffset", "0");
+                    response.put_ext_field("maxOffset", "2");
+                }
+                _ => {}
+            }
+            true
+        })
+        .await
+    }
+
+    fn mq(queue_id: i32) -> MessageQueue {
+        MessageQueue {
+            topic: "T1".to_owned(),
+            broker_name: "b1".to_owned(),
+            queue_id,
+        }
+    }
+
+    async fn new_consumer(
+        requests: Arc<Mutex<Vec<Frame>>>,
+    ) -> Result<LitePullConsumer, ClientError> {
+        let name_server = mock_pull_cluster(requests).await;
+        let client_option = ClientOption {
+            name_server,
+            ..Default::default()
+        };
+        let option = LitePullConsumerOption {
+            auto_commit: false,
+            ..Default::default()
+        };
+        LitePullConsumer::new(client_option, option)
+    }
+
+    /// Queue id, as tagged by the mock broker, and offset of each message.
+    fn offsets(messages: &[MessageExt]) -> Vec<(&str, i64)> {
+        messages
+            .iter()
+            .map(|message| (message.tag.as_str(), message.queue_offset))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_assign_poll_and_seek() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let consumer = new_consumer(Arc::clone(&requests)).await?;
+        consumer.assign(vec![mq(0)]).await?;
+        assert!(consumer.subscribe("T1", "*").is_err());
+        consumer.start().await?;
+
+        let messages = consumer.poll(Duration::from_secs(5)).await?;
+        assert_eq!(offsets(&messages), vec![("0", 0), ("0", 1)]);
+        assert_eq!(messages[0].broker_name, "b1");
+        assert!(consumer.poll(Duration::from_millis(100)).await?.is_empty());
+
+        consumer.seek(&mq(0), 1).await?;
+        let messages = consumer.poll(Duration::from_secs(5)).await?;
+        assert_eq!(offsets(&messages), vec![("0", 1)]);
+        assert!(matches!(
+            consumer.seek(&mq(0), 3).await,
+            Err(ClientError::OffsetOutOfRange { max: 2, .. })
+        ));
+        assert!(consumer.seek(&mq(1), 0).await.is_err());
+
+        consumer.commit_sync().await;
+        consumer.shutdown().await;
+        // Offsets are committed by oneway requests, which the broker may not have received yet.
+        for _ in 0..50 {
+            if requests.lock().unwrap().iter().any(|r| r.code == 15) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let requests = requests.lock().unwrap();
+        let commit = requests.iter().find(|r| r.code == 15).unwrap();
+        assert_eq!(commit.ext_fields["queueId"], "0");
+        assert_eq!(commit.ext_fields["commitOffset"], "2");
+        // Offsets are committed by the application only.
+        assert!(requests
+            .iter()
+            .filter(|r| r.code == 11)
+            .all(|r| r.ext_fields["sysFlag"] == "6"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let consumer = new_consumer(requests).await?;
+        consumer.assign(vec![mq(0), mq(1)]).await?;
+        consumer.pause(&[mq(1)]);
+        consumer.start().await?;
+        assert_eq!(consumer.assignment().len(), 2);
+
+        let messages = consumer.poll(Duration::from_secs(5)).await?;
+        assert_eq!(offsets(&messages), vec![("0", 0), ("0", 1)]);
+        assert!(consumer.poll(Duration::from_millis(200)).await?.is_empty());
+
+        consumer.resume(&[mq(1)]);
+        let messages = consumer.poll(Duration::from_secs(5)).await?;
+        assert_eq!(offsets(&messages), vec![("1", 0), ("1", 1)]);
+        consumer.shutdown().await;
+        assert!(matches!(
+            consumer.start().await,
+            Err(ClientError::IllegalState(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_poll_queues_in_turn() -> Result<(), ClientError> {
+        let name_server = mock_pull_cluster(Arc::new(Mutex::new(vec![]))).await;
+        let client_option = ClientOption {
+            name_server,
+            ..Default::default()
+        };
+        let option = LitePullConsumerOption {
+            auto_commit: false,
+            pull_batch_size: 1,
+            ..Default::default()
+        };
+        let consumer = LitePullConsumer::new(client_option, option)?;
+        consumer.assign(vec![mq(0), mq(1)]).await?;
+        consumer.start().await?;
+        // Let both queues prefetch all of their messages first.
+        for _ in 0..50 {
+            let cached: Vec<usize> = consumer
+                .inner
+                .queues
+                .lock()
+                .unwrap()
+                .values()
+                .map(|queue| queue.pq.stats().cached_msg_count)
+                .collect();
+            if cached == vec![2, 2] {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let mut polled = vec![];
+        for _ in 0..4 {
+            let messages = consumer.poll(Duration::from_secs(5)).await?;
+            polled.extend(
+                offsets(&messages)
+                    .into_iter()
+                    .map(|(tag, _)| tag.to_owned()),
+            );
+        }
+        assert_eq!(polled, vec!["0", "1", "0", "1"]);
+        consumer.shutdown().await;
+        Ok(())
+    }
+
+    /// Answer a pull of queue 0 of `T1` with the message at the offset requested, suggesting to pull from the slave
+    /// next. Further pulls are held from offset 2 on.
+    fn respond_pull(request: &Frame, response: &mut Frame) -> bool {
+        let offset: i64 = request.ext_fields["queueOffset"].parse().unwrap();
+        if offset > 1 {
+            return false;
+        }
+        response.body = encode_stored("T1", offset, &[("TAGS", "0")], b"body");
+        response.put_ext_field("suggestWhichBrokerId", "1");
+        response.put_ext_field("nextBeginOffset", &(offset + 1).to_string());
+        response.put_ext_field("minOffset", "0");
+        response.put_ext_field("maxOffset", "2");
+        true
+    }
+
+    #[tokio::test]
+    async fn test_pull_from_suggested_broker() -> Result<(), ClientError> {
+        let slave_requests = Arc::new(Mutex::new(vec![]));
+        let pulls = Arc::clone(&slave_requests);
+        let slave = mock_server(move |request| {
+            let mut response = Frame::new();
+            response.opaque = request.opaque;
+            let held = request.code == 11 && !respond_pull(&request, &mut response);
+            pulls.lock().unwrap().push(request);
+            (!held).then_some(response)
+        })
+        .await;
+
+        let master_requests = Arc::new(Mutex::new(vec![]));
+        let requests = Arc::clone(&master_requests);
+        let master = mock_server(move |request| {
+            let mut response = Frame::new();
+            response.opaque = request.opaque;
+            match request.code {
+                14 => response.put_ext_field("offset", "0"),
+                11 => {
+                    respond_pull(&request, &mut response);
+                }
+                _ => {}
+            }
+            requests.lock().unwrap().push(request);
+            Some(response)
+        })
+        .await;
+
+        let route = route_json(&[&master, &slave], 1);
+        let name_server = mock_server(move |request| {
+            let mut response = Frame::new();
+            response.opaque = request.opaque;
+            response.body = bytes::Bytes::from(route.clone());
+            Some(response)
+        })
+        .await;
+
+        let client_option = ClientOption {
+            name_server,
+            ..Default::default()
+        };
+        let option = LitePullConsumerOption {
+            auto_commit: false,
+            ..Default::default()
+        };
+        let consumer = LitePullConsumer::new(client_option, option)?;
+        assert_eq!(consumer.preferred_broker_addr(&mq(0)).await?, master);
+        consumer.assign(vec![mq(0)]).await?;
+        consumer.start().await?;
+
+        let mut messages = vec![];
+        while messages.len() < 2 {
+            messages.extend(consumer.poll(Duration::from_secs(5)).await?);
+        }
+        assert_eq!(offsets(&messages), vec![("0", 0), ("0", 1)]);
+        consumer.shutdown().await;
+
+        // The master serves the first pull only, and the slave the following ones.
+        let pulled_from = |requests: &Arc<Mutex<Vec<Frame>>>| {
+            requests
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|r| r.code == 11)
+                .map(|r| r.ext_fields["queueOffset"].clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pulled_from(&master_requests), vec!["0"]);
+        assert_eq!(pulled_from(&slave_requests)[0], "1");
+        Ok(())
+    }
+}
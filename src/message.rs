@@ -1,10 +1,24 @@
 //!
 //! Define Message struct. Application data are enveloped in `Message` before publishing to Apache RocketMQ.
 //!
-use bytes;
+use crate::compression::{self, Compression};
+use crate::error::ClientError;
+use crate::simple_consumer::ReceiptHandle;
+use bytes::{self, Buf, BufMut};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::vec::Vec;
 
+/// Number of delay levels brokers are configured with by default, ranging from 1s, 5s, 10s, 30s, 1m up to 2h.
+pub const MAX_DELAY_LEVEL: u8 = 18;
+
+/// Longest delay of timer messages accepted by brokers with the default `timerMaxDelaySec`.
+pub const MAX_TIMER_DELAY: Duration = Duration::from_secs(3 * 24 * 3600);
+
+#[derive(Debug, Clone)]
 pub struct Message {
     /// In the publisher-subscriber model, a topic is an addresses where messages are delivered to and subscribed from.
     pub topic: String,
@@ -24,4 +38,945 @@ pub struct Message {
     pub(crate) properties: HashMap<String, String>,
 
     pub body: bytes::Bytes,
+
+    /// How the body is compressed, following the policy of the publisher by default. Batches are compressed as a
+    /// whole by that policy, regardless of this.
+    pub compression: Compression,
+}
+
+impl Message {
+    pub fn new(topic: &str, body: impl Into<bytes::Bytes>) -> Self {
+        Self {
+            topic: topic.to_owned(),
+            tag: String::new(),
+            keys: vec![],
+            attributes: HashMap::new(),
+            properties: HashMap::new(),
+            body: body.into(),
+            compression: Compression::Default,
+        }
+    }
+
+    /// Deliver the message after the delay of the given level, 1 being the shortest. Level 0 cancels the delay.
+    ///
+    /// # Errors
+    /// Raise ClientError::InvalidDelay if the level exceeds `MAX_DELAY_LEVEL`.
+    pub fn with_delay_level(mut self, level: u8) -> Result<Self, ClientError> {
+        if level > MAX_DELAY_LEVEL {
+            return Err(ClientError::InvalidDelay(format!(
+                "delay level {} is out of range [1, {}]",
+                level, MAX_DELAY_LEVEL
+            )));
+        }
+        self.clear_delay();
+        if level > 0 {
+            self.properties
+                .insert(property::DELAY_TIME_LEVEL.to_owned(), level.to_string());
+        }
+        Ok(self)
+    }
+
+    /// Deliver the message at the given time, which brokers of RocketMQ 5 support with millisecond precision.
+    /// Messages scheduled in the past are delivered right away.
+    ///
+    /// # Errors
+    /// Raise ClientError::InvalidDelay if the time is more than `MAX_TIMER_DELAY` ahead.
+    pub fn with_deliver_time(mut self, deliver_time: SystemTime) -> Result<Self, ClientError> {
+        if let Ok(delay) = deliver_time.duration_since(SystemTime::now()) {
+            check_timer_delay(delay)?;
+        }
+        let millis = deliver_time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        self.clear_delay();
+        self.properties
+            .insert(property::TIMER_DELIVER_MS.to_owned(), millis.to_string());
+        Ok(self)
+    }
+
+    /// Deliver the message once the delay elapses after it is stored, in whole seconds.
+    ///
+    /// # Errors
+    /// Raise ClientError::InvalidDelay if the delay is shorter than a second or longer than `MAX_TIMER_DELAY`.
+    pub fn with_delay(mut self, delay: Duration) -> Result<Self, ClientError> {
+        check_timer_delay(delay)?;
+        if delay.as_secs() == 0 {
+            return Err(ClientError::InvalidDelay(format!(
+                "delay {:?} is shorter than a second",
+                delay
+            )));
+        }
+        self.clear_delay();
+        self.properties.insert(
+            property::TIMER_DELAY_SEC.to_owned(),
+            delay.as_secs().to_string(),
+        );
+        Ok(self)
+    }
+
+    /// Whether the message is a reply to a request, to be pushed by brokers to the requesting client.
+    pub(crate) fn is_reply(&self) -> bool {
+        self.properties
+            .get(property::MESSAGE_TYPE)
+            .is_some_and(|t| t == REPLY_MESSAGE_FLAG)
+    }
+
+    /// Whether the message is to be delivered later, by delay level or by timer.
+    pub(crate) fn is_delayed(&self) -> bool {
+        DELAY_PROPERTIES
+            .iter()
+            .any(|key| self.properties.contains_key(*key))
+    }
+
+    fn clear_delay(&mut self) {
+        DELAY_PROPERTIES.iter().for_each(|key| {
+            self.properties.remove(*key);
+        });
+    }
+
+    /// Serialize tag, keys, user attributes and system properties as carried by send requests.
+    pub(crate) fn encode_properties(&self) -> String {
+        let mut properties = self.attributes.clone();
+        properties.extend(self.properties.clone());
+        if !self.tag.is_empty() {
+            properties.insert(property::TAGS.to_owned(), self.tag.clone());
+        }
+        if !self.keys.is_empty() {
+            properties.insert(property::KEYS.to_owned(), self.keys.join(" "));
+        }
+        encode_properties(&properties)
+    }
+}
+
+/// Suffix of the topic replies are sent to, one per cluster, which brokers create automatically.
+const REPLY_TOPIC_POSTFIX: &str = "_REPLY_TOPIC";
+
+/// Value of the `MSG_TYPE` property marking replies, which publishers send through SEND_REPLY_MESSAGE.
+pub(crate) const REPLY_MESSAGE_FLAG: &str = "reply";
+
+/// Properties scheduling a message, only one of which is set at a time.
+const DELAY_PROPERTIES: &[&str] = &[
+    property::DELAY_TIME_LEVEL,
+    property::TIMER_DELIVER_MS,
+    property::TIMER_DELAY_SEC,
+];
+
+fn check_timer_delay(delay: Duration) -> Result<(), ClientError> {
+    if delay > MAX_TIMER_DELAY {
+        return Err(ClientError::InvalidDelay(format!(
+            "delay {:?} exceeds the maximum {:?}",
+            delay, MAX_TIMER_DELAY
+        )));
+    }
+    Ok(())
+}
+
+/// Encode messages of a batch, each in the layout brokers split batches by, as the body of SEND_BATCH_MESSAGE.
+///
+/// # Errors
+/// Raise ClientError::InvalidBatch if the batch is empty, spans topics, targets a retry topic, holds delayed
+/// messages or holds messages whose properties do not fit in 2 bytes of length, none of which brokers support.
+pub(crate) fn encode_batch(messages: &[Message]) -> Result<bytes::Bytes, ClientError> {
+    let topic = match messages.first() {
+        Some(message) => &message.topic,
+        None => return Err(ClientError::InvalidBatch("batch is empty".to_owned())),
+    };
+    if topic.starts_with(RETRY_GROUP_TOPIC_PREFIX) {
+        return Err(ClientError::InvalidBatch(
+            "retry topics are not supported".to_owned(),
+        ));
+    }
+    let mut buf = bytes::BytesMut::new();
+    for message in messages {
+        if &message.topic != topic {
+            return Err(ClientError::InvalidBatch(
+                "messages of a batch must share the same topic".to_owned(),
+            ));
+        }
+        if message.is_delayed() {
+            return Err(ClientError::InvalidBatch(
+                "delayed messages are not supported".to_owned(),
+            ));
+        }
+        let properties = message.encode_properties();
+        if properties.len() > i16::MAX as usize {
+            return Err(ClientError::InvalidBatch(
+                "properties of a message exceed 32767 bytes".to_owned(),
+            ));
+        }
+        let total_size = 4 + 4 + 4 + 4 + 4 + message.body.len() + 2 + properties.len();
+        buf.put_i32(total_size as i32);
+        // Magic code and body CRC, both ignored by brokers.
+        buf.put_i32(0);
+        buf.put_i32(0);
+        // Flag
+        buf.put_i32(0);
+        buf.put_i32(message.body.len() as i32);
+        buf.put_slice(&message.body);
+        buf.put_i16(properties.len() as i16);
+        buf.put_slice(properties.as_bytes());
+    }
+    Ok(buf.freeze())
+}
+
+/// Bit of `sys_flag` marking the born host as IPv6, stored in 16 bytes rather than 4.
+pub(crate) const BORNHOST_V6_FLAG: i32 = 1 << 4;
+
+/// Bit of `sys_flag` marking the store host as IPv6, stored in 16 bytes rather than 4.
+pub(crate) const STOREHOST_V6_FLAG: i32 = 1 << 5;
+
+/// Decode an offset message id, as brokers assign, into the address of the broker storing the message and the
+/// offset of the message in its commit log.
+///
+/// # Errors
+/// Raise ClientError::InvalidMessageId if the id is not the upper-case hex of an IPv4 or IPv6 address, a port and an
+/// offset.
+pub fn decode_offset_msg_id(offset_msg_id: &str) -> Result<(SocketAddr, i64), ClientError> {
+    let invalid = || ClientError::InvalidMessageId(offset_msg_id.to_owned());
+    let bytes = (0..offset_msg_id.len())
+        .step_by(2)
+        .map(|i| {
+            offset_msg_id
+                .get(i..i + 2)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(invalid)?;
+    let mut buf = bytes::Bytes::from(bytes);
+    let ip = match buf.len() {
+        16 => IpAddr::V4(Ipv4Addr::from(buf.get_u32())),
+        28 => IpAddr::V6(Ipv6Addr::from(buf.get_u128())),
+        _ => return Err(invalid()),
+    };
+    let port = buf.get_i32();
+    let offset = buf.get_i64();
+    Ok((SocketAddr::new(ip, port as u16), offset))
+}
+
+/// Prefix of the topic that messages failed to consume are sent back to, one per consumer group.
+pub(crate) const RETRY_GROUP_TOPIC_PREFIX: &str = "%RETRY%";
+
+/// Prefix of the topic holding messages that exhausted their retries, one per consumer group.
+pub(crate) const DLQ_GROUP_TOPIC_PREFIX: &str = "%DLQ%";
+
+/// Name of the retry topic of a consumer group.
+pub fn retry_topic(group: &str) -> String {
+    format!("{}{}", RETRY_GROUP_TOPIC_PREFIX, group)
+}
+
+/// Name of the dead letter topic of a consumer group.
+pub fn dlq_topic(group: &str) -> String {
+    format!("{}{}", DLQ_GROUP_TOPIC_PREFIX, group)
+}
+
+/// Keys of system properties that are shared with brokers and other language SDKs.
+pub(crate) mod property {
+    pub(crate) const KEYS: &str = "KEYS";
+    pub(crate) const TAGS: &str = "TAGS";
+    pub(crate) const WAIT_STORE_MSG_OK: &str = "WAIT";
+    pub(crate) const DELAY_TIME_LEVEL: &str = "DELAY";
+    pub(crate) const RETRY_TOPIC: &str = "RETRY_TOPIC";
+    pub(crate) const REAL_TOPIC: &str = "REAL_TOPIC";
+    pub(crate) const REAL_QUEUE_ID: &str = "REAL_QID";
+    pub(crate) const TRANSACTION_PREPARED: &str = "TRAN_MSG";
+    pub(crate) const PRODUCER_GROUP: &str = "PGROUP";
+    pub(crate) const MIN_OFFSET: &str = "MIN_OFFSET";
+    pub(crate) const MAX_OFFSET: &str = "MAX_OFFSET";
+    pub(crate) const BUYER_ID: &str = "BUYER_ID";
+    pub(crate) const ORIGIN_MESSAGE_ID: &str = "ORIGIN_MESSAGE_ID";
+    pub(crate) const TRANSFER_FLAG: &str = "TRANSFER_FLAG";
+    pub(crate) const CORRECTION_FLAG: &str = "CORRECTION_FLAG";
+    pub(crate) const MQ2_FLAG: &str = "MQ2_FLAG";
+    pub(crate) const RECONSUME_TIME: &str = "RECONSUME_TIME";
+    pub(crate) const MSG_REGION: &str = "MSG_REGION";
+    pub(crate) const TRACE_SWITCH: &str = "TRACE_ON";
+    pub(crate) const UNIQ_CLIENT_MESSAGE_ID_KEYIDX: &str = "UNIQ_KEY";
+    pub(crate) const MAX_RECONSUME_TIMES: &str = "MAX_RECONSUME_TIMES";
+    pub(crate) const CONSUME_START_TIMESTAMP: &str = "CONSUME_START_TIME";
+    pub(crate) const POP_CK: &str = "POP_CK";
+
+    /// Topic a message is popped from, which is a retry topic for redelivered messages. Kept by the client only.
+    pub(crate) const POP_TOPIC: &str = "POP_TOPIC";
+    pub(crate) const FIRST_POP_TIME: &str = "1ST_POP_TIME";
+    pub(crate) const TIMER_DELIVER_MS: &str = "TIMER_DELIVER_MS";
+    pub(crate) const TIMER_DELAY_SEC: &str = "TIMER_DELAY_SEC";
+    pub(crate) const TIMER_OUT_MS: &str = "TIMER_OUT_MS";
+    pub(crate) const CLUSTER: &str = "CLUSTER";
+    pub(crate) const MESSAGE_TYPE: &str = "MSG_TYPE";
+    pub(crate) const CORRELATION_ID: &str = "CORRELATION_ID";
+    pub(crate) const REPLY_TO_CLIENT: &str = "REPLY_TO_CLIENT";
+    pub(crate) const MESSAGE_TTL: &str = "TTL";
+
+    /// Properties that are reserved by the system rather than defined by applications.
+    pub(crate) const SYSTEM_PROPERTIES: &[&str] = &[
+        KEYS,
+        TAGS,
+        WAIT_STORE_MSG_OK,
+        DELAY_TIME_LEVEL,
+        RETRY_TOPIC,
+        REAL_TOPIC,
+        REAL_QUEUE_ID,
+        TRANSACTION_PREPARED,
+        PRODUCER_GROUP,
+        MIN_OFFSET,
+        MAX_OFFSET,
+        BUYER_ID,
+        ORIGIN_MESSAGE_ID,
+        TRANSFER_FLAG,
+        CORRECTION_FLAG,
+        MQ2_FLAG,
+        RECONSUME_TIME,
+        MSG_REGION,
+        TRACE_SWITCH,
+        UNIQ_CLIENT_MESSAGE_ID_KEYIDX,
+        MAX_RECONSUME_TIMES,
+        CONSUME_START_TIMESTAMP,
+        POP_CK,
+        FIRST_POP_TIME,
+        TIMER_DELIVER_MS,
+        TIMER_DELAY_SEC,
+        TIMER_OUT_MS,
+        CLUSTER,
+        MESSAGE_TYPE,
+        CORRELATION_ID,
+        REPLY_TO_CLIENT,
+        MESSAGE_TTL,
+    ];
+}
+
+const NAME_VALUE_SEPARATOR: char = '\u{1}';
+const PROPERTY_SEPARATOR: char = '\u{2}';
+
+/// Decode properties serialized in form of `key\u{1}value\u{2}key\u{1}value\u{2}`.
+pub(crate) fn decode_properties(data: &str) -> HashMap<String, String> {
+    data.split(PROPERTY_SEPARATOR)
+        .filter_map(|item| item.split_once(NAME_VALUE_SEPARATOR))
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect()
+}
+
+/// Encode properties in form of `key\u{1}value\u{2}key\u{1}value\u{2}`.
+pub(crate) fn encode_properties(properties: &HashMap<String, String>) -> String {
+    properties
+        .iter()
+        .map(|(k, v)| format!("{}{}{}{}", k, NAME_VALUE_SEPARATOR, v, PROPERTY_SEPARATOR))
+        .collect()
+}
+
+/// A message queue is the smallest unit of a topic that messages are load-balanced among.
+///
+/// Queues are ordered by topic, broker name and queue id, so that all members of a group see them in the same order.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageQueue {
+    pub topic: String,
+    pub broker_name: String,
+    pub queue_id: i32,
+}
+
+impl fmt::Display for MessageQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MessageQueue [topic={}, brokerName={}, queueId={}]",
+            self.topic, self.broker_name, self.queue_id
+        )
+    }
+}
+
+/// A message that has been stored by broker and delivered to subscribers, along with its store metadata.
+#[derive(Debug, Clone)]
+pub struct MessageExt {
+    pub topic: String,
+
+    pub tag: String,
+
+    pub keys: Vec<String>,
+
+    /// User defined attributes in form of key-value pairs.
+    pub attributes: HashMap<String, String>,
+
+    /// System properties.
+    pub(crate) properties: HashMap<String, String>,
+
+    pub body: bytes::Bytes,
+
+    /// Message identifier assigned by publisher, falling back to `offset_msg_id` if absent.
+    pub msg_id: String,
+
+    /// Identifier encoding store host and commit log offset of the message.
+    pub offset_msg_id: String,
+
+    pub broker_name: String,
+
+    pub queue_id: i32,
+
+    pub queue_offset: i64,
+
+    pub commit_log_offset: i64,
+
+    pub sys_flag: i32,
+
+    pub born_timestamp: i64,
+
+    pub born_host: SocketAddr,
+
+    pub store_timestamp: i64,
+
+    pub store_host: SocketAddr,
+
+    /// Number of times this message has been re-delivered to consumers.
+    pub reconsume_times: i32,
+}
+
+impl MessageExt {
+    /// Decode all messages carried by a pull response body.
+    ///
+    /// # Errors
+    /// Raise ClientError::InvalidFrame if the body is truncated or malformed.
+    pub(crate) fn decode_batch(mut data: bytes::Bytes) -> Result<Vec<MessageExt>, ClientError> {
+        let mut messages = vec![];
+        while data.has_remaining() {
+            messages.push(MessageExt::decode(&mut data)?);
+        }
+        Ok(messages)
+    }
+
+    fn decode(src: &mut bytes::Bytes) -> Result<MessageExt, ClientError> {
+        let truncated = || ClientError::InvalidFrame("Truncated message".to_owned());
+        if src.remaining() < 4 {
+            return Err(truncated());
+        }
+        let store_size = src.get_i32();
+        if store_size < 4 || src.remaining() < store_size as usize - 4 {
+            return Err(truncated());
+        }
+        let mut buf = src.split_to(store_size as usize - 4);
+
+        // Fixed-length fields from magic code through sys flag.
+        if buf.remaining() < 4 * 4 + 8 * 2 + 4 {
+            return Err(truncated());
+        }
+        let _magic_code = buf.get_i32();
+        let _body_crc = buf.get_i32();
+        let queue_id = buf.get_i32();
+        let _flag = buf.get_i32();
+        let queue_offset = buf.get_i64();
+        let commit_log_offset = buf.get_i64();
+        let sys_flag = buf.get_i32();
+
+        // Hosts take 16 bytes of IPv6 address plus port, or 4 bytes of IPv4 address plus port.
+        let host_length = |flag| if sys_flag & flag != 0 { 16 + 4 } else { 4 + 4 };
+        let born_host_length = host_length(BORNHOST_V6_FLAG);
+        let store_host_length = host_length(STOREHOST_V6_FLAG);
+        if buf.remaining() < 8 + born_host_length + 8 + store_host_length + 4 + 8 + 4 {
+            return Err(truncated());
+        }
+        let born_timestamp = buf.get_i64();
+        let born_host = MessageExt::decode_host(&mut buf, sys_flag & BORNHOST_V6_FLAG != 0);
+        let store_timestamp = buf.get_i64();
+        let store_host = MessageExt::decode_host(&mut buf, sys_flag & STOREHOST_V6_FLAG != 0);
+        let reconsume_times = buf.get_i32();
+        let _prepared_transaction_offset = buf.get_i64();
+
+        let body_length = usize::try_from(buf.get_i32()).map_err(|_e| truncated())?;
+        match body_length.checked_add(1) {
+            Some(length) if buf.remaining() >= length => {}
+            _ => return Err(truncated()),
+        }
+        let body = compression::decompress(buf.split_to(body_length), sys_flag)?;
+
+        let topic_length = buf.get_u8() as usize;
+        if buf.remaining() < topic_length {
+            return Err(truncated());
+        }
+        let topic = String::from_utf8_lossy(&buf.split_to(topic_length)).into_owned();
+
+        let mut properties = HashMap::new();
+        if buf.remaining() >= 2 {
+            let properties_length = buf.get_i16() as usize;
+            if buf.remaining() < properties_length {
+                return Err(truncated());
+            }
+            properties =
+                decode_properties(&String::from_utf8_lossy(&buf.split_to(properties_length)));
+        }
+
+        let message = MessageExt::from_properties(topic, properties, body);
+        let offset_msg_id = MessageExt::offset_msg_id(&store_host, commit_log_offset);
+        Ok(MessageExt {
+            msg_id: if message.msg_id.is_empty() {
+                offset_msg_id.clone()
+            } else {
+                message.msg_id
+            },
+            offset_msg_id,
+            queue_id,
+            queue_offset,
+            commit_log_offset,
+            sys_flag,
+            born_timestamp,
+            born_host,
+            store_timestamp,
+            store_host,
+            reconsume_times,
+            ..message
+        })
+    }
+
+    /// Build a message from properties in wire form, splitting system properties from user attributes. Store
+    /// metadata are left unset.
+    pub(crate) fn from_properties(
+        topic: String,
+        properties: HashMap<String, String>,
+        body: bytes::Bytes,
+    ) -> MessageExt {
+        let (properties, attributes): (HashMap<_, _>, HashMap<_, _>) = properties
+            .into_iter()
+            .partition(|(k, _)| property::SYSTEM_PROPERTIES.contains(&k.as_str()));
+        let tag = properties.get(property::TAGS).cloned().unwrap_or_default();
+        let keys = properties
+            .get(property::KEYS)
+            .map(|keys| keys.split(' ').map(str::to_owned).collect())
+            .unwrap_or_default();
+        let msg_id = properties
+            .get(property::UNIQ_CLIENT_MESSAGE_ID_KEYIDX)
+            .cloned()
+            .unwrap_or_default();
+        let unknown_host = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+        MessageExt {
+            topic,
+            tag,
+            keys,
+            attributes,
+            properties,
+            body,
+            msg_id,
+            offset_msg_id: String::new(),
+            broker_name: String::new(),
+            queue_id: 0,
+            queue_offset: 0,
+            commit_log_offset: 0,
+            sys_flag: 0,
+            born_timestamp: 0,
+            born_host: unknown_host,
+            store_timestamp: 0,
+            store_host: unknown_host,
+            reconsume_times: 0,
+        }
+    }
+
+    fn decode_host(buf: &mut bytes::Bytes, v6: bool) -> SocketAddr {
+        let ip = if v6 {
+            IpAddr::V6(Ipv6Addr::from(buf.get_u128()))
+        } else {
+            IpAddr::V4(Ipv4Addr::from(buf.get_u32()))
+        };
+        let port = buf.get_i32();
+        SocketAddr::new(ip, port as u16)
+    }
+
+    /// Offset message id is the upper-case hex of store IP, store port and commit log offset.
+    fn offset_msg_id(store_host: &SocketAddr, commit_log_offset: i64) -> String {
+        let mut id = String::new();
+        match store_host.ip() {
+            IpAddr::V4(ip) => ip.octets().iter().for_each(|b| id += &format!("{:02X}", b)),
+            IpAddr::V6(ip) => ip.octets().iter().for_each(|b| id += &format!("{:02X}", b)),
+        }
+        id += &format!("{:08X}", store_host.port() as i32);
+        id += &format!("{:016X}", commit_log_offset);
+        id
+    }
+
+    /// Look up a system property of this message.
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+
+    /// Restore the original topic of a message redelivered through the retry topic of `group`.
+    pub(crate) fn reset_retry_topic(&mut self, group: &str) {
+        if self.topic == retry_topic(group) {
+            if let Some(topic) = self.properties.get(property::RETRY_TOPIC) {
+                self.topic = topic.clone();
+            }
+        }
+    }
+
+    /// Receipt handle of a message received by `SimpleConsumer`, with which it is acknowledged or kept invisible
+    /// for longer. `None` for messages pulled by other consumers.
+    pub fn receipt_handle(&self) -> Option<ReceiptHandle> {
+        let extra_info = self.properties.get(property::POP_CK)?;
+        let popped_topic = self
+            .properties
+            .get(property::POP_TOPIC)
+            .unwrap_or(&self.topic);
+        ReceiptHandle::parse(&self.topic, popped_topic, extra_info).ok()
+    }
+
+    /// When a timer message was scheduled to be delivered by its publisher, if it was.
+    ///
+    /// Messages delayed by level carry no such time, as levels are mapped to delays by broker configuration.
+    pub fn scheduled_delivery_time(&self) -> Option<SystemTime> {
+        let millis = |key| {
+            self.properties
+                .get(key)
+                .and_then(|value: &String| value.parse::<u64>().ok())
+        };
+        let millis = millis(property::TIMER_OUT_MS)
+            .or_else(|| millis(property::TIMER_DELIVER_MS))
+            .or_else(|| {
+                let secs = millis(property::TIMER_DELAY_SEC)?;
+                let born_timestamp = u64::try_from(self.born_timestamp).ok()?;
+                born_timestamp.checked_add(secs.checked_mul(1000)?)
+            })?;
+        UNIX_EPOCH.checked_add(Duration::from_millis(millis))
+    }
+
+    /// Create the reply to a message sent by `Publisher::request`, to be published by any publisher of the cluster.
+    ///
+    /// # Errors
+    /// Raise ClientError::NotRequest if the message is not a request, or was not stored by a broker of RocketMQ
+    /// 4.6 or later, which stamps the cluster replies go through.
+    pub fn create_reply(&self, body: impl Into<bytes::Bytes>) -> Result<Message, ClientError> {
+        let get = |key| {
+            self.properties
+                .get(key)
+                .ok_or_else(|| ClientError::NotRequest(self.msg_id.clone()))
+        };
+        let cluster = get(property::CLUSTER)?;
+        let mut reply = Message::new(&format!("{}{}", cluster, REPLY_TOPIC_POSTFIX), body);
+        for key in [
+            property::CORRELATION_ID,
+            property::REPLY_TO_CLIENT,
+            property::MESSAGE_TTL,
+        ] {
+            reply.properties.insert(key.to_owned(), get(key)?.clone());
+        }
+        reply.properties.insert(
+            property::MESSAGE_TYPE.to_owned(),
+            REPLY_MESSAGE_FLAG.to_owned(),
+        );
+        Ok(reply)
+    }
+
+    /// Identify the message queue this message is stored in.
+    pub fn message_queue(&self) -> MessageQueue {
+        MessageQueue {
+            topic: self.topic.clone(),
+            broker_name: self.broker_name.clone(),
+            queue_id: self.queue_id,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use bytes::{BufMut, BytesMut};
+
+    /// Encode a message in the broker store layout, as carried by pull responses.
+    pub(crate) fn encode_stored(
+        topic: &str,
+        queue_offset: i64,
+        properties: &[(&str, &str)],
+        body: &[u8],
+    ) -> bytes::Bytes {
+        let props: String = properties
+            .iter()
+            .map(|(k, v)| format!("{}{}{}{}", k, NAME_VALUE_SEPARATOR, v, PROPERTY_SEPARATOR))
+            .collect();
+        let mut buf = BytesMut::new();
+        buf.put_i32(0);
+        buf.put_i32(0xdaa320a7u32 as i32);
+        buf.put_i32(0);
+        buf.put_i32(1);
+        buf.put_i32(0);
+        buf.put_i64(queue_offset);
+        buf.put_i64(1024 + queue_offset);
+        buf.put_i32(0);
+        buf.put_i64(1000);
+        buf.put_slice(&[127, 0, 0, 1]);
+        buf.put_i32(5000);
+        buf.put_i64(2000);
+        buf.put_slice(&[10, 0, 0, 1]);
+        buf.put_i32(10911);
+        buf.put_i32(0);
+        buf.put_i64(0);
+        buf.put_i32(body.len() as i32);
+        buf.put_slice(body);
+        buf.put_u8(topic.len() as u8);
+        buf.put_slice(topic.as_bytes());
+        buf.put_i16(props.len() as i16);
+        buf.put_slice(props.as_bytes());
+        let len = buf.len() as i32;
+        (&mut buf[0..4]).put_i32(len);
+        buf.freeze()
+    }
+
+    #[test]
+    fn test_decode_properties() {
+        let properties = decode_properties("TAGS\u{1}TagA\u{2}KEYS\u{1}k1 k2\u{2}");
+        assert_eq!(properties.len(), 2);
+        assert_eq!(properties.get("TAGS"), Some(&"TagA".to_owned()));
+    }
+
+    #[test]
+    fn test_decode_batch() -> Result<(), ClientError> {
+        let mut data = BytesMut::new();
+        data.put(encode_stored(
+            "T1",
+            7,
+            &[("TAGS", "TagA"), ("KEYS", "k1 k2"), ("region", "eu")],
+            b"hello",
+        ));
+        data.put(encode_stored("T1", 8, &[], b"world"));
+        let messages = MessageExt::decode_batch(data.freeze())?;
+        assert_eq!(messages.len(), 2);
+
+        let first = &messages[0];
+        assert_eq!(first.topic, "T1");
+        assert_eq!(first.queue_id, 1);
+        assert_eq!(first.queue_offset, 7);
+        assert_eq!(first.tag, "TagA");
+        assert_eq!(first.keys, vec!["k1".to_owned(), "k2".to_owned()]);
+        assert_eq!(first.attributes.get("region"), Some(&"eu".to_owned()));
+        assert!(first.property("region").is_none());
+        assert_eq!(first.body, bytes::Bytes::from_static(b"hello"));
+        assert_eq!(first.store_host, "10.0.0.1:10911".parse().unwrap());
+        assert_eq!(first.offset_msg_id, "0A00000100002A9F0000000000000407");
+        assert_eq!(first.msg_id, first.offset_msg_id);
+
+        assert_eq!(messages[1].queue_offset, 8);
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_properties() {
+        let mut message = Message::new("T1", "body");
+        message.tag = "TagA".to_owned();
+        message.keys = vec!["k1".to_owned(), "k2".to_owned()];
+        message
+            .attributes
+            .insert("region".to_owned(), "eu".to_owned());
+        let properties = decode_properties(&message.encode_properties());
+        assert_eq!(properties.len(), 3);
+        assert_eq!(properties.get("KEYS"), Some(&"k1 k2".to_owned()));
+        assert_eq!(properties.get("region"), Some(&"eu".to_owned()));
+    }
+
+    #[test]
+    fn test_delay() -> Result<(), ClientError> {
+        let message = Message::new("T1", "body").with_delay_level(3)?;
+        assert_eq!(message.properties.get("DELAY"), Some(&"3".to_owned()));
+        assert!(matches!(
+            Message::new("T1", "body").with_delay_level(19),
+            Err(ClientError::InvalidDelay(_))
+        ));
+
+        // Scheduling replaces the delay set before.
+        let message = message.with_delay(Duration::from_secs(90))?;
+        assert!(!message.properties.contains_key("DELAY"));
+        assert_eq!(
+            message.properties.get("TIMER_DELAY_SEC"),
+            Some(&"90".to_owned())
+        );
+        let message = message.with_deliver_time(UNIX_EPOCH + Duration::from_millis(1234))?;
+        assert!(!message.properties.contains_key("TIMER_DELAY_SEC"));
+        assert_eq!(
+            message.properties.get("TIMER_DELIVER_MS"),
+            Some(&"1234".to_owned())
+        );
+        assert!(!message.with_delay_level(0)?.is_delayed());
+
+        let message = Message::new("T1", "body");
+        assert!(message.with_delay(Duration::from_millis(500)).is_err());
+        let message = Message::new("T1", "body");
+        assert!(message.with_delay(MAX_TIMER_DELAY * 2).is_err());
+        let message = Message::new("T1", "body");
+        let too_late = SystemTime::now() + MAX_TIMER_DELAY * 2;
+        assert!(message.with_deliver_time(too_late).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_scheduled_delivery_time() -> Result<(), ClientError> {
+        let data = encode_stored("T1", 0, &[("TIMER_DELIVER_MS", "5000")], b"body");
+        let message = MessageExt::decode_batch(data)?.remove(0);
+        let expected = UNIX_EPOCH + Duration::from_millis(5000);
+        assert_eq!(message.scheduled_delivery_time(), Some(expected));
+
+        // Born at 1000 ms since the epoch.
+        let data = encode_stored("T1", 0, &[("TIMER_DELAY_SEC", "4")], b"body");
+        let message = MessageExt::decode_batch(data)?.remove(0);
+        assert_eq!(message.scheduled_delivery_time(), Some(expected));
+
+        let data = encode_stored("T1", 0, &[("DELAY", "3")], b"body");
+        let message = MessageExt::decode_batch(data)?.remove(0);
+        assert_eq!(message.scheduled_delivery_time(), None);
+
+        // Times out of range are dropped rather than overflowing.
+        let data = encode_stored(
+            "T1",
+            0,
+            &[("TIMER_DELAY_SEC", &u64::MAX.to_string())],
+            b"body",
+        );
+        let message = MessageExt::decode_batch(data)?.remove(0);
+        assert_eq!(message.scheduled_delivery_time(), None);
+        let data = encode_stored("T1", 0, &[("TIMER_DELAY_SEC", "4")], b"body");
+        let mut message = MessageExt::decode_batch(data)?.remove(0);
+        message.born_timestamp = -1;
+        assert_eq!(message.scheduled_delivery_time(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_batch() -> Result<(), ClientError> {
+        let mut first = Message::new("T1", "hello");
+        first.tag = "TagA".to_owned();
+        let data = encode_batch(&[first, Message::new("T1", "world!")])?;
+        let mut buf = data.clone();
+        let size = buf.get_i32() as usize;
+        assert_eq!(size, 20 + 5 + 2 + "TAGS\u{1}TagA\u{2}".len());
+        buf.advance(12);
+        assert_eq!(buf.get_i32(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(data.len(), size + 20 + 6 + 2);
+
+        assert!(encode_batch(&[]).is_err());
+        let mixed = [Message::new("T1", "a"), Message::new("T2", "b")];
+        assert!(matches!(
+            encode_batch(&mixed),
+            Err(ClientError::InvalidBatch(_))
+        ));
+        let delayed = [
+            Message::new("T1", "a"),
+            Message::new("T1", "b").with_delay(Duration::from_secs(10))?,
+        ];
+        assert!(matches!(
+            encode_batch(&delayed),
+            Err(ClientError::InvalidBatch(_))
+        ));
+        assert!(encode_batch(&[Message::new("%RETRY%G1", "a")]).is_err());
+        let mut large = Message::new("T1", "a");
+        large.keys = vec!["k".repeat(i16::MAX as usize)];
+        assert!(matches!(
+            encode_batch(&[large]),
+            Err(ClientError::InvalidBatch(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_compressed() -> Result<(), ClientError> {
+        let body = "hello ".repeat(1000);
+        let (compressed, sys_flag) =
+            compression::compress(body.as_bytes(), compression::CompressionType::Zlib, 5)?;
+        let mut data = BytesMut::from(&encode_stored("T1", 0, &[], &compressed)[..]);
+        // Offset of sys_flag in the store layout.
+        (&mut data[36..40]).put_i32(sys_flag);
+        let message = MessageExt::decode_batch(data.freeze())?.remove(0);
+        assert_eq!(message.body, body.as_bytes());
+        assert_eq!(message.sys_flag, sys_flag);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_ipv6_host() -> Result<(), ClientError> {
+        let stored = encode_stored("T1", 0, &[], b"body");
+        let ip: Ipv6Addr = "fd00::1".parse().unwrap();
+        // Replace the IPv4 store host at offset 64 of the store layout.
+        let mut data = BytesMut::from(&stored[..64]);
+        data.put_slice(&ip.octets());
+        data.put_slice(&stored[68..]);
+        let len = data.len() as i32;
+        (&mut data[0..4]).put_i32(len);
+        (&mut data[36..40]).put_i32(STOREHOST_V6_FLAG);
+
+        let message = MessageExt::decode_batch(data.freeze())?.remove(0);
+        assert_eq!(message.born_host, "127.0.0.1:5000".parse().unwrap());
+        assert_eq!(message.store_host, "[fd00::1]:10911".parse().unwrap());
+        assert_eq!(message.body, "body");
+        assert_eq!(
+            message.offset_msg_id,
+            "FD00000000000000000000000000000100002A9F0000000000000400"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_offset_msg_id() -> Result<(), ClientError> {
+        let (store_host, offset) = decode_offset_msg_id("0A00000100002A9F0000000000000407")?;
+        assert_eq!(store_host, "10.0.0.1:10911".parse().unwrap());
+        assert_eq!(offset, 1031);
+        let id = MessageExt::offset_msg_id(&"[fd00::1]:10911".parse().unwrap(), 1024);
+        assert_eq!(
+            decode_offset_msg_id(&id)?,
+            ("[fd00::1]:10911".parse().unwrap(), 1024)
+        );
+        for id in [
+            "",
+            "0A00000100002A9F00000000000004",
+            "0A00000100002A9F000000000000040Z",
+        ] {
+            assert!(matches!(
+                decode_offset_msg_id(id),
+                Err(ClientError::InvalidMessageId(_))
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_reply() -> Result<(), ClientError> {
+        let data = encode_stored(
+            "T1",
+            0,
+            &[
+                ("CLUSTER", "C1"),
+                ("CORRELATION_ID", "c1"),
+                ("REPLY_TO_CLIENT", "127.0.0.1@1"),
+                ("TTL", "3000"),
+            ],
+            b"request",
+        );
+        let request = MessageExt::decode_batch(data)?.remove(0);
+        let reply = request.create_reply("response")?;
+        assert_eq!(reply.topic, "C1_REPLY_TOPIC");
+        assert!(reply.is_reply());
+        assert_eq!(reply.properties["CORRELATION_ID"], "c1");
+        assert_eq!(reply.properties["REPLY_TO_CLIENT"], "127.0.0.1@1");
+        assert_eq!(reply.properties["TTL"], "3000");
+
+        let data = encode_stored("T1", 0, &[("CLUSTER", "C1")], b"request");
+        let message = MessageExt::decode_batch(data)?.remove(0);
+        assert!(matches!(
+            message.create_reply("response"),
+            Err(ClientError::NotRequest(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_retry_topic() -> Result<(), ClientError> {
+        let data = encode_stored("%RETRY%G1", 0, &[("RETRY_TOPIC", "T1")], b"body");
+        let mut message = MessageExt::decode_batch(data)?.remove(0);
+        message.reset_retry_topic("G2");
+        assert_eq!(message.topic, "%RETRY%G1");
+        message.reset_retry_topic("G1");
+        assert_eq!(message.topic, "T1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let data = encode_stored("T1", 7, &[], b"hello");
+        assert!(MessageExt::decode_batch(data.slice(0..data.len() - 3)).is_err());
+
+        // A negative body length is rejected rather than overflowing.
+        let mut data = BytesMut::from(&data[..]);
+        (&mut data[84..88]).put_i32(-1);
+        assert!(MessageExt::decode_batch(data.freeze()).is_err());
+    }
 }
@@ -0,0 +1,511 @@
+//!
+//! Define `OffsetStore`, which keeps consumption progress of each message queue, along with implementations backed by
+//! brokers and by local files.
+//!
+use crate::client::Client;
+use crate::error::ClientError;
+use crate::frame::{Frame, RequestCode, ResponseCode};
+use crate::message::MessageQueue;
+use crate::protocol;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Where `OffsetStore::read_offset` looks for the offset of a queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadOffsetType {
+    /// Offsets cached in memory only.
+    ReadFromMemory,
+
+    /// Persisted offsets only, bypassing and refreshing the cache.
+    ReadFromStore,
+
+    /// Offsets cached in memory, falling back to persisted ones.
+    MemoryFirstThenStore,
+}
+
+/// Consumption progress of message queues, kept per `(topic, broker_name, queue_id)`.
+///
+/// Offsets are updated in memory as messages are consumed and persisted in batches by `persist_all`.
+#[async_trait]
+pub trait OffsetStore: Send + Sync {
+    /// Load persisted offsets into memory.
+    async fn load(&self) -> Result<(), ClientError>;
+
+    /// Update offset of the queue in memory. With `increase_only`, a smaller offset never replaces a larger one.
+    fn update_offset(&self, mq: &MessageQueue, offset: i64, increase_only: bool);
+
+    /// Read offset of the queue, returning `None` if the group has not committed any offset of it.
+    async fn read_offset(
+        &self,
+        mq: &MessageQueue,
+        read_type: ReadOffsetType,
+    ) -> Result<Option<i64>, ClientError>;
+
+    /// Persist offsets of the given queues.
+    async fn persist_all(&self, mqs: &HashSet<MessageQueue>);
+
+    /// Persist offset of a single queue.
+    async fn persist(&self, mq: &MessageQueue);
+
+    /// Forget the cached offset of a queue that is no longer assigned to this client.
+    fn remove_offset(&self, mq: &MessageQueue);
+}
+
+fn update_table(
+    table: &Mutex<HashMap<MessageQueue, i64>>,
+    mq: &MessageQueue,
+    offset: i64,
+    increase_only: bool,
+) {
+    if let Ok(mut map) = table.lock() {
+        let entry = map.entry(mq.clone()).or_insert(offset);
+        if !increase_only || offset > *entry {
+            *entry = offset;
+        }
+    }
+}
+
+fn read_table(table: &Mutex<HashMap<MessageQueue, i64>>, mq: &MessageQueue) -> Option<i64> {
+    table.lock().ok().and_then(|map| map.get(mq).copied())
+}
+
+/// Offset store of clustering consumers, persisting progress to the broker hosting each queue so that it is
+/// shared by all members of the group.
+pub struct RemoteBrokerOffsetStore {
+    client: Arc<Client>,
+    group: String,
+    offsets: Mutex<HashMap<MessageQueue, i64>>,
+}
+
+impl RemoteBrokerOffsetStore {
+    pub(crate) fn new(client: Arc<Client>, group: &str) -> Self {
+        Self {
+            client,
+            group: group.to_owned(),
+            offsets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn master_addr(&self, mq: &MessageQueue) -> Result<String, ClientError> {
+        let route = self
+            .client
+            .routes
+            .get_or_query(
+                &mq.topic,
+                &self.client.connections,
+                self.client.option.request_timeout,
+            )
+            .await?;
+        route
+            .master_addr(&mq.broker_name)
+            .map(str::to_owned)
+            .ok_or_else(|| ClientError::BrokerNotFound(mq.broker_name.clone()))
+    }
+
+    async fn fetch_offset_from_broker(
+        &self,
+        mq: &MessageQueue,
+    ) -> Result<Option<i64>, ClientError> {
+        let addr = self.master_addr(mq).await?;
+        let header = protocol::QueryConsumerOffsetRequestHeader {
+            consumer_group: self.group.clone(),
+            topic: mq.topic.clone(),
+            queue_id: mq.queue_id,
+        };
+        let frame = Frame::request(RequestCode::QueryConsumerOffset, header);
+        let response = self
+            .client
+            .connections
+            .invoke(&addr, frame, self.client.option.request_timeout)
+            .await?;
+        if response.code == ResponseCode::QueryNotFound as i32 {
+            return Ok(None);
+        }
+        response.ensure_success()?;
+        let offset = protocol::OffsetResponseHeader::try_from(&response.ext_fields)?.offset;
+        Ok(Some(offset))
+    }
+
+    async fn update_offset_to_broker(
+        &self,
+        mq: &MessageQueue,
+        offset: i64,
+    ) -> Result<(), ClientError> {
+        let addr = self.master_addr(mq).await?;
+        let header = protocol::UpdateConsumerOffsetRequestHeader {
+            consumer_group: self.group.clone(),
+            topic: mq.topic.clone(),
+            queue_id: mq.queue_id,
+            commit_offset: offset,
+        };
+        let frame = Frame::request(RequestCode::UpdateConsumerOffset, header);
+        self.client.connections.invoke_oneway(&addr, frame).await
+    }
+}
+
+#[async_trait]
+impl OffsetStore for RemoteBrokerOffsetStore {
+    async fn load(&self) -> Result<(), ClientError> {
+        Ok(())
+    }
+
+    fn update_offset(&self, mq: &MessageQueue, offset: i64, increase_only: bool) {
+        update_table(&self.offsets, mq, offset, increase_only);
+    }
+
+    async fn read_offset(
+        &self,
+        mq: &MessageQueue,
+        read_type: ReadOffsetType,
+    ) -> Result<Option<i64>, ClientError> {
+        if read_type != ReadOffsetType::ReadFromStore {
+            let offset = read_table(&self.offsets, mq);
+            if offset.is_some() || read_type == ReadOffsetType::ReadFromMemory {
+                return Ok(offset);
+            }
+        }
+        let offset = self.fetch_offset_from_broker(mq).await?;
+        if let Some(offset) = offset {
+            self.update_offset(mq, offset, false);
+        }
+        Ok(offset)
+    }
+
+    async fn persist_all(&self, mqs: &HashSet<MessageQueue>) {
+        let offsets: Vec<(MessageQueue, i64)> = match self.offsets.lock() {
+            Ok(map) => map
+                .iter()
+                .filter(|(mq, _)| mqs.contains(mq))
+                .map(|(mq, offset)| (mq.clone(), *offset))
+                .collect(),
+            Err(_e) => return,
+        };
+        for (mq, offset) in offsets {
+            if let Err(e) = self.update_offset_to_broker(&mq, offset).await {
+                eprintln!("Failed to persist offset of {:?}. Cause: {}", mq, e);
+            }
+        }
+    }
+
+    async fn persist(&self, mq: &MessageQueue) {
+        if let Some(offset) = read_table(&self.offsets, mq) {
+            if let Err(e) = self.update_offset_to_broker(mq, offset).await {
+                eprintln!("Failed to persist offset of {:?}. Cause: {}", mq, e);
+            }
+        }
+    }
+
+    fn remove_offset(&self, mq: &MessageQueue) {
+        if let Ok(mut map) = self.offsets.lock() {
+            map.remove(mq);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OffsetEntry {
+    #[serde(flatten)]
+    mq: MessageQueue,
+    offset: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OffsetTable {
+    offset_table: Vec<OffsetEntry>,
+}
+
+/// Offset store of broadcasting consumers, persisting progress as JSON to a local file, as each client consumes
+/// all queues on its own.
+///
+/// The file is replaced atomically by renaming a freshly written temporary file, and the previous version is kept
+/// with a `.bak` suffix to recover from a corrupted file.
+pub struct LocalFileOffsetStore {
+    path: PathBuf,
+    offsets: Mutex<HashMap<MessageQueue, i64>>,
+}
+
+impl LocalFileOffsetStore {
+    /// Create a store persisting to `<dir>/<client_id>/<group>/offsets.json`.
+    pub fn new(dir: &Path, client_id: &str, group: &str) -> Self {
+        let path = dir.join(client_id).join(group).join("offsets.json");
+        Self {
+            path,
+            offsets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn backup_path(&self) -> PathBuf {
+        self.path.with_extension("json.bak")
+    }
+
+    async fn read_file(path: &Path) -> Result<Option<OffsetTable>, ClientError> {
+        match tokio::fs::read(path).await {
+            Ok(data) => serde_json::from_slice(&data)
+                .map(Some)
+                .map_err(|e| store_error(path, e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(store_error(path, e)),
+        }
+    }
+
+    /// Read the offset file, falling back to its backup if the file is missing or corrupted.
+    async fn read_table(&self) -> Result<OffsetTable, ClientError> {
+        match LocalFileOffsetStore::read_file(&self.path).await {
+            Ok(Some(table)) => Ok(table),
+            result => match LocalFileOffsetStore::read_file(&self.backup_path()).await {
+                Ok(Some(table)) => Ok(table),
+                Ok(None) => result.map(Option::unwrap_or_default),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    async fn write_table(&self, table: &OffsetTable) -> Result<(), ClientError> {
+        let path = &self.path;
+        let data = serde_json::to_vec_pretty(table).map_err(|e| store_error(path, e))?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|e| store_error(dir, e))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, data)
+            .await
+            .map_err(|e| store_error(&tmp, e))?;
+        if tokio::fs::metadata(path).await.is_ok() {
+            tokio::fs::rename(path, self.backup_path())
+                .await
+                .map_err(|e| store_error(path, e))?;
+        }
+        tokio::fs::rename(&tmp, path)
+            .await
+            .map_err(|e| store_error(path, e))?;
+        Ok(())
+    }
+}
+
+fn store_error(path: &Path, cause: impl std::fmt::Display) -> ClientError {
+    ClientError::OffsetStore(format!("{}: {}", path.display(), cause))
+}
+
+#[async_trait]
+impl OffsetStore for LocalFileOffsetStore {
+    async fn load(&self) -> Result<(), ClientError> {
+        let table = self.read_table().await?;
+        if let Ok(mut map) = self.offsets.lock() {
+            for entry in table.offset_table {
+                map.insert(entry.mq, entry.offset);
+            }
+        }
+        Ok(())
+    }
+
+    fn update_offset(&self, mq: &MessageQueue, offset: i64, increase_only: bool) {
+        update_table(&self.offsets, mq, offset, increase_only);
+    }
+
+    async fn read_offset(
+        &self,
+        mq: &MessageQueue,
+        read_type: ReadOffsetType,
+    ) -> Result<Option<i64>, ClientError> {
+        if read_type != ReadOffsetType::ReadFromStore {
+            let offset = read_table(&self.offsets, mq);
+            if offset.is_some() || read_type == ReadOffsetType::ReadFromMemory {
+                return Ok(offset);
+            }
+        }
+        let offset = self
+            .read_table()
+            .await?
+            .offset_table
+            .into_iter()
+            .find(|entry| &entry.mq == mq)
+            .map(|entry| entry.offset);
+        if let Some(offset) = offset {
+            self.update_offset(mq, offset, false);
+        }
+        Ok(offset)
+    }
+
+    /// Persist offsets of all queues cached in memory, whichever queues are given, keeping offsets of other queues
+    /// found on disk, since the file holds the offsets of every queue of the group.
+    async fn persist_all(&self, _mqs: &HashSet<MessageQueue>) {
+        let mut offsets: HashMap<MessageQueue, i64> = match self.read_table().await {
+            Ok(table) => table
+                .offset_table
+                .into_iter()
+                .map(|entry| (entry.mq, entry.offset))
+                .collect(),
+            Err(_e) => HashMap::new(),
+        };
+        match self.offsets.lock() {
+            Ok(map) => offsets.extend(map.iter().map(|(mq, offset)| (mq.clone(), *offset))),
+            Err(_e) => return,
+        }
+        let table = OffsetTable {
+            offset_table: offsets
+                .into_iter()
+                .map(|(mq, offset)| OffsetEntry { mq, offset })
+                .collect(),
+        };
+        if let Err(e) = self.write_table(&table).await {
+            eprintln!(
+                "Failed to persist offsets to {}. Cause: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+
+    async fn persist(&self, mq: &MessageQueue) {
+        let mut mqs = HashSet::new();
+        mqs.insert(mq.clone());
+        self.persist_all(&mqs).await;
+    }
+
+    fn remove_offset(&self, mq: &MessageQueue) {
+        if let Ok(mut map) = self.offsets.lock() {
+            map.remove(mq);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mq(queue_id: i32) -> MessageQueue {
+        MessageQueue {
+            topic: "T1".to_owned(),
+            broker_name: "b1".to_owned(),
+            queue_id,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_update_offset() -> Result<(), ClientError> {
+        let store = LocalFileOffsetStore::new(&temp_dir("update-offset"), "c1", "G1");
+        store.update_offset(&mq(0), 10, false);
+        store.update_offset(&mq(0), 5, true);
+        assert_eq!(
+            store
+                .read_offset(&mq(0), ReadOffsetType::ReadFromMemory)
+                .await?,
+            Some(10)
+        );
+        store.update_offset(&mq(0), 5, false);
+        assert_eq!(
+            store
+                .read_offset(&mq(0), ReadOffsetType::ReadFromMemory)
+                .await?,
+            Some(5)
+        );
+        assert_eq!(
+            store
+                .read_offset(&mq(1), ReadOffsetType::ReadFromMemory)
+                .await?,
+            None
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_local_file_persist_and_load() -> Result<(), ClientError> {
+        let dir = temp_dir("persist-offset");
+        let store = LocalFileOffsetStore::new(&dir, "c1", "G1");
+        store.update_offset(&mq(0), 10, false);
+        store.update_offset(&mq(1), 20, false);
+        let mqs: HashSet<MessageQueue> = [mq(0), mq(1)].into_iter().collect();
+        store.persist_all(&mqs).await;
+        store.update_offset(&mq(0), 11, false);
+        store.persist_all(&mqs).await;
+
+        let store = LocalFileOffsetStore::new(&dir, "c1", "G1");
+        store.load().await?;
+        assert_eq!(
+            store
+                .read_offset(&mq(0), ReadOffsetType::ReadFromMemory)
+                .await?,
+            Some(11)
+        );
+
+        // Corrupt the offset file; the backup of the previous version is used instead.
+        std::fs::write(&store.path, b"{").unwrap();
+        let store = LocalFileOffsetStore::new(&dir, "c1", "G1");
+        assert_eq!(
+            store
+                .read_offset(&mq(0), ReadOffsetType::ReadFromStore)
+                .await?,
+            Some(10)
+        );
+        assert_eq!(
+            store
+                .read_offset(&mq(1), ReadOffsetType::MemoryFirstThenStore)
+                .await?,
+            Some(20)
+        );
+
+        // Neither the file nor its backup is usable.
+        std::fs::write(store.backup_path(), b"{").unwrap();
+        let store = LocalFileOffsetStore::new(&dir, "c1", "G1");
+        assert!(matches!(
+            store
+                .read_offset(&mq(0), ReadOffsetType::ReadFromStore)
+                .await,
+            Err(ClientError::OffsetStore(_))
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_local_file_persist_single_queue() -> Result<(), ClientError> {
+        let dir = temp_dir("persist-single-offset");
+        let store = LocalFileOffsetStore::new(&dir, "c1", "G1");
+        store.update_offset(&mq(0), 10, false);
+        store.update_offset(&mq(1), 20, false);
+        store.persist(&mq(0)).await;
+        store.persist(&mq(0)).await;
+
+        let store = LocalFileOffsetStore::new(&dir, "c1", "G1");
+        store.load().await?;
+        assert_eq!(
+            store
+                .read_offset(&mq(1), ReadOffsetType::ReadFromMemory)
+                .await?,
+            Some(20)
+        );
+
+        // Offsets on disk of queues no longer cached in memory are kept too.
+        store.remove_offset(&mq(1));
+        store.update_offset(&mq(0), 11, false);
+        store.persist(&mq(0)).await;
+        let store = LocalFileOffsetStore::new(&dir, "c1", "G1");
+        assert_eq!(
+            store
+                .read_offset(&mq(0), ReadOffsetType::ReadFromStore)
+                .await?,
+            Some(11)
+        );
+        assert_eq!(
+            store
+                .read_offset(&mq(1), ReadOffsetType::ReadFromStore)
+                .await?,
+            Some(20)
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
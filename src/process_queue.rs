@@ -0,0 +1,428 @@
+//!
+//! Define ProcessQueue, the client side snapshot of a message queue, holding messages pulled but not yet consumed.
+//!
+use crate::client::current_millis;
+use crate::message::MessageExt;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Locks granted by brokers expire after this period unless renewed.
+pub(crate) const LOCK_MAX_LIVE_TIME: Duration = Duration::from_secs(30);
+
+struct Messages {
+    /// Messages pulled and waiting to be consumed, ordered by queue offset.
+    pending: BTreeMap<i64, MessageExt>,
+
+    /// Messages taken by the orderly consume task and being consumed.
+    consuming: BTreeMap<i64, MessageExt>,
+
+    /// Largest queue offset ever pulled.
+    max_offset: i64,
+
+    /// Total body size of pending and consuming messages, in bytes.
+    cached_size: usize,
+
+    /// When delivery of each message to a concurrent listener started.
+    consume_started: HashMap<i64, Instant>,
+}
+
+impl Messages {
+    /// Offset that is safe to commit: that of the lowest message not yet acknowledged, or the one past all pulled
+    /// messages if none is outstanding.
+    fn commit_offset(&self) -> i64 {
+        let pending = self.pending.keys().next();
+        let consuming = self.consuming.keys().next();
+        match (pending, consuming) {
+            (Some(a), Some(b)) => *a.min(b),
+            (Some(a), None) | (None, Some(a)) => *a,
+            (None, None) => self.max_offset + 1,
+        }
+    }
+
+    fn first_offset(&self) -> Option<i64> {
+        let pending = self.pending.keys().next();
+        let consuming = self.consuming.keys().next();
+        pending.into_iter().chain(consuming).min().copied()
+    }
+
+    fn last_offset(&self) -> Option<i64> {
+        let pending = self.pending.keys().next_back();
+        let consuming = self.consuming.keys().next_back();
+        pending.into_iter().chain(consuming).max().copied()
+    }
+}
+
+/// Snapshot of a process queue, for monitoring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessQueueStats {
+    /// Number of messages pulled but not yet consumed.
+    pub cached_msg_count: usize,
+
+    /// Total body size of cached messages, in bytes.
+    pub cached_msg_size: usize,
+
+    pub cached_msg_min_offset: Option<i64>,
+
+    pub cached_msg_max_offset: Option<i64>,
+
+    /// Distance between the offsets of the oldest and newest cached messages.
+    pub max_span: i64,
+
+    /// Next offset to pull from.
+    pub next_offset: i64,
+
+    /// Whether the broker side lock is held, which only matters to orderly consumption.
+    pub locked: bool,
+
+    pub dropped: bool,
+
+    /// Number of times pulling was delayed as thresholds of the queue were exceeded.
+    pub flow_control_times: u64,
+
+    /// Milliseconds since the Unix epoch when messages were last pulled, or 0 if never.
+    pub last_pull_timestamp: i64,
+
+    /// Milliseconds since the Unix epoch when messages were last delivered to the listener, or 0 if never.
+    pub last_consume_timestamp: i64,
+}
+
+pub(crate) struct ProcessQueue {
+    messages: Mutex<Messages>,
+
+    /// Offset from which the next pull starts.
+    next_offset: AtomicI64,
+
+    /// Set once the queue is no longer assigned to this client.
+    dropped: AtomicBool,
+
+    /// Whether this client holds the broker side lock of the queue.
+    locked: AtomicBool,
+
+    last_lock_time: Mutex<Instant>,
+
+    /// Held while messages of this queue are being consumed, so that consumption is strictly serial and
+    /// the queue is not unlocked in the middle of a consume attempt.
+    pub(crate) consume_lock: tokio::sync::Mutex<()>,
+
+    /// Signals the consume task that messages arrived or the lock state changed.
+    pub(crate) notify: Notify,
+
+    flow_control_times: AtomicU64,
+    last_pull_timestamp: AtomicI64,
+    last_consume_timestamp: AtomicI64,
+}
+
+impl ProcessQueue {
+    pub(crate) fn new(offset: i64) -> Self {
+        Self {
+            messages: Mutex::new(Messages {
+                pending: BTreeMap::new(),
+                consuming: BTreeMap::new(),
+                max_offset: offset - 1,
+                cached_size: 0,
+                consume_started: HashMap::new(),
+            }),
+            next_offset: AtomicI64::new(offset),
+            dropped: AtomicBool::new(false),
+            locked: AtomicBool::new(false),
+            last_lock_time: Mutex::new(Instant::now()),
+            consume_lock: tokio::sync::Mutex::new(()),
+            notify: Notify::new(),
+            flow_control_times: AtomicU64::new(0),
+            last_pull_timestamp: AtomicI64::new(0),
+            last_consume_timestamp: AtomicI64::new(0),
+        }
+    }
+
+    pub(crate) fn next_offset(&self) -> i64 {
+        self.next_offset.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_next_offset(&self, offset: i64) {
+        self.next_offset.store(offset, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_dropped(&self) -> bool {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_dropped(&self) {
+        self.dropped.store(true, Ordering::Relaxed);
+        self.notify.notify_one();
+    }
+
+    pub(crate) fn set_locked(&self, locked: bool) {
+        if locked {
+            if let Ok(mut time) = self.last_lock_time.lock() {
+                *time = Instant::now();
+            }
+        }
+        self.locked.store(locked, Ordering::Relaxed);
+        if locked {
+            // Resume consumption that may have stopped as the previous lock expired.
+            self.notify.notify_one();
+        }
+    }
+
+    /// Whether the broker side lock is held and has not expired.
+    pub(crate) fn is_lock_valid(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+            && self
+                .last_lock_time
+                .lock()
+                .map(|time| time.elapsed() < LOCK_MAX_LIVE_TIME)
+                .unwrap_or(false)
+    }
+
+    pub(crate) fn put_messages(&self, messages: Vec<MessageExt>) {
+        if let Ok(mut guard) = self.messages.lock() {
+            for message in messages {
+                guard.max_offset = guard.max_offset.max(message.queue_offset);
+                guard.cached_size += message.body.len();
+                if let Some(previous) = guard.pending.insert(message.queue_offset, message) {
+                    guard.cached_size -= previous.body.len();
+                }
+            }
+        }
+        self.last_pull_timestamp
+            .store(current_millis(), Ordering::Relaxed);
+        self.notify.notify_one();
+    }
+
+    /// Record that the messages are being delivered to a concurrent listener.
+    pub(crate) fn start_consuming(&self, offsets: &[i64]) {
+        if let Ok(mut guard) = self.messages.lock() {
+            let now = Instant::now();
+            for offset in offsets {
+                guard.consume_started.insert(*offset, now);
+            }
+        }
+        self.last_consume_timestamp
+            .store(current_millis(), Ordering::Relaxed);
+    }
+
+    /// Messages delivered to a concurrent listener longer than `timeout` ago and not yet removed.
+    pub(crate) fn expired_messages(&self, timeout: Duration) -> Vec<MessageExt> {
+        match self.messages.lock() {
+            Ok(guard) => guard
+                .consume_started
+                .iter()
+                .filter(|(_, started)| started.elapsed() > timeout)
+                .filter_map(|(offset, _)| guard.pending.get(offset).cloned())
+                .collect(),
+            Err(_e) => vec![],
+        }
+    }
+
+    pub(crate) fn inc_flow_control_times(&self) {
+        self.flow_control_times.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> ProcessQueueStats {
+        let (count, size, min, max) = match self.messages.lock() {
+            Ok(guard) => (
+                guard.pending.len() + guard.consuming.len(),
+                guard.cached_size,
+                guard.first_offset(),
+                guard.last_offset(),
+            ),
+            Err(_e) => (0, 0, None, None),
+        };
+        ProcessQueueStats {
+            cached_msg_count: count,
+            cached_msg_size: size,
+            cached_msg_min_offset: min,
+            cached_msg_max_offset: max,
+            max_span: max.zip(min).map(|(max, min)| max - min).unwrap_or_default(),
+            next_offset: self.next_offset(),
+            locked: self.is_lock_valid(),
+            dropped: self.is_dropped(),
+            flow_control_times: self.flow_control_times.load(Ordering::Relaxed),
+            last_pull_timestamp: self.last_pull_timestamp.load(Ordering::Relaxed),
+            last_consume_timestamp: self.last_consume_timestamp.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Remove messages consumed concurrently, returning the offset that is now safe to commit.
+    ///
+    /// Return `None` if the queue is poisoned and no offset may be committed.
+    pub(crate) fn remove_messages(&self, offsets: &[i64]) -> Option<i64> {
+        let mut guard = self.messages.lock().ok()?;
+        for offset in offsets {
+            if let Some(message) = guard.pending.remove(offset) {
+                guard.cached_size -= message.body.len();
+            }
+            guard.consume_started.remove(offset);
+        }
+        Some(guard.commit_offset())
+    }
+
+    /// Account for messages up to `offset` that were pulled but filtered out, returning the offset that is now safe
+    /// to commit.
+    pub(crate) fn skip_to(&self, offset: i64) -> Option<i64> {
+        let mut guard = self.messages.lock().ok()?;
+        guard.max_offset = guard.max_offset.max(offset);
+        Some(guard.commit_offset())
+    }
+
+    /// Move up to `batch_size` messages with the smallest offsets from pending to consuming.
+    pub(crate) fn take_messages(&self, batch_size: usize) -> Vec<MessageExt> {
+        self.last_consume_timestamp
+            .store(current_millis(), Ordering::Relaxed);
+        let mut taken = vec![];
+        if let Ok(mut guard) = self.messages.lock() {
+            while taken.len() < batch_size {
+                match guard.pending.pop_first() {
+                    Some((offset, message)) => {
+                        guard.consuming.insert(offset, message.clone());
+                        taken.push(message);
+                    }
+                    None => break,
+                }
+            }
+        }
+        taken
+    }
+
+    /// Mark all consuming messages as consumed, returning the offset that is now safe to commit.
+    ///
+    /// Return `None` if the queue is poisoned and no offset may be committed.
+    pub(crate) fn commit(&self) -> Option<i64> {
+        let mut guard = self.messages.lock().ok()?;
+        let consumed: usize = guard.consuming.values().map(|m| m.body.len()).sum();
+        guard.cached_size -= consumed;
+        guard.consuming.clear();
+        Some(guard.commit_offset())
+    }
+
+    /// Put consuming messages back so that they are delivered again, in order, by the next take.
+    pub(crate) fn make_messages_to_consume_again(&self, messages: Vec<MessageExt>) {
+        if let Ok(mut guard) = self.messages.lock() {
+            for message in messages {
+                guard.consuming.remove(&message.queue_offset);
+                guard.pending.insert(message.queue_offset, message);
+            }
+        }
+    }
+
+    /// Put all consuming messages back as they were taken.
+    pub(crate) fn rollback(&self) {
+        if let Ok(mut guard) = self.messages.lock() {
+            let consuming = std::mem::take(&mut guard.consuming);
+            guard.pending.extend(consuming);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::tests::encode_stored;
+
+    fn messages(offsets: std::ops::Range<i64>) -> Vec<MessageExt> {
+        offsets
+            .flat_map(|offset| {
+                MessageExt::decode_batch(encode_stored("T1", offset, &[], b"body")).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_take_and_commit() {
+        let pq = ProcessQueue::new(10);
+        pq.put_messages(messages(10..15));
+
+        let taken = pq.take_messages(2);
+        assert_eq!(taken.len(), 2);
+        assert_eq!(taken[0].queue_offset, 10);
+        assert_eq!(pq.commit(), Some(12));
+
+        let taken = pq.take_messages(8);
+        assert_eq!(taken.len(), 3);
+        assert!(pq.take_messages(1).is_empty());
+        assert_eq!(pq.commit(), Some(15));
+    }
+
+    #[test]
+    fn test_consume_again() {
+        let pq = ProcessQueue::new(0);
+        pq.put_messages(messages(0..3));
+        let taken = pq.take_messages(2);
+        pq.make_messages_to_consume_again(taken);
+        assert_eq!(pq.commit(), Some(0));
+
+        let taken = pq.take_messages(1);
+        assert_eq!(taken[0].queue_offset, 0);
+    }
+
+    #[test]
+    fn test_remove_messages() {
+        let pq = ProcessQueue::new(0);
+        pq.put_messages(messages(0..4));
+        assert_eq!(pq.remove_messages(&[1, 2]), Some(0));
+        assert_eq!(pq.remove_messages(&[0]), Some(3));
+        assert_eq!(pq.remove_messages(&[3]), Some(4));
+    }
+
+    #[test]
+    fn test_skip_to() {
+        let pq = ProcessQueue::new(0);
+        pq.put_messages(messages(0..2));
+        assert_eq!(pq.skip_to(5), Some(0));
+        assert_eq!(pq.remove_messages(&[0, 1]), Some(6));
+    }
+
+    #[test]
+    fn test_stats() {
+        let pq = ProcessQueue::new(0);
+        pq.put_messages(messages(0..4));
+        let stats = pq.stats();
+        assert_eq!(stats.cached_msg_count, 4);
+        assert_eq!(stats.cached_msg_size, 16);
+        assert_eq!(stats.max_span, 3);
+        assert!(stats.last_pull_timestamp > 0);
+
+        pq.take_messages(1);
+        pq.commit();
+        pq.remove_messages(&[3]);
+        let stats = pq.stats();
+        assert_eq!(stats.cached_msg_count, 2);
+        assert_eq!(stats.cached_msg_size, 8);
+        assert_eq!(
+            (stats.cached_msg_min_offset, stats.cached_msg_max_offset),
+            (Some(1), Some(2))
+        );
+    }
+
+    #[test]
+    fn test_expired_messages() {
+        let pq = ProcessQueue::new(0);
+        pq.put_messages(messages(0..3));
+        pq.start_consuming(&[0, 1]);
+        assert!(pq.expired_messages(Duration::from_secs(60)).is_empty());
+        std::thread::sleep(Duration::from_millis(20));
+        pq.remove_messages(&[0]);
+        let expired = pq.expired_messages(Duration::from_millis(10));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].queue_offset, 1);
+    }
+
+    #[test]
+    fn test_commit_offset_of_empty_queue() {
+        let pq = ProcessQueue::new(7);
+        assert_eq!(pq.commit(), Some(7));
+    }
+
+    #[test]
+    fn test_lock() {
+        let pq = ProcessQueue::new(0);
+        assert!(!pq.is_lock_valid());
+        pq.set_locked(true);
+        assert!(pq.is_lock_valid());
+        pq.set_locked(false);
+        assert!(!pq.is_lock_valid());
+    }
+}
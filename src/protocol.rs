@@ -1,10 +1,41 @@
 //!
 //! Define protocols used when talking to Apache RocketMQ servers.
 //!
-use serde::Deserialize;
-use std::collections::HashMap;
+use crate::connection;
+use crate::error::ClientError;
+use crate::message::MessageQueue;
+use crate::sql92;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::vec::Vec;
 
+/// Read and parse a mandatory field of a custom response header.
+fn parse_field<T: FromStr>(map: &HashMap<String, String>, key: &str) -> Result<T, ClientError> {
+    map.get(key)
+        .and_then(|value| value.parse::<T>().ok())
+        .ok_or_else(|| ClientError::InvalidFrame(format!("Missing or malformed header `{}`", key)))
+}
+
+/// Deserialize a map keyed by message queues, whose keys are JSON strings once normalized by `Frame::json_body`.
+fn deserialize_mq_table<'de, D, V>(deserializer: D) -> Result<HashMap<MessageQueue, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    let table: HashMap<String, V> = HashMap::deserialize(deserializer)?;
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            serde_json::from_str(&key)
+                .map(|mq| (mq, value))
+                .map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
+
 pub struct GetRouteInfoRequestHeader {
     topic: String,
 }
@@ -35,6 +66,34 @@ pub struct QueueData {
     pub(crate) topic_syn_flag: i32,
 }
 
+/// Bit of `QueueData::perm` permitting subscribers to read.
+pub const PERM_READ: i32 = 1 << 2;
+
+/// Bit of `QueueData::perm` permitting publishers to write.
+pub const PERM_WRITE: i32 = 1 << 1;
+
+/// Bit of `QueueData::perm` letting topics created automatically inherit the route of the default topic.
+pub const PERM_INHERIT: i32 = 1;
+
+impl QueueData {
+    pub fn readable(&self) -> bool {
+        self.perm & PERM_READ == PERM_READ
+    }
+
+    pub fn writable(&self) -> bool {
+        self.perm & PERM_WRITE == PERM_WRITE
+    }
+
+    pub fn inheritable(&self) -> bool {
+        self.perm & PERM_INHERIT == PERM_INHERIT
+    }
+
+    /// System flag of the topic, as the broker registered it.
+    pub fn topic_syn_flag(&self) -> i32 {
+        self.topic_syn_flag
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BrokerData {
@@ -43,6 +102,30 @@ pub struct BrokerData {
     pub(crate) broker_addrs: HashMap<i64, String>,
 }
 
+/// Broker id of master nodes; slaves have positive ids.
+pub const MASTER_ID: i64 = 0;
+
+impl BrokerData {
+    /// Name of the cluster the broker belongs to.
+    pub fn cluster(&self) -> &str {
+        &self.cluster
+    }
+
+    /// Address of the master node, whose broker id is 0.
+    pub(crate) fn master_addr(&self) -> Option<&str> {
+        self.broker_addrs.get(&MASTER_ID).map(String::as_str)
+    }
+
+    /// Address of the node with the given broker id, or of the one with the lowest id if it is not in the route,
+    /// such as a slave gone down. Return whether the node selected is a slave along with its address.
+    pub(crate) fn select_addr(&self, broker_id: i64) -> Option<(&str, bool)> {
+        self.broker_addrs
+            .get_key_value(&broker_id)
+            .or_else(|| self.broker_addrs.iter().min_by_key(|(id, _)| **id))
+            .map(|(id, addr)| (addr.as_str(), *id != MASTER_ID))
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TopicRouteData {
@@ -56,6 +139,479 @@ pub struct TopicRouteData {
     pub(crate) filter_server_table: HashMap<String, Vec<String>>,
 }
 
+impl TopicRouteData {
+    /// Filter servers of each broker address, deprecated along with class filters.
+    pub fn filter_server_table(&self) -> &HashMap<String, Vec<String>> {
+        &self.filter_server_table
+    }
+
+    /// Find address of the master node of the named broker.
+    pub(crate) fn master_addr(&self, broker_name: &str) -> Option<&str> {
+        self.broker_datas
+            .iter()
+            .find(|broker_data| broker_data.broker_name == broker_name)
+            .and_then(BrokerData::master_addr)
+    }
+
+    /// Find address of the node of the named broker with the given broker id, falling back as
+    /// `BrokerData::select_addr` does.
+    pub(crate) fn select_addr(&self, broker_name: &str, broker_id: i64) -> Option<(&str, bool)> {
+        self.broker_datas
+            .iter()
+            .find(|broker_data| broker_data.broker_name == broker_name)
+            .and_then(|broker_data| broker_data.select_addr(broker_id))
+    }
+}
+
+/// Brokers registered with name servers, grouped by cluster, as answered to GET_BROKER_CLUSTER_INFO.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterInfo {
+    /// Nodes of each broker, keyed by broker name.
+    pub(crate) broker_addr_table: HashMap<String, BrokerData>,
+
+    /// Names of the brokers of each cluster.
+    #[serde(default)]
+    pub(crate) cluster_addr_table: HashMap<String, HashSet<String>>,
+}
+
+impl ClusterInfo {
+    /// Names of all clusters, sorted, including those only brokers name as theirs.
+    pub fn cluster_names(&self) -> Vec<&str> {
+        let names: BTreeSet<&str> = self
+            .cluster_addr_table
+            .keys()
+            .map(String::as_str)
+            .chain(self.broker_addr_table.values().map(|b| b.cluster.as_str()))
+            .collect();
+        names.into_iter().collect()
+    }
+
+    /// Names of the brokers of the cluster, sorted, whether the cluster lists them or they name the cluster as
+    /// theirs; empty if the cluster is unknown.
+    pub fn broker_names(&self, cluster: &str) -> Vec<&str> {
+        let listed = self
+            .cluster_addr_table
+            .get(cluster)
+            .into_iter()
+            .flatten()
+            .map(String::as_str);
+        let names: BTreeSet<&str> = self
+            .broker_addr_table
+            .values()
+            .filter(|b| b.cluster == cluster)
+            .map(|b| b.broker_name.as_str())
+            .chain(listed)
+            .collect();
+        names.into_iter().collect()
+    }
+
+    /// Address of the master node of the named broker.
+    pub fn master_addr(&self, broker_name: &str) -> Option<&str> {
+        self.broker_addr_table
+            .get(broker_name)
+            .and_then(BrokerData::master_addr)
+    }
+
+    /// Addresses of the master nodes of all brokers of the cluster, as administrative changes are applied to.
+    pub fn master_addrs(&self, cluster: &str) -> Vec<&str> {
+        self.broker_names(cluster)
+            .into_iter()
+            .filter_map(|broker_name| self.master_addr(broker_name))
+            .collect()
+    }
+
+    /// Addresses of all nodes, masters and slaves, of all brokers.
+    pub fn broker_addrs(&self) -> HashSet<&str> {
+        self.broker_addr_table
+            .values()
+            .flat_map(|broker_data| broker_data.broker_addrs.values().map(String::as_str))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct CreateTopicRequestHeader {
+    pub(crate) topic: String,
+    pub(crate) default_topic: String,
+    pub(crate) read_queue_nums: i32,
+    pub(crate) write_queue_nums: i32,
+    pub(crate) perm: i32,
+    pub(crate) topic_filter_type: String,
+    pub(crate) topic_sys_flag: i32,
+    pub(crate) order: bool,
+}
+
+impl From<CreateTopicRequestHeader> for HashMap<String, String> {
+    fn from(header: CreateTopicRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("topic".to_owned(), header.topic);
+        map.insert("defaultTopic".to_owned(), header.default_topic);
+        map.insert(
+            "readQueueNums".to_owned(),
+            header.read_queue_nums.to_string(),
+        );
+        map.insert(
+            "writeQueueNums".to_owned(),
+            header.write_queue_nums.to_string(),
+        );
+        map.insert("perm".to_owned(), header.perm.to_string());
+        map.insert("topicFilterType".to_owned(), header.topic_filter_type);
+        map.insert("topicSysFlag".to_owned(), header.topic_sys_flag.to_string());
+        map.insert("order".to_owned(), header.order.to_string());
+        map
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct DeleteTopicRequestHeader {
+    pub(crate) topic: String,
+
+    /// Name servers delete the route of the topic within this cluster only, or entirely if absent.
+    pub(crate) cluster_name: Option<String>,
+}
+
+impl From<DeleteTopicRequestHeader> for HashMap<String, String> {
+    fn from(header: DeleteTopicRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("topic".to_owned(), header.topic);
+        if let Some(cluster_name) = header.cluster_name {
+            map.insert("clusterName".to_owned(), cluster_name);
+        }
+        map
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TopicList {
+    pub(crate) topic_list: HashSet<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct GetConsumeStatsRequestHeader {
+    pub(crate) consumer_group: String,
+    pub(crate) topic: String,
+}
+
+impl From<GetConsumeStatsRequestHeader> for HashMap<String, String> {
+    fn from(header: GetConsumeStatsRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("consumerGroup".to_owned(), header.consumer_group);
+        map.insert("topic".to_owned(), header.topic);
+        map
+    }
+}
+
+/// Progress of a consumer group on a queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OffsetWrapper {
+    /// Offset following the last message stored in the queue.
+    pub broker_offset: i64,
+
+    /// Offset committed by the group.
+    pub consumer_offset: i64,
+
+    /// Offset the group pulls from next, ahead of `consumer_offset` by messages being consumed.
+    #[serde(default)]
+    pub pull_offset: i64,
+
+    /// Milliseconds since the Unix epoch when the message at `consumer_offset` was stored, or 0 if unknown.
+    #[serde(default)]
+    pub last_timestamp: i64,
+}
+
+impl OffsetWrapper {
+    /// Number of messages stored but not yet consumed by the group, that is its lag.
+    pub fn diff(&self) -> i64 {
+        self.broker_offset - self.consumer_offset
+    }
+}
+
+/// Progress of a consumer group on queues of a topic, as answered to GET_CONSUME_STATS by each broker and merged
+/// across them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumeStats {
+    #[serde(deserialize_with = "deserialize_mq_table")]
+    pub offset_table: HashMap<MessageQueue, OffsetWrapper>,
+
+    /// Messages consumed per second by the group.
+    #[serde(default)]
+    pub consume_tps: f64,
+}
+
+impl ConsumeStats {
+    /// Lag of the group summed over all queues.
+    pub fn diff_total(&self) -> i64 {
+        self.offset_table.values().map(OffsetWrapper::diff).sum()
+    }
+
+    pub fn broker_offset_total(&self) -> i64 {
+        self.offset_table.values().map(|o| o.broker_offset).sum()
+    }
+
+    pub fn consumer_offset_total(&self) -> i64 {
+        self.offset_table.values().map(|o| o.consumer_offset).sum()
+    }
+
+    /// Add queues and throughput reported by another broker.
+    pub(crate) fn merge(&mut self, other: ConsumeStats) {
+        self.offset_table.extend(other.offset_table);
+        self.consume_tps += other.consume_tps;
+    }
+}
+
+/// Connection of a client to a broker.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientConnection {
+    pub client_id: String,
+
+    /// Remote address of the client, as seen by the broker.
+    pub client_addr: String,
+
+    /// Language of the client, such as `JAVA` or `RUST`.
+    pub language: String,
+    pub version: i32,
+}
+
+/// Subscription of a consumer group to a topic.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionInfo {
+    pub topic: String,
+
+    /// Expression messages are filtered by, such as `TagA || TagB`.
+    pub sub_string: String,
+
+    /// Either `TAG` or `SQL92`.
+    #[serde(default)]
+    pub expression_type: String,
+    pub sub_version: i64,
+}
+
+/// Members of a consumer group online on a broker and their subscriptions, as answered to
+/// GET_CONSUMER_CONNECTION_LIST.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumerConnection {
+    #[serde(rename = "connectionSet")]
+    pub connections: Vec<ClientConnection>,
+
+    /// Subscriptions keyed by topic.
+    #[serde(rename = "subscriptionTable", default)]
+    pub subscriptions: HashMap<String, SubscriptionInfo>,
+
+    /// `CONSUME_ACTIVELY` for pull consumers, or `CONSUME_PASSIVELY` for push consumers.
+    #[serde(default)]
+    pub consume_type: Option<String>,
+
+    /// `CLUSTERING` or `BROADCASTING`.
+    #[serde(default)]
+    pub message_model: Option<String>,
+
+    #[serde(default)]
+    pub consume_from_where: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ViewMessageRequestHeader {
+    /// Offset of the message in the commit log.
+    pub(crate) offset: i64,
+}
+
+impl From<ViewMessageRequestHeader> for HashMap<String, String> {
+    fn from(header: ViewMessageRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("offset".to_owned(), header.offset.to_string());
+        map
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct QueryMessageRequestHeader {
+    pub(crate) topic: String,
+    pub(crate) key: String,
+    pub(crate) max_num: i32,
+    pub(crate) begin_timestamp: i64,
+    pub(crate) end_timestamp: i64,
+
+    /// Look up the index of unique keys, that is client message ids, rather than that of keys.
+    pub(crate) unique_key_query: bool,
+}
+
+impl From<QueryMessageRequestHeader> for HashMap<String, String> {
+    fn from(header: QueryMessageRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("topic".to_owned(), header.topic);
+        map.insert("key".to_owned(), header.key);
+        map.insert("maxNum".to_owned(), header.max_num.to_string());
+        map.insert(
+            "beginTimestamp".to_owned(),
+            header.begin_timestamp.to_string(),
+        );
+        map.insert("endTimestamp".to_owned(), header.end_timestamp.to_string());
+        map.insert(
+            "_UNIQUE_KEY_QUERY".to_owned(),
+            header.unique_key_query.to_string(),
+        );
+        map
+    }
+}
+
+/// Configuration of a consumer group on a broker.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SubscriptionGroupConfig {
+    pub group_name: String,
+    pub consume_enable: bool,
+    pub consume_from_min_enable: bool,
+
+    /// Whether members of the group may consume in broadcasting mode.
+    pub consume_broadcast_enable: bool,
+
+    /// Number of queues of the retry topic of the group.
+    pub retry_queue_nums: i32,
+
+    /// Times a message is redelivered before it is moved to the dead letter queue.
+    pub retry_max_times: i32,
+
+    /// Node the group consumes from, 0 being the master.
+    pub broker_id: i64,
+
+    /// Node the group is redirected to once it lags far behind.
+    pub which_broker_when_consume_slowly: i64,
+    pub notify_consumer_ids_changed_enable: bool,
+
+    /// Whether the group consumes in order, so that brokers redeliver its messages accordingly.
+    pub consume_message_orderly: bool,
+}
+
+impl Default for SubscriptionGroupConfig {
+    fn default() -> Self {
+        Self {
+            group_name: String::new(),
+            consume_enable: true,
+            consume_from_min_enable: true,
+            consume_broadcast_enable: true,
+            retry_queue_nums: 1,
+            retry_max_times: 16,
+            broker_id: MASTER_ID,
+            which_broker_when_consume_slowly: 1,
+            notify_consumer_ids_changed_enable: true,
+            consume_message_orderly: false,
+        }
+    }
+}
+
+impl SubscriptionGroupConfig {
+    /// Configure the group as brokers do when creating groups automatically.
+    pub fn new(group_name: &str) -> Self {
+        Self {
+            group_name: group_name.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SubscriptionGroupWrapper {
+    pub(crate) subscription_group_table: HashMap<String, SubscriptionGroupConfig>,
+}
+
+#[derive(Debug)]
+pub(crate) struct DeleteSubscriptionGroupRequestHeader {
+    pub(crate) group_name: String,
+
+    /// Also remove offsets committed by the group.
+    pub(crate) clean_offset: bool,
+}
+
+impl From<DeleteSubscriptionGroupRequestHeader> for HashMap<String, String> {
+    fn from(header: DeleteSubscriptionGroupRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("groupName".to_owned(), header.group_name);
+        map.insert("cleanOffset".to_owned(), header.clean_offset.to_string());
+        map
+    }
+}
+
+/// Runtime statistics of a broker, as answered to GET_BROKER_RUNTIME_INFO in form of a key/value table.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BrokerRuntimeInfo {
+    table: HashMap<String, String>,
+}
+
+impl BrokerRuntimeInfo {
+    /// Raw value of the statistic, such as `brokerVersionDesc` or `commitLogDiskRatio`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.table.get(key).map(String::as_str)
+    }
+
+    /// Value of the statistic parsed as `T`, or `None` if it is missing or malformed.
+    pub fn get_parsed<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.get(key).and_then(|value| value.trim().parse().ok())
+    }
+
+    /// All statistics, keyed by name.
+    pub fn table(&self) -> &HashMap<String, String> {
+        &self.table
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.get("brokerVersionDesc")
+    }
+
+    /// Messages stored today so far.
+    pub fn msg_put_total_today_now(&self) -> Option<i64> {
+        self.get_parsed("msgPutTotalTodayNow")
+    }
+
+    /// Messages pulled today so far.
+    pub fn msg_get_total_today_now(&self) -> Option<i64> {
+        self.get_parsed("msgGetTotalTodayNow")
+    }
+
+    pub fn commit_log_max_offset(&self) -> Option<i64> {
+        self.get_parsed("commitLogMaxOffset")
+    }
+
+    /// Ratio of the disk holding the commit log in use, between 0 and 1.
+    pub fn commit_log_disk_ratio(&self) -> Option<f64> {
+        self.get_parsed("commitLogDiskRatio")
+    }
+
+    /// Messages stored per second over the last 10 seconds, the first of the rates `putTps` lists.
+    pub fn put_tps(&self) -> Option<f64> {
+        self.get("putTps")
+            .and_then(|value| value.split_whitespace().next())
+            .and_then(|value| value.parse().ok())
+    }
+}
+
+/// Parse configuration in form of Java properties, one `key=value` per line, as brokers exchange it.
+pub(crate) fn decode_config(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+        .collect()
+}
+
+/// Format configuration as Java properties, sorted by key.
+pub(crate) fn encode_config(config: &HashMap<String, String>) -> String {
+    let mut entries: Vec<_> = config.iter().collect();
+    entries.sort_unstable();
+    entries
+        .into_iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect()
+}
+
 #[derive(Debug)]
 pub(crate) struct SendMessageRequestHeader {
     pub(crate) producer_group: String,
@@ -117,9 +673,526 @@ impl From<SendMessageRequestHeader> for HashMap<String, String> {
     }
 }
 
+#[derive(Debug)]
+pub(crate) struct SendMessageResponseHeader {
+    pub(crate) msg_id: String,
+    pub(crate) queue_id: i32,
+    pub(crate) queue_offset: i64,
+}
+
+impl TryFrom<&HashMap<String, String>> for SendMessageResponseHeader {
+    type Error = ClientError;
+
+    fn try_from(map: &HashMap<String, String>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            msg_id: parse_field(map, "msgId")?,
+            queue_id: parse_field(map, "queueId")?,
+            queue_offset: parse_field(map, "queueOffset")?,
+        })
+    }
+}
+
+/// Bit of `PullMessageRequestHeader::sys_flag` indicating `commit_offset` should be persisted by broker.
+pub(crate) const PULL_FLAG_COMMIT_OFFSET: i32 = 1;
+
+/// Bit of `PullMessageRequestHeader::sys_flag` allowing broker to hold the request till new messages arrive.
+pub(crate) const PULL_FLAG_SUSPEND: i32 = 1 << 1;
+
+/// Bit of `PullMessageRequestHeader::sys_flag` indicating the request carries its own subscription.
+pub(crate) const PULL_FLAG_SUBSCRIPTION: i32 = 1 << 2;
+
+#[derive(Debug)]
+pub(crate) struct PullMessageRequestHeader {
+    pub(crate) consumer_group: String,
+    pub(crate) topic: String,
+    pub(crate) queue_id: i32,
+    pub(crate) queue_offset: i64,
+    pub(crate) max_msg_nums: i32,
+    pub(crate) sys_flag: i32,
+    pub(crate) commit_offset: i64,
+    pub(crate) suspend_timeout_millis: i64,
+    pub(crate) subscription: Option<String>,
+    pub(crate) sub_version: i64,
+    pub(crate) expression_type: Option<String>,
+}
+
+impl From<PullMessageRequestHeader> for HashMap<String, String> {
+    fn from(header: PullMessageRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("consumerGroup".to_owned(), header.consumer_group);
+        map.insert("topic".to_owned(), header.topic);
+        map.insert("queueId".to_owned(), format!("{}", header.queue_id));
+        map.insert("queueOffset".to_owned(), format!("{}", header.queue_offset));
+        map.insert("maxMsgNums".to_owned(), format!("{}", header.max_msg_nums));
+        map.insert("sysFlag".to_owned(), format!("{}", header.sys_flag));
+        map.insert(
+            "commitOffset".to_owned(),
+            format!("{}", header.commit_offset),
+        );
+        map.insert(
+            "suspendTimeoutMillis".to_owned(),
+            format!("{}", header.suspend_timeout_millis),
+        );
+        if let Some(subscription) = header.subscription {
+            map.insert("subscription".to_owned(), subscription);
+        }
+        map.insert("subVersion".to_owned(), format!("{}", header.sub_version));
+        if let Some(expression_type) = header.expression_type {
+            map.insert("expressionType".to_owned(), expression_type);
+        }
+        map
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PullMessageResponseHeader {
+    pub(crate) suggest_which_broker_id: i64,
+    pub(crate) next_begin_offset: i64,
+}
+
+impl TryFrom<&HashMap<String, String>> for PullMessageResponseHeader {
+    type Error = ClientError;
+
+    fn try_from(map: &HashMap<String, String>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            suggest_which_broker_id: parse_field(map, "suggestWhichBrokerId")?,
+            next_begin_offset: parse_field(map, "nextBeginOffset")?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct QueryConsumerOffsetRequestHeader {
+    pub(crate) consumer_group: String,
+    pub(crate) topic: String,
+    pub(crate) queue_id: i32,
+}
+
+impl From<QueryConsumerOffsetRequestHeader> for HashMap<String, String> {
+    fn from(header: QueryConsumerOffsetRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("consumerGroup".to_owned(), header.consumer_group);
+        map.insert("topic".to_owned(), header.topic);
+        map.insert("queueId".to_owned(), format!("{}", header.queue_id));
+        map
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct UpdateConsumerOffsetRequestHeader {
+    pub(crate) consumer_group: String,
+    pub(crate) topic: String,
+    pub(crate) queue_id: i32,
+    pub(crate) commit_offset: i64,
+}
+
+impl From<UpdateConsumerOffsetRequestHeader> for HashMap<String, String> {
+    fn from(header: UpdateConsumerOffsetRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("consumerGroup".to_owned(), header.consumer_group);
+        map.insert("topic".to_owned(), header.topic);
+        map.insert("queueId".to_owned(), format!("{}", header.queue_id));
+        map.insert(
+            "commitOffset".to_owned(),
+            format!("{}", header.commit_offset),
+        );
+        map
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct GetMaxOffsetRequestHeader {
+    pub(crate) topic: String,
+    pub(crate) queue_id: i32,
+}
+
+impl From<GetMaxOffsetRequestHeader> for HashMap<String, String> {
+    fn from(header: GetMaxOffsetRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("topic".to_owned(), header.topic);
+        map.insert("queueId".to_owned(), format!("{}", header.queue_id));
+        map
+    }
+}
+
+/// GET_MIN_OFFSET carries the same fields as GET_MAX_OFFSET.
+pub(crate) type GetMinOffsetRequestHeader = GetMaxOffsetRequestHeader;
+
+#[derive(Debug)]
+pub(crate) struct SearchOffsetRequestHeader {
+    pub(crate) topic: String,
+    pub(crate) queue_id: i32,
+    pub(crate) timestamp: i64,
+}
+
+impl From<SearchOffsetRequestHeader> for HashMap<String, String> {
+    fn from(header: SearchOffsetRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("topic".to_owned(), header.topic);
+        map.insert("queueId".to_owned(), format!("{}", header.queue_id));
+        map.insert("timestamp".to_owned(), format!("{}", header.timestamp));
+        map
+    }
+}
+
+/// Response header shared by QUERY_CONSUMER_OFFSET, SEARCH_OFFSET_BY_TIMESTAMP, GET_MIN_OFFSET and GET_MAX_OFFSET.
+#[derive(Debug)]
+pub(crate) struct OffsetResponseHeader {
+    pub(crate) offset: i64,
+}
+
+impl TryFrom<&HashMap<String, String>> for OffsetResponseHeader {
+    type Error = ClientError;
+
+    fn try_from(map: &HashMap<String, String>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            offset: parse_field(map, "offset")?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct GetConsumerListByGroupRequestHeader {
+    pub(crate) consumer_group: String,
+}
+
+impl From<GetConsumerListByGroupRequestHeader> for HashMap<String, String> {
+    fn from(header: GetConsumerListByGroupRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("consumerGroup".to_owned(), header.consumer_group);
+        map
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GetConsumerListByGroupResponseBody {
+    pub(crate) consumer_id_list: Vec<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct UnregisterClientRequestHeader {
+    pub(crate) client_id: String,
+    pub(crate) consumer_group: Option<String>,
+}
+
+impl From<UnregisterClientRequestHeader> for HashMap<String, String> {
+    fn from(header: UnregisterClientRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("clientID".to_owned(), header.client_id);
+        if let Some(consumer_group) = header.consumer_group {
+            map.insert("consumerGroup".to_owned(), consumer_group);
+        }
+        map
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ConsumerSendMsgBackRequestHeader {
+    pub(crate) offset: i64,
+    pub(crate) group: String,
+    pub(crate) delay_level: i32,
+    pub(crate) origin_msg_id: String,
+    pub(crate) origin_topic: String,
+    pub(crate) max_reconsume_times: i32,
+}
+
+impl From<ConsumerSendMsgBackRequestHeader> for HashMap<String, String> {
+    fn from(header: ConsumerSendMsgBackRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("offset".to_owned(), format!("{}", header.offset));
+        map.insert("group".to_owned(), header.group);
+        map.insert("delayLevel".to_owned(), format!("{}", header.delay_level));
+        map.insert("originMsgId".to_owned(), header.origin_msg_id);
+        map.insert("originTopic".to_owned(), header.origin_topic);
+        map.insert("unitMode".to_owned(), "false".to_owned());
+        map.insert(
+            "maxReconsumeTimes".to_owned(),
+            format!("{}", header.max_reconsume_times),
+        );
+        map
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PopMessageRequestHeader {
+    pub(crate) consumer_group: String,
+    pub(crate) topic: String,
+
+    /// Queue to pop from, or `-1` to let broker pick among all queues of the topic.
+    pub(crate) queue_id: i32,
+    pub(crate) max_msg_nums: i32,
+    pub(crate) invisible_time: i64,
+    pub(crate) poll_time: i64,
+    pub(crate) born_time: i64,
+
+    /// Where a group pops a queue for the first time from: `0` for its min offset, `1` for its max offset.
+    pub(crate) init_mode: i32,
+    pub(crate) exp_type: String,
+    pub(crate) exp: String,
+}
+
+impl From<PopMessageRequestHeader> for HashMap<String, String> {
+    fn from(header: PopMessageRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("consumerGroup".to_owned(), header.consumer_group);
+        map.insert("topic".to_owned(), header.topic);
+        map.insert("queueId".to_owned(), format!("{}", header.queue_id));
+        map.insert("maxMsgNums".to_owned(), format!("{}", header.max_msg_nums));
+        map.insert(
+            "invisibleTime".to_owned(),
+            format!("{}", header.invisible_time),
+        );
+        map.insert("pollTime".to_owned(), format!("{}", header.poll_time));
+        map.insert("bornTime".to_owned(), format!("{}", header.born_time));
+        map.insert("initMode".to_owned(), format!("{}", header.init_mode));
+        map.insert("expType".to_owned(), header.exp_type);
+        map.insert("exp".to_owned(), header.exp);
+        map
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PopMessageResponseHeader {
+    pub(crate) pop_time: i64,
+    pub(crate) invisible_time: i64,
+    pub(crate) revive_qid: i32,
+
+    /// Start offset of each queue popped, such as `0 1 100;1 0 5`: whether the queue belongs to the retry topic of
+    /// the group, queue id and offset, separated by spaces.
+    pub(crate) start_offset_info: Option<String>,
+}
+
+impl PopMessageResponseHeader {
+    /// Start offset popped from the queue, keyed by whether it belongs to the retry topic of the group and queue id.
+    pub(crate) fn start_offset(&self, retry: bool, queue_id: i32) -> Option<i64> {
+        self.start_offset_info
+            .as_deref()?
+            .split(';')
+            .map(|entry| entry.split(' ').collect::<Vec<_>>())
+            .find(|fields| {
+                fields.len() == 3
+                    && (fields[0] == "1") == retry
+                    && fields[1].parse() == Ok(queue_id)
+            })
+            .and_then(|fields| fields[2].parse().ok())
+    }
+}
+
+impl TryFrom<&HashMap<String, String>> for PopMessageResponseHeader {
+    type Error = ClientError;
+
+    fn try_from(map: &HashMap<String, String>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            pop_time: parse_field(map, "popTime")?,
+            invisible_time: parse_field(map, "invisibleTime")?,
+            revive_qid: parse_field(map, "reviveQid")?,
+            start_offset_info: map.get("startOffsetInfo").cloned(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct AckMessageRequestHeader {
+    pub(crate) consumer_group: String,
+    pub(crate) topic: String,
+    pub(crate) queue_id: i32,
+    pub(crate) extra_info: String,
+    pub(crate) offset: i64,
+}
+
+impl From<AckMessageRequestHeader> for HashMap<String, String> {
+    fn from(header: AckMessageRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("consumerGroup".to_owned(), header.consumer_group);
+        map.insert("topic".to_owned(), header.topic);
+        map.insert("queueId".to_owned(), format!("{}", header.queue_id));
+        map.insert("extraInfo".to_owned(), header.extra_info);
+        map.insert("offset".to_owned(), format!("{}", header.offset));
+        map
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ChangeInvisibleTimeRequestHeader {
+    pub(crate) consumer_group: String,
+    pub(crate) topic: String,
+    pub(crate) queue_id: i32,
+    pub(crate) extra_info: String,
+    pub(crate) offset: i64,
+    pub(crate) invisible_time: i64,
+}
+
+impl From<ChangeInvisibleTimeRequestHeader> for HashMap<String, String> {
+    fn from(header: ChangeInvisibleTimeRequestHeader) -> Self {
+        let mut map = HashMap::new();
+        map.insert("consumerGroup".to_owned(), header.consumer_group);
+        map.insert("topic".to_owned(), header.topic);
+        map.insert("queueId".to_owned(), format!("{}", header.queue_id));
+        map.insert("extraInfo".to_owned(), header.extra_info);
+        map.insert("offset".to_owned(), format!("{}", header.offset));
+        map.insert(
+            "invisibleTime".to_owned(),
+            format!("{}", header.invisible_time),
+        );
+        map
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ChangeInvisibleTimeResponseHeader {
+    pub(crate) pop_time: i64,
+    pub(crate) invisible_time: i64,
+    pub(crate) revive_qid: i32,
+}
+
+impl TryFrom<&HashMap<String, String>> for ChangeInvisibleTimeResponseHeader {
+    type Error = ClientError;
+
+    fn try_from(map: &HashMap<String, String>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            pop_time: parse_field(map, "popTime")?,
+            invisible_time: parse_field(map, "invisibleTime")?,
+            revive_qid: parse_field(map, "reviveQid")?,
+        })
+    }
+}
+
+/// Header of PUSH_REPLY_MESSAGE_TO_CLIENT, through which brokers push replies to the client awaiting them. The
+/// body is that of the reply.
+#[derive(Debug)]
+pub(crate) struct ReplyMessageRequestHeader {
+    pub(crate) topic: String,
+    pub(crate) queue_id: i32,
+    pub(crate) sys_flag: i32,
+    pub(crate) born_timestamp: i64,
+    pub(crate) born_host: Option<SocketAddr>,
+    pub(crate) store_timestamp: i64,
+    pub(crate) store_host: Option<SocketAddr>,
+    pub(crate) reconsume_times: i32,
+    pub(crate) properties: String,
+}
+
+impl TryFrom<&HashMap<String, String>> for ReplyMessageRequestHeader {
+    type Error = ClientError;
+
+    fn try_from(map: &HashMap<String, String>) -> Result<Self, Self::Error> {
+        // Hosts are formatted by Java as `/127.0.0.1:10911`, or `/0:0:0:0:0:0:0:1:10911` for IPv6.
+        let host = |key| {
+            map.get(key).and_then(|host: &String| {
+                connection::parse_socket_addr(host.trim_start_matches('/'))
+            })
+        };
+        Ok(Self {
+            topic: parse_field(map, "topic")?,
+            queue_id: parse_field(map, "queueId")?,
+            sys_flag: parse_field(map, "sysFlag")?,
+            born_timestamp: parse_field(map, "bornTimestamp")?,
+            born_host: host("bornHost"),
+            store_timestamp: parse_field(map, "storeTimestamp")?,
+            store_host: host("storeHost"),
+            reconsume_times: map
+                .get("reconsumeTimes")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+            properties: map.get("properties").cloned().unwrap_or_default(),
+        })
+    }
+}
+
+/// Header of RESET_CONSUMER_CLIENT_OFFSET, which brokers send to consumers of a group to rewind a topic.
+#[derive(Debug)]
+pub(crate) struct ResetOffsetRequestHeader {
+    pub(crate) topic: String,
+    pub(crate) group: String,
+}
+
+impl TryFrom<&HashMap<String, String>> for ResetOffsetRequestHeader {
+    type Error = ClientError;
+
+    fn try_from(map: &HashMap<String, String>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            topic: parse_field(map, "topic")?,
+            group: parse_field(map, "group")?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ResetOffsetBody {
+    #[serde(deserialize_with = "deserialize_mq_table")]
+    pub(crate) offset_table: HashMap<MessageQueue, i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SubscriptionData {
+    pub(crate) class_filter_mode: bool,
+    pub(crate) topic: String,
+    pub(crate) sub_string: String,
+    pub(crate) tags_set: Vec<String>,
+    pub(crate) code_set: Vec<i32>,
+    pub(crate) sub_version: i64,
+    pub(crate) expression_type: String,
+
+    /// Parsed `sub_string` of SQL92 subscriptions, used to filter messages locally.
+    #[serde(skip)]
+    pub(crate) sql: Option<Arc<sql92::Expression>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ConsumerData {
+    pub(crate) group_name: String,
+    pub(crate) consume_type: String,
+    pub(crate) message_model: String,
+    pub(crate) consume_from_where: String,
+    pub(crate) subscription_data_set: Vec<SubscriptionData>,
+    pub(crate) unit_mode: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ProducerData {
+    pub(crate) group_name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HeartbeatData {
+    #[serde(rename = "clientID")]
+    pub(crate) client_id: String,
+    pub(crate) producer_data_set: Vec<ProducerData>,
+    pub(crate) consumer_data_set: Vec<ConsumerData>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LockBatchRequestBody {
+    pub(crate) consumer_group: String,
+    pub(crate) client_id: String,
+    pub(crate) mq_set: Vec<MessageQueue>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct LockBatchResponseBody {
+    #[serde(rename = "lockOKMQSet", default)]
+    pub(crate) lock_ok_mq_set: Vec<MessageQueue>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UnlockBatchRequestBody {
+    pub(crate) consumer_group: String,
+    pub(crate) client_id: String,
+    pub(crate) mq_set: Vec<MessageQueue>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::frame::Frame;
     use std::collections::HashMap;
 
     #[test]
@@ -153,6 +1226,16 @@ mod tests {
         assert_eq!(broker_data.broker_name, "b1");
         assert_eq!(broker_data.cluster, "C1");
         assert_eq!(broker_data.broker_addrs.len(), 2);
+        assert_eq!(broker_data.select_addr(1), Some(("localhost:1234", true)));
+        assert_eq!(broker_data.select_addr(2), Some(("localhost:8888", false)));
+
+        // Slaves serve reads while the master is down.
+        let mut broker_data = broker_data;
+        broker_data.broker_addrs.remove(&MASTER_ID);
+        assert_eq!(
+            broker_data.select_addr(MASTER_ID),
+            Some(("localhost:1234", true))
+        );
         Ok(())
     }
 
@@ -175,4 +1258,184 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_cluster_info() -> Result<(), ClientError> {
+        // Broker ids are integer keys, as fastjson writes them.
+        let mut frame = Frame::new();
+        frame.body = bytes::Bytes::from(
+            r#"{"brokerAddrTable":{"b1":{"brokerAddrs":{0:"10.0.0.1:10911",1:"10.0.0.2:10911"},"brokerName":"b1","cluster":"C1"},"b2":{"brokerAddrs":{1:"10.0.0.3:10911"},"brokerName":"b2","cluster":"C1"},"b3":{"brokerAddrs":{0:"10.0.0.4:10911"},"brokerName":"b3","cluster":"C2"}},"clusterAddrTable":{"C1":["b2","b1"],"C2":["b3"]}}"#,
+        );
+        let cluster_info: ClusterInfo = frame.json_body()?;
+        assert_eq!(cluster_info.cluster_names(), vec!["C1", "C2"]);
+        assert_eq!(cluster_info.broker_names("C1"), vec!["b1", "b2"]);
+        assert!(cluster_info.broker_names("C3").is_empty());
+        assert_eq!(cluster_info.master_addr("b1"), Some("10.0.0.1:10911"));
+        assert_eq!(cluster_info.master_addr("b2"), None);
+        assert_eq!(cluster_info.master_addrs("C1"), vec!["10.0.0.1:10911"]);
+        assert_eq!(cluster_info.broker_addrs().len(), 4);
+
+        // Brokers name their cluster even where the cluster table is left out.
+        frame.body = bytes::Bytes::from(
+            r#"{"brokerAddrTable":{"b1":{"brokerAddrs":{0:"10.0.0.1:10911"},"brokerName":"b1","cluster":"C1"}}}"#,
+        );
+        let cluster_info: ClusterInfo = frame.json_body()?;
+        assert_eq!(cluster_info.cluster_names(), vec!["C1"]);
+        assert_eq!(cluster_info.master_addrs("C1"), vec!["10.0.0.1:10911"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_consume_stats() -> Result<(), ClientError> {
+        let mut frame = Frame::new();
+        frame.body = bytes::Bytes::from(
+            r#"{"consumeTps":1.5,"offsetTable":{{"brokerName":"b1","queueId":0,"topic":"T1"}:{"brokerOffset":10,"consumerOffset":4,"lastTimestamp":1000,"pullOffset":6},{"brokerName":"b1","queueId":1,"topic":"T1"}:{"brokerOffset":3,"consumerOffset":3,"lastTimestamp":0}}}"#,
+        );
+        let mut stats: ConsumeStats = frame.json_body()?;
+        let mq = MessageQueue {
+            topic: "T1".to_owned(),
+            broker_name: "b1".to_owned(),
+            queue_id: 0,
+        };
+        assert_eq!(stats.offset_table[&mq].diff(), 6);
+        assert_eq!(stats.offset_table[&mq].pull_offset, 6);
+
+        let mut other = ConsumeStats::default();
+        other.offset_table.insert(
+            MessageQueue {
+                broker_name: "b2".to_owned(),
+                ..mq
+            },
+            OffsetWrapper {
+                broker_offset: 5,
+                consumer_offset: 1,
+                ..Default::default()
+            },
+        );
+        other.consume_tps = 0.5;
+        stats.merge(other);
+        assert_eq!(stats.offset_table.len(), 3);
+        assert_eq!(stats.diff_total(), 10);
+        assert_eq!(stats.broker_offset_total(), 18);
+        assert_eq!(stats.consumer_offset_total(), 8);
+        assert_eq!(stats.consume_tps, 2.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_config() {
+        let config = decode_config(
+            "# comment\nbrokerName=b1\n\nflushDiskType = ASYNC_FLUSH\nmsgTraceTopicName=\n",
+        );
+        assert_eq!(config.len(), 3);
+        assert_eq!(config["flushDiskType"], "ASYNC_FLUSH");
+        assert_eq!(config["msgTraceTopicName"], "");
+        let config: HashMap<String, String> = [("b", "2"), ("a", "1")]
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        assert_eq!(encode_config(&config), "a=1\nb=2\n");
+    }
+
+    #[test]
+    fn test_subscription_group_config() -> Result<(), ClientError> {
+        let mut frame = Frame::new();
+        frame.body = bytes::Bytes::from(
+            r#"{"dataVersion":{"counter":1,"timestamp":0},"subscriptionGroupTable":{"G1":{"brokerId":0,"consumeBroadcastEnable":false,"consumeEnable":true,"groupName":"G1","retryMaxTimes":3,"retryQueueNums":1}}}"#,
+        );
+        let wrapper: SubscriptionGroupWrapper = frame.json_body()?;
+        let config = &wrapper.subscription_group_table["G1"];
+        assert_eq!(config.retry_max_times, 3);
+        assert!(!config.consume_broadcast_enable);
+        // Fields missing from older brokers take their defaults.
+        assert_eq!(config.which_broker_when_consume_slowly, 1);
+
+        let info: BrokerRuntimeInfo = serde_json::from_str(
+            r#"{"table":{"brokerVersionDesc":"V4_9_4","msgPutTotalTodayNow":"42","putTps":"1.5 2.0 3.0","commitLogDiskRatio":"0.25"}}"#,
+        )
+        .map_err(|e| ClientError::InvalidFrame(e.to_string()))?;
+        assert_eq!(info.version(), Some("V4_9_4"));
+        assert_eq!(info.msg_put_total_today_now(), Some(42));
+        assert_eq!(info.msg_get_total_today_now(), None);
+        assert_eq!(info.put_tps(), Some(1.5));
+        assert_eq!(info.commit_log_disk_ratio(), Some(0.25));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pull_message_response_header() -> Result<(), Box<dyn std::error::Error>> {
+        let mut map = HashMap::new();
+        map.insert("suggestWhichBrokerId".to_owned(), "1".to_owned());
+        map.insert("nextBeginOffset".to_owned(), "32".to_owned());
+        map.insert("minOffset".to_owned(), "0".to_owned());
+        map.insert("maxOffset".to_owned(), "64".to_owned());
+        let header = PullMessageResponseHeader::try_from(&map)?;
+        assert_eq!(header.suggest_which_broker_id, 1);
+        assert_eq!(header.next_begin_offset, 32);
+
+        map.remove("nextBeginOffset");
+        assert!(PullMessageResponseHeader::try_from(&map).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pop_message_response_header() -> Result<(), Box<dyn std::error::Error>> {
+        let mut map = HashMap::new();
+        map.insert("popTime".to_owned(), "1700000000000".to_owned());
+        map.insert("invisibleTime".to_owned(), "30000".to_owned());
+        map.insert("reviveQid".to_owned(), "2".to_owned());
+        map.insert("startOffsetInfo".to_owned(), "0 1 100;1 0 5".to_owned());
+        let header = PopMessageResponseHeader::try_from(&map)?;
+        assert_eq!(header.revive_qid, 2);
+        assert_eq!(header.start_offset(false, 1), Some(100));
+        assert_eq!(header.start_offset(true, 0), Some(5));
+        assert_eq!(header.start_offset(false, 0), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_batch_body() -> Result<(), Box<dyn std::error::Error>> {
+        let body = LockBatchRequestBody {
+            consumer_group: "G1".to_owned(),
+            client_id: "127.0.0.1@1".to_owned(),
+            mq_set: vec![MessageQueue {
+                topic: "T1".to_owned(),
+                broker_name: "b1".to_owned(),
+                queue_id: 2,
+            }],
+        };
+        let json = serde_json::to_string(&body)?;
+        assert!(json.contains(r#""mqSet":[{"topic":"T1","brokerName":"b1","queueId":2}]"#));
+
+        let json = r#"{"lockOKMQSet":[{"brokerName":"b1","queueId":2,"topic":"T1"}]}"#;
+        let response: LockBatchResponseBody = serde_json::from_str(json)?;
+        assert_eq!(response.lock_ok_mq_set.len(), 1);
+        assert_eq!(response.lock_ok_mq_set[0].queue_id, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_offset_body() -> Result<(), ClientError> {
+        let mut frame = crate::frame::Frame::new();
+        frame.body = bytes::Bytes::from(
+            r#"{"offsetTable":{{"brokerName":"b1","queueId":0,"topic":"T1"}:12,{"brokerName":"b1","queueId":1,"topic":"T1"}:7}}"#,
+        );
+        let body: ResetOffsetBody = frame.json_body()?;
+        let mq = |queue_id| MessageQueue {
+            topic: "T1".to_owned(),
+            broker_name: "b1".to_owned(),
+            queue_id,
+        };
+        assert_eq!(body.offset_table.len(), 2);
+        assert_eq!(body.offset_table[&mq(0)], 12);
+        assert_eq!(body.offset_table[&mq(1)], 7);
+
+        let mut map = HashMap::new();
+        map.insert("topic".to_owned(), "T1".to_owned());
+        assert!(ResetOffsetRequestHeader::try_from(&map).is_err());
+        map.insert("group".to_owned(), "G1".to_owned());
+        let header = ResetOffsetRequestHeader::try_from(&map)?;
+        assert_eq!((header.topic.as_str(), header.group.as_str()), ("T1", "G1"));
+        Ok(())
+    }
 }
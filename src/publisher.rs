@@ -1,18 +1,1011 @@
 //!
 //! Messaging are about publishing and subscribing messages. `Publisher` is the struct to utilize to deliver message to broker.
 //!
-use crate::message::Message;
+use crate::client::{current_millis, Client, ClientOption};
+use crate::compression::{self, Compression, CompressionType};
+use crate::connection::RequestProcessor;
+use crate::error::ClientError;
+use crate::frame::{Frame, RequestCode, ResponseCode};
+use crate::message::{self, property, Message, MessageExt, MessageQueue};
+use crate::protocol;
+use crate::route::TopicPublishInfo;
+use crate::validator;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
 
-struct Publisher {
+/// How a message accepted by broker is persisted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendStatus {
+    SendOk,
+
+    /// The message is stored but not flushed to disk in time.
+    FlushDiskTimeout,
+
+    /// The message is stored but not replicated to the slave in time.
+    FlushSlaveTimeout,
+
+    /// The message is stored but no slave is available to replicate it.
+    SlaveNotAvailable,
+}
+
+/// Outcome of publishing a message.
+#[derive(Debug, Clone)]
+pub struct SendResult {
+    pub status: SendStatus,
+
+    /// Identifier assigned by publisher.
+    pub msg_id: String,
+
+    /// Identifier encoding store host and commit log offset of the message.
+    pub offset_msg_id: String,
+
+    pub message_queue: MessageQueue,
+
+    pub queue_offset: i64,
+}
+
+/// Options of a publisher.
+#[derive(Debug, Clone)]
+pub struct PublisherOption {
+    /// Bodies larger than this, in bytes, are compressed unless messages override it.
+    pub compress_body_threshold: usize,
+
+    pub compression_type: CompressionType,
+
+    /// Level of zlib and zstd compression, the higher the smaller and slower.
+    pub compression_level: u32,
+
+    /// Messages, and batches as a whole, whose body exceeds this size in bytes are rejected before being sent.
+    pub max_message_size: usize,
+
+    /// Topic whose route is used to publish to topics that do not exist yet, so that brokers enabling
+    /// `autoCreateTopicEnable` create them on the first message.
+    pub default_topic: String,
+
+    /// Number of queues of topics created automatically, capped by that of the default topic.
+    pub default_topic_queue_nums: i32,
+
+    /// Publish to masters only, skipping brokers whose master is down. Otherwise, their slave with the lowest id
+    /// is published to instead, as brokers enabling `enableSlaveActingMaster` expect.
+    pub send_to_master_only: bool,
+}
+
+impl Default for PublisherOption {
+    fn default() -> Self {
+        Self {
+            compress_body_threshold: 4 * 1024,
+            compression_type: CompressionType::Zlib,
+            compression_level: 5,
+            max_message_size: validator::DEFAULT_MAX_MESSAGE_SIZE,
+            default_topic: validator::AUTO_CREATE_TOPIC_KEY_TOPIC.to_owned(),
+            default_topic_queue_nums: 4,
+            send_to_master_only: true,
+        }
+    }
+}
+
+type PublishInfoEntry = (Arc<protocol::TopicRouteData>, Arc<TopicPublishInfo>);
+
+/// Requests awaiting replies, keyed by correlation id.
+type PendingReplies = Arc<Mutex<HashMap<String, oneshot::Sender<MessageExt>>>>;
+
+pub struct Publisher {
+    client: Arc<Client>,
     group: String,
+    option: PublisherOption,
+
+    /// Publish info of each topic, along with the route it is derived from.
+    publish_infos: Mutex<HashMap<String, PublishInfoEntry>>,
+
+    replies: PendingReplies,
+
+    /// Topics requests are sent to, whose brokers are kept aware of this publisher so as to push replies to it.
+    request_topics: Arc<Mutex<HashSet<String>>>,
+
+    /// Topics published to through the route of the default topic, with when name servers were last asked for
+    /// their own routes.
+    auto_create_topics: Mutex<HashMap<String, Instant>>,
 }
 
 impl Publisher {
-    pub fn new(group: &str) -> Self {
+    /// # Errors
+    /// Raise ClientError::IllegalGroup or ClientError::GroupTooLong if the group name is invalid.
+    pub fn new(group: &str, option: ClientOption) -> Result<Self, ClientError> {
+        Self::with_option(group, option, PublisherOption::default())
+    }
+
+    /// # Errors
+    /// Raise ClientError::IllegalGroup or ClientError::GroupTooLong if the group name is invalid.
+    pub fn with_option(
+        group: &str,
+        client_option: ClientOption,
+        option: PublisherOption,
+    ) -> Result<Self, ClientError> {
+        validator::check_group(group)?;
+        let mut publisher = Self::with_client(Arc::new(Client::new(client_option)?), group);
+        publisher.option = option;
+        Ok(publisher)
+    }
+
+    /// Build a publisher on top of an existing client, sharing its connections and routes.
+    pub(crate) fn with_client(client: Arc<Client>, group: &str) -> Self {
+        // Replies may be pushed as soon as the first request is sent, even by concurrent callers.
+        let replies: PendingReplies = Arc::new(Mutex::new(HashMap::new()));
+        client.connections.register_processor(
+            RequestCode::PushReplyMessageToClient,
+            Arc::new(ReplyProcessor {
+                replies: Arc::clone(&replies),
+            }),
+        );
         Publisher {
+            client,
             group: group.to_owned(),
+            option: PublisherOption::default(),
+            publish_infos: Mutex::new(HashMap::new()),
+            replies,
+            request_topics: Arc::new(Mutex::new(HashSet::new())),
+            auto_create_topics: Mutex::new(HashMap::new()),
         }
     }
 
-    pub fn publish(&mut self, message: &Message) {}
+    /// Publish the message to one of the writable queues of its topic, in round-robin fashion.
+    ///
+    /// # Errors
+    /// Raise ClientError if the topic is invalid or reserved, or the body is empty or too large, before contacting
+    /// any server.
+    pub async fn publish(&self, message: &Message) -> Result<SendResult, ClientError> {
+        validator::check_message(message, self.option.max_message_size)?;
+        self.publish_unchecked(message).await
+    }
+
+    /// Publish the message without validating it, for consumers sending messages back to retry topics.
+    pub(crate) async fn publish_unchecked(
+        &self,
+        message: &Message,
+    ) -> Result<SendResult, ClientError> {
+        let (addr, mq) = self.select_queue(&message.topic).await?;
+        self.send(&addr, mq, message).await
+    }
+
+    /// Publish the message as a request and wait for the reply, which its consumer creates by
+    /// `MessageExt::create_reply` and publishes in turn.
+    ///
+    /// Brokers discard the reply once `timeout` elapses after the request is sent.
+    ///
+    /// # Errors
+    /// Raise ClientError::ReplyTimeout if the request is not sent and replied within `timeout`.
+    pub async fn request(
+        &self,
+        message: &Message,
+        timeout: Duration,
+    ) -> Result<MessageExt, ClientError> {
+        validator::check_message(message, self.option.max_message_size)?;
+        // Registering with brokers and sending count against the timeout too, as they do against the TTL brokers
+        // apply to the request.
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        let correlation_id = unique_id(&self.client.client_id);
+        let mut request = message.clone();
+        request.properties.extend([
+            (property::CORRELATION_ID.to_owned(), correlation_id.clone()),
+            (
+                property::REPLY_TO_CLIENT.to_owned(),
+                self.client.client_id.clone(),
+            ),
+            (
+                property::MESSAGE_TTL.to_owned(),
+                timeout.as_millis().to_string(),
+            ),
+        ]);
+        let (tx, rx) = oneshot::channel();
+        if let Ok(mut replies) = self.replies.lock() {
+            replies.insert(correlation_id.clone(), tx);
+        }
+
+        let result = tokio::time::timeout_at(deadline, async {
+            self.prepare_request(&request.topic).await;
+            self.publish(&request).await?;
+            rx.await
+                .map_err(|_e| ClientError::ReplyTimeout(correlation_id.clone()))
+        })
+        .await
+        .unwrap_or_else(|_e| Err(ClientError::ReplyTimeout(correlation_id.clone())));
+        if let Ok(mut replies) = self.replies.lock() {
+            replies.remove(&correlation_id);
+        }
+        result
+    }
+
+    /// Make sure brokers serving the topic know this publisher before it sends requests there, renewing the
+    /// registration periodically for as long as the publisher lives. The topic is prepared again by the next
+    /// request if any broker fails to register this publisher.
+    async fn prepare_request(&self, topic: &str) {
+        match self.request_topics.lock() {
+            Ok(topics) if !topics.contains(topic) => {}
+            _ => return,
+        }
+        let registered = self
+            .client
+            .send_producer_heartbeat(&self.group, &[topic.to_owned()])
+            .await;
+        if !registered {
+            return;
+        }
+        let first = match self.request_topics.lock() {
+            Ok(mut topics) => topics.insert(topic.to_owned()) && topics.len() == 1,
+            Err(_e) => return,
+        };
+        if !first {
+            return;
+        }
+
+        let client = Arc::clone(&self.client);
+        let group = self.group.clone();
+        let request_topics = Arc::downgrade(&self.request_topics);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(client.option.heartbeat_interval).await;
+                let topics: Vec<String> = match Weak::upgrade(&request_topics) {
+                    Some(topics) => match topics.lock() {
+                        Ok(topics) => topics.iter().cloned().collect(),
+                        Err(_e) => return,
+                    },
+                    // The publisher is dropped.
+                    None => return,
+                };
+                client.send_producer_heartbeat(&group, &topics).await;
+            }
+        });
+    }
+
+    /// Publish messages of the same topic to one queue at once. Identifiers of the messages in the result are
+    /// joined by commas, in order.
+    ///
+    /// # Errors
+    /// Raise ClientError::InvalidBatch if the batch is empty, spans topics or holds delayed messages, and the
+    /// error `publish` raises if any message is invalid or the batch as a whole is too large.
+    pub async fn publish_batch(&self, messages: &[Message]) -> Result<SendResult, ClientError> {
+        for message in messages {
+            validator::check_message(message, self.option.max_message_size)?;
+        }
+        let mut msg_ids = vec![];
+        let messages: Vec<Message> = messages
+            .iter()
+            .map(|message| {
+                let mut message = message.clone();
+                let msg_id = message
+                    .properties
+                    .entry(property::UNIQ_CLIENT_MESSAGE_ID_KEYIDX.to_owned())
+                    .or_insert_with(|| unique_id(&self.client.client_id));
+                msg_ids.push(msg_id.clone());
+                message
+            })
+            .collect();
+        let body = message::encode_batch(&messages)?;
+        validator::check_message_size(body.len(), self.option.max_message_size)?;
+        let (body, sys_flag) = self.compress_body(&body, Compression::Default)?;
+
+        let (addr, mq) = self.select_queue(&messages[0].topic).await?;
+        let header = protocol::SendMessageRequestHeader {
+            producer_group: self.group.clone(),
+            topic: mq.topic.clone(),
+            default_topic: self.option.default_topic.clone(),
+            default_topic_queue_nums: self.option.default_topic_queue_nums,
+            queue_id: mq.queue_id,
+            sys_flag,
+            born_timestamp: current_millis(),
+            flag: 0,
+            properties: Some(String::new()),
+            reconsume_times: None,
+            unit_mode: None,
+            batch: Some(true),
+            max_reconsume_times: None,
+        };
+        let mut frame = Frame::request(RequestCode::SendBatchMessage, header);
+        frame.body = body;
+        self.invoke_send(&addr, mq, frame, msg_ids.join(",")).await
+    }
+
+    /// Address messages of the topic sent to queues of the named broker go to, which is its master, or the slave
+    /// acting as master without `send_to_master_only`.
+    ///
+    /// # Errors
+    /// Raise ClientError::BrokerNotFound if the route of the topic lists no node of the broker to publish to.
+    pub async fn broker_addr(&self, topic: &str, broker_name: &str) -> Result<String, ClientError> {
+        self.publish_info(topic)
+            .await?
+            .broker_addr(broker_name)
+            .map(str::to_owned)
+            .ok_or_else(|| ClientError::BrokerNotFound(broker_name.to_owned()))
+    }
+
+    /// Select the next writable queue of the topic, in round-robin fashion, along with the address to send to.
+    async fn select_queue(&self, topic: &str) -> Result<(String, MessageQueue), ClientError> {
+        let publish_info = self.publish_info(topic).await?;
+        let mq = publish_info
+            .select_queue()
+            .ok_or_else(|| ClientError::RouteNotFound(topic.to_owned()))?
+            .clone();
+        let addr = publish_info
+            .broker_addr(&mq.broker_name)
+            .ok_or_else(|| ClientError::BrokerNotFound(mq.broker_name.clone()))?
+            .to_owned();
+        Ok((addr, mq))
+    }
+
+    /// Publish info of the topic, derived anew whenever its route changes.
+    async fn publish_info(&self, topic: &str) -> Result<Arc<TopicPublishInfo>, ClientError> {
+        let (route, auto_create) = self.publish_route(topic).await?;
+        let mut publish_infos = self
+            .publish_infos
+            .lock()
+            .map_err(|_e| ClientError::Unknown)?;
+        if let Some((cached_route, publish_info)) = publish_infos.get(topic) {
+            if Arc::ptr_eq(cached_route, &route) {
+                return Ok(Arc::clone(publish_info));
+            }
+        }
+        // Brokers create as many queues as requested, up to the number the default topic has.
+        let max_queue_nums = if auto_create {
+            self.option.default_topic_queue_nums
+        } else {
+            i32::MAX
+        };
+        let publish_info = Arc::new(TopicPublishInfo::with_options(
+            topic,
+            &route,
+            max_queue_nums,
+            self.option.send_to_master_only,
+        ));
+        publish_infos.insert(topic.to_owned(), (route, Arc::clone(&publish_info)));
+        Ok(publish_info)
+    }
+
+    /// Route to publish to the topic: its own if name servers know it, or that of the default topic otherwise,
+    /// along with whether it is the latter. Name servers are asked again once in a while until the topic appears.
+    ///
+    /// # Errors
+    /// Raise ClientError::RouteNotFound if neither the topic nor the default topic exists.
+    async fn publish_route(
+        &self,
+        topic: &str,
+    ) -> Result<(Arc<protocol::TopicRouteData>, bool), ClientError> {
+        if let Some(route) = self.client.routes.route(topic)? {
+            return Ok((route, false));
+        }
+        let queried_recently = self
+            .auto_create_topics
+            .lock()
+            .map_err(|_e| ClientError::Unknown)?
+            .get(topic)
+            .is_some_and(|at| at.elapsed() < self.client.option.poll_name_server_interval);
+        if !queried_recently {
+            let timeout = self.client.option.request_timeout;
+            match self
+                .client
+                .routes
+                .query(topic, &self.client.connections, timeout)
+                .await
+            {
+                Ok(route) => {
+                    if let Ok(mut topics) = self.auto_create_topics.lock() {
+                        topics.remove(topic);
+                    }
+                    return Ok((route, false));
+                }
+                Err(ClientError::RouteNotFound(_)) => {
+                    if let Ok(mut topics) = self.auto_create_topics.lock() {
+                        topics.insert(topic.to_owned(), Instant::now());
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        match self
+            .client
+            .routes
+            .get_or_query(
+                &self.option.default_topic,
+                &self.client.connections,
+                self.client.option.request_timeout,
+            )
+            .await
+        {
+            Ok(route) => Ok((route, true)),
+            Err(ClientError::RouteNotFound(_)) => Err(ClientError::RouteNotFound(topic.to_owned())),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn send(
+        &self,
+        addr: &str,
+        mq: MessageQueue,
+        message: &Message,
+    ) -> Result<SendResult, ClientError> {
+        let mut properties = message.encode_properties();
+        let msg_id = match message
+            .properties
+            .get(property::UNIQ_CLIENT_MESSAGE_ID_KEYIDX)
+        {
+            Some(msg_id) => msg_id.clone(),
+            None => {
+                let msg_id = unique_id(&self.client.client_id);
+                properties += &message::encode_properties(
+                    &[(
+                        property::UNIQ_CLIENT_MESSAGE_ID_KEYIDX.to_owned(),
+                        msg_id.clone(),
+                    )]
+                    .into_iter()
+                    .collect(),
+                );
+                msg_id
+            }
+        };
+
+        // Brokers move messages sent back to a retry topic to the dead letter queue once their retries exhaust.
+        let (reconsume_times, max_reconsume_times) =
+            if mq.topic.starts_with(message::RETRY_GROUP_TOPIC_PREFIX) {
+                let parse = |key| {
+                    message
+                        .properties
+                        .get(key)
+                        .and_then(|value: &String| value.parse().ok())
+                };
+                (
+                    parse(property::RECONSUME_TIME),
+                    parse(property::MAX_RECONSUME_TIMES),
+                )
+            } else {
+                (None, None)
+            };
+
+        let (body, sys_flag) = self.compress_body(&message.body, message.compression)?;
+        let header = protocol::SendMessageRequestHeader {
+            producer_group: self.group.clone(),
+            topic: mq.topic.clone(),
+            default_topic: self.option.default_topic.clone(),
+            default_topic_queue_nums: self.option.default_topic_queue_nums,
+            queue_id: mq.queue_id,
+            sys_flag,
+            born_timestamp: current_millis(),
+            flag: 0,
+            properties: Some(properties),
+            reconsume_times,
+            unit_mode: None,
+            batch: None,
+            max_reconsume_times,
+        };
+        // Brokers push replies to the requesting client rather than store them.
+        let code = if message.is_reply() {
+            RequestCode::SendReplyMessage
+        } else {
+            RequestCode::SendMessage
+        };
+        let mut frame = Frame::request(code, header);
+        frame.body = body;
+        self.invoke_send(addr, mq, frame, msg_id).await
+    }
+
+    /// Compress the body as the message or this publisher requires, returning it along with the bits to set in
+    /// `sys_flag`.
+    fn compress_body(
+        &self,
+        body: &bytes::Bytes,
+        compression: Compression,
+    ) -> Result<(bytes::Bytes, i32), ClientError> {
+        let compression_type = match compression {
+            Compression::Disabled => None,
+            Compression::Always(compression_type) => Some(compression_type),
+            Compression::Default => (body.len() > self.option.compress_body_threshold)
+                .then_some(self.option.compression_type),
+        };
+        match compression_type {
+            Some(compression_type) => {
+                compression::compress(body, compression_type, self.option.compression_level)
+            }
+            None => Ok((body.clone(), 0)),
+        }
+    }
+
+    async fn invoke_send(
+        &self,
+        addr: &str,
+        mq: MessageQueue,
+        frame: Frame,
+        msg_id: String,
+    ) -> Result<SendResult, ClientError> {
+        let response = self.client.invoke_vip(addr, frame).await?;
+
+        let status = match response.code {
+            code if code == ResponseCode::Success as i32 => SendStatus::SendOk,
+            code if code == ResponseCode::FlushDiskTimeout as i32 => SendStatus::FlushDiskTimeout,
+            code if code == ResponseCode::FlushSlaveTimeout as i32 => SendStatus::FlushSlaveTimeout,
+            code if code == ResponseCode::SlaveNotAvailable as i32 => SendStatus::SlaveNotAvailable,
+            _ => {
+                response.ensure_success()?;
+                SendStatus::SendOk
+            }
+        };
+        let header = protocol::SendMessageResponseHeader::try_from(&response.ext_fields)?;
+        Ok(SendResult {
+            status,
+            msg_id,
+            offset_msg_id: header.msg_id,
+            message_queue: MessageQueue {
+                queue_id: header.queue_id,
+                ..mq
+            },
+            queue_offset: header.queue_offset,
+        })
+    }
+}
+
+/// Complete pending requests with replies pushed through PUSH_REPLY_MESSAGE_TO_CLIENT.
+struct ReplyProcessor {
+    replies: PendingReplies,
+}
+
+impl RequestProcessor for ReplyProcessor {
+    fn process(&self, request: &Frame) -> Frame {
+        let mut response = Frame::new();
+        let header = match protocol::ReplyMessageRequestHeader::try_from(&request.ext_fields) {
+            Ok(header) => header,
+            Err(e) => {
+                response.code = ResponseCode::SystemError as i32;
+                response.remark = e.to_string();
+                return response;
+            }
+        };
+        let body = match compression::decompress(request.body(), header.sys_flag) {
+            Ok(body) => body,
+            Err(e) => {
+                response.code = ResponseCode::SystemError as i32;
+                response.remark = e.to_string();
+                return response;
+            }
+        };
+        let properties = message::decode_properties(&header.properties);
+        let mut reply = MessageExt::from_properties(header.topic, properties, body);
+        reply.queue_id = header.queue_id;
+        reply.sys_flag = header.sys_flag;
+        reply.born_timestamp = header.born_timestamp;
+        reply.store_timestamp = header.store_timestamp;
+        reply.reconsume_times = header.reconsume_times;
+        if let Some(host) = header.born_host {
+            reply.born_host = host;
+        }
+        if let Some(host) = header.store_host {
+            reply.store_host = host;
+        }
+
+        let waiter = match reply.property(property::CORRELATION_ID) {
+            Some(correlation_id) => self
+                .replies
+                .lock()
+                .ok()
+                .and_then(|mut replies| replies.remove(correlation_id)),
+            None => None,
+        };
+        match waiter {
+            Some(waiter) => {
+                let _ = waiter.send(reply);
+            }
+            // The request timed out already.
+            None => eprintln!("Discard reply {} as no request awaits it", reply.msg_id),
+        }
+        response
+    }
+}
+
+/// Generate a message identifier unique across clients: hash of client id, process id, timestamp and a sequence.
+fn unique_id(client_id: &str) -> String {
+    static SEQUENCE: AtomicU32 = AtomicU32::new(0);
+    let mut hasher = DefaultHasher::new();
+    client_id.hash(&mut hasher);
+    format!(
+        "{:08X}{:04X}{:012X}{:08X}",
+        hasher.finish() as u32,
+        std::process::id() as u16,
+        current_millis() & 0xFFFF_FFFF_FFFF,
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::tests::{mock_cluster, mock_server, route_json};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_publish() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let server = mock_cluster(2, Arc::clone(&requests), |request, response| {
+            if let 10 | 320 = request.code {
+                response.code = ResponseCode::FlushDiskTimeout as i32;
+                response.put_ext_field("msgId", "0A00000100002A9F0000000000000407");
+                response.put_ext_field("queueId", &request.ext_fields["queueId"]);
+                response.put_ext_field("queueOffset", "7");
+            }
+            true
+        })
+        .await;
+
+        let option = ClientOption {
+            name_server: server,
+            ..Default::default()
+        };
+        let publisher = Publisher::new("G1", option)?;
+        let mut message = Message::new("T1", "hello");
+        message.tag = "TagA".to_owned();
+        let first = publisher.publish(&message).await?;
+        let second = publisher.publish(&message).await?;
+        assert_eq!(first.status, SendStatus::FlushDiskTimeout);
+        assert_eq!(first.queue_offset, 7);
+        assert_ne!(first.message_queue, second.message_queue);
+        assert_ne!(first.msg_id, second.msg_id);
+        assert_eq!(first.msg_id.len(), 32);
+
+        let batch = [Message::new("T1", "a"), Message::new("T1", "b")];
+        let result = publisher.publish_batch(&batch).await?;
+        let msg_ids: Vec<&str> = result.msg_id.split(',').collect();
+        assert_eq!(msg_ids.len(), 2);
+        assert_ne!(msg_ids[0], msg_ids[1]);
+        let delayed = [Message::new("T1", "a").with_delay_level(1)?];
+        assert!(matches!(
+            publisher.publish_batch(&delayed).await,
+            Err(ClientError::InvalidBatch(_))
+        ));
+
+        let requests = requests.lock().unwrap();
+        let send = requests.iter().find(|r| r.code == 10).unwrap();
+        assert_eq!(send.body, bytes::Bytes::from_static(b"hello"));
+        let properties = message::decode_properties(&send.ext_fields["properties"]);
+        assert_eq!(properties.get("TAGS"), Some(&"TagA".to_owned()));
+        assert_eq!(properties.get("UNIQ_KEY"), Some(&first.msg_id));
+        let send = requests.iter().find(|r| r.code == 320).unwrap();
+        assert_eq!(send.ext_fields["batch"], "true");
+        assert!(send.body.len() > 2 * (20 + 1 + 2));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_before_sending() -> Result<(), ClientError> {
+        // No server listens, so any error but validation ones would be raised by connecting.
+        let option = ClientOption {
+            name_server: "127.0.0.1:1".to_owned(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            Publisher::new("G 1", option.clone()),
+            Err(ClientError::IllegalGroup(_))
+        ));
+        let publisher = Publisher::with_option(
+            "G1",
+            option,
+            PublisherOption {
+                max_message_size: 8,
+                ..Default::default()
+            },
+        )?;
+        let result = publisher.publish(&Message::new("TBW102", "body")).await;
+        assert!(matches!(result, Err(ClientError::ReservedTopic(_))));
+        let result = publisher.publish(&Message::new("T1", "")).await;
+        assert!(matches!(result, Err(ClientError::EmptyBody)));
+        let result = publisher.publish(&Message::new("T1", "large body")).await;
+        assert!(matches!(result, Err(ClientError::MessageTooLarge { .. })));
+        let batch = [Message::new("T1", "body"), Message::new("T1", "body")];
+        let result = publisher.publish_batch(&batch).await;
+        assert!(matches!(result, Err(ClientError::MessageTooLarge { .. })));
+        let result = publisher
+            .request(&Message::new("TBW102", "body"), Duration::from_secs(1))
+            .await;
+        assert!(matches!(result, Err(ClientError::ReservedTopic(_))));
+        assert!(publisher.request_topics.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compression() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let server = mock_cluster(1, Arc::clone(&requests), |_, response| {
+            response.put_ext_field("msgId", "0A00000100002A9F0000000000000407");
+            response.put_ext_field("queueId", "0");
+            response.put_ext_field("queueOffset", "0");
+            true
+        })
+        .await;
+
+        let client_option = ClientOption {
+            name_server: server,
+            ..Default::default()
+        };
+        let option = PublisherOption {
+            compress_body_threshold: 100,
+            ..Default::default()
+        };
+        let publisher = Publisher::with_option("G1", client_option, option)?;
+        let body = "x".repeat(1000);
+        publisher.publish(&Message::new("T1", body.clone())).await?;
+        let mut message = Message::new("T1", body.clone());
+        message.compression = Compression::Disabled;
+        publisher.publish(&message).await?;
+        let mut message = Message::new("T1", "tiny");
+        message.compression = Compression::Always(CompressionType::Zlib);
+        publisher.publish(&message).await?;
+        publisher.publish(&Message::new("T1", "tiny")).await?;
+        let batch = [Message::new("T1", body.clone()), Message::new("T1", body)];
+        publisher.publish_batch(&batch).await?;
+
+        let requests = requests.lock().unwrap();
+        let sends: Vec<&Frame> = requests.iter().filter(|r| r.code != 105).collect();
+        let sys_flags: Vec<&str> = sends
+            .iter()
+            .map(|r| r.ext_fields["sysFlag"].as_str())
+            .collect();
+        assert_eq!(sys_flags, vec!["769", "0", "769", "0", "769"]);
+        let decompressed = compression::decompress(sends[0].body(), 0x301)?;
+        assert_eq!(decompressed.len(), 1000);
+        assert!(sends[0].body.len() < 100);
+        assert_eq!(sends[1].body.len(), 1000);
+        let batch = compression::decompress(sends[4].body(), 0x301)?;
+        // Each message carries its UNIQ_KEY property only.
+        assert_eq!(batch.len(), 2 * (20 + 1000 + 2 + "UNIQ_KEY".len() + 2 + 32));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auto_create_topic() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let received = Arc::clone(&requests);
+        let broker = mock_server(move |request| {
+            let mut response = Frame::new();
+            response.opaque = request.opaque;
+            response.put_ext_field("msgId", "0A00000100002A9F0000000000000407");
+            response.put_ext_field("queueId", &request.ext_fields["queueId"]);
+            response.put_ext_field("queueOffset", "0");
+            received.lock().unwrap().push(request);
+            Some(response)
+        })
+        .await;
+        let received = Arc::clone(&requests);
+        let created = Arc::new(AtomicBool::new(false));
+        let topic_created = Arc::clone(&created);
+        let server = mock_server(move |request| {
+            let mut response = Frame::new();
+            response.opaque = request.opaque;
+            let queue_nums = match request.ext_fields["topic"].as_str() {
+                "TBW102" => 8,
+                _ if topic_created.load(Ordering::SeqCst) => 3,
+                _ => {
+                    response.code = ResponseCode::TopicNotExist as i32;
+                    return Some(response);
+                }
+            };
+            response.body = bytes::Bytes::from(route_json(&[&broker], queue_nums));
+            received.lock().unwrap().push(request);
+            Some(response)
+        })
+        .await;
+
+        let client_option = ClientOption {
+            name_server: server,
+            poll_name_server_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        let option = PublisherOption {
+            default_topic_queue_nums: 2,
+            ..Default::default()
+        };
+        let publisher = Publisher::with_option("G1", client_option, option)?;
+        let message = Message::new("T1", "body");
+        let mut queue_ids = vec![];
+        for _ in 0..3 {
+            queue_ids.push(publisher.publish(&message).await?.message_queue.queue_id);
+        }
+        assert_eq!(queue_ids, vec![0, 1, 0]);
+
+        created.store(true, Ordering::SeqCst);
+        let mut queue_ids = vec![];
+        for _ in 0..3 {
+            queue_ids.push(publisher.publish(&message).await?.message_queue.queue_id);
+        }
+        queue_ids.sort();
+        assert_eq!(queue_ids, vec![0, 1, 2]);
+
+        let requests = requests.lock().unwrap();
+        let send = requests.iter().find(|r| r.code == 10).unwrap();
+        assert_eq!(send.ext_fields["topic"], "T1");
+        assert_eq!(send.ext_fields["defaultTopic"], "TBW102");
+        assert_eq!(send.ext_fields["defaultTopicQueueNums"], "2");
+        // The default route is cached, while that of the topic is cached once it exists.
+        let queries = |topic| {
+            requests
+                .iter()
+                .filter(|r| r.code == 105 && r.ext_fields["topic"] == topic)
+                .count()
+        };
+        assert_eq!(queries("TBW102"), 1);
+        assert_eq!(queries("T1"), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request() -> Result<(), ClientError> {
+        use crate::connection::Connection;
+        use tokio::net::TcpListener;
+
+        // Serve as both name server and broker, replying to requests tagged `echo` only.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let broker_addr = addr.clone();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let received = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let broker_addr = broker_addr.clone();
+                let received = Arc::clone(&received);
+                tokio::spawn(async move {
+                    let mut connection = Connection::from_stream(stream);
+                    while let Ok(Some(request)) = connection.read_frame().await {
+                        if request.frame_type() == crate::frame::Type::Response {
+                            received.lock().unwrap().push(request);
+                            continue;
+                        }
+                        let mut response = Frame::new();
+                        response.opaque = request.opaque;
+                        response.mark_response_type();
+                        let mut reply = None;
+                        match request.code {
+                            105 => {
+                                response.body = bytes::Bytes::from(route_json(&[&broker_addr], 1));
+                            }
+                            10 => {
+                                response.put_ext_field("msgId", "0A00000100002A9F0000000000000407");
+                                response.put_ext_field("queueId", "0");
+                                response.put_ext_field("queueOffset", "0");
+                                let properties =
+                                    message::decode_properties(&request.ext_fields["properties"]);
+                                if properties.get("TAGS").map(String::as_str) == Some("echo") {
+                                    let mut push = Frame::new();
+                                    push.code = RequestCode::PushReplyMessageToClient as i32;
+                                    push.opaque = 1000;
+                                    for (key, value) in [
+                                        ("topic", "C1_REPLY_TOPIC"),
+                                        ("queueId", "0"),
+                                        ("sysFlag", "0"),
+                                        ("bornTimestamp", "1"),
+                                        ("bornHost", "/10.0.0.2:5000"),
+                                        ("storeTimestamp", "2"),
+                                        ("storeHost", "/10.0.0.1:10911"),
+                                    ] {
+                                        push.put_ext_field(key, value);
+                                    }
+                                    let reply_properties: HashMap<String, String> = [
+                                        ("CORRELATION_ID", &properties["CORRELATION_ID"]),
+                                        ("MSG_TYPE", &"reply".to_owned()),
+                                    ]
+                                    .into_iter()
+                                    .map(|(k, v)| (k.to_owned(), v.clone()))
+                                    .collect();
+                                    push.put_ext_field(
+                                        "properties",
+                                        &message::encode_properties(&reply_properties),
+                                    );
+                                    push.body = request.body();
+                                    reply = Some(push);
+                                }
+                            }
+                            _ => {}
+                        }
+                        received.lock().unwrap().push(request);
+                        if connection.write_frame(&response).await.is_err() {
+                            break;
+                        }
+                        if let Some(push) = reply {
+                            if connection.write_frame(&push).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        let option = ClientOption {
+            name_server: addr,
+            ..Default::default()
+        };
+        let publisher = Publisher::new("G1", option)?;
+        let mut message = Message::new("T1", "ping");
+        message.tag = "echo".to_owned();
+        let reply = publisher.request(&message, Duration::from_secs(3)).await?;
+        assert_eq!(reply.body, bytes::Bytes::from_static(b"ping"));
+        assert_eq!(reply.topic, "C1_REPLY_TOPIC");
+        assert_eq!(reply.store_host, "10.0.0.1:10911".parse().unwrap());
+
+        message.tag = "silent".to_owned();
+        let result = publisher
+            .request(&message, Duration::from_millis(200))
+            .await;
+        assert!(matches!(result, Err(ClientError::ReplyTimeout(_))));
+        assert!(publisher.replies.lock().unwrap().is_empty());
+
+        let requests = requests.lock().unwrap();
+        let heartbeat = requests.iter().find(|r| r.code == 34).unwrap();
+        let heartbeat = String::from_utf8_lossy(&heartbeat.body);
+        assert!(heartbeat.contains(r#""producerDataSet":[{"groupName":"G1"}]"#));
+        let send = requests.iter().find(|r| r.code == 10).unwrap();
+        let properties = message::decode_properties(&send.ext_fields["properties"]);
+        assert_eq!(properties["TTL"], "3000");
+        assert_eq!(properties["REPLY_TO_CLIENT"], publisher.client.client_id);
+        // The pushed reply is acknowledged.
+        assert!(requests.iter().any(|r| r.opaque == 1000 && r.code == 0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_until_registered() -> Result<(), ClientError> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Reject the first heartbeat and hold the ones after it.
+        let requests = Arc::new(Mutex::new(vec![]));
+        let heartbeats = AtomicUsize::new(0);
+        let server = mock_cluster(1, Arc::clone(&requests), move |request, response| {
+            match request.code {
+                34 => {
+                    if heartbeats.fetch_add(1, Ordering::SeqCst) > 0 {
+                        return false;
+                    }
+                    response.code = ResponseCode::SystemError as i32;
+                }
+                10 => {
+                    response.put_ext_field("msgId", "0A00000100002A9F0000000000000407");
+                    response.put_ext_field("queueId", "0");
+                    response.put_ext_field("queueOffset", "0");
+                }
+                _ => {}
+            }
+            true
+        })
+        .await;
+
+        let option = ClientOption {
+            name_server: server,
+            ..Default::default()
+        };
+        let publisher = Publisher::new("G1", option)?;
+        let message = Message::new("T1", "ping");
+        let result = publisher
+            .request(&message, Duration::from_millis(200))
+            .await;
+        assert!(matches!(result, Err(ClientError::ReplyTimeout(_))));
+        assert!(publisher.request_topics.lock().unwrap().is_empty());
+
+        // Registering again is bounded by the timeout of the request.
+        let start = tokio::time::Instant::now();
+        let result = publisher
+            .request(&message, Duration::from_millis(200))
+            .await;
+        assert!(matches!(result, Err(ClientError::ReplyTimeout(_))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(publisher.request_topics.lock().unwrap().is_empty());
+        assert!(publisher.replies.lock().unwrap().is_empty());
+        let sends = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.code == 10)
+            .count();
+        assert_eq!(sends, 1);
+        Ok(())
+    }
 }
@@ -0,0 +1,86 @@
+//!
+//! Strategies to allocate message queues of a topic among consumer clients of the same group.
+//!
+use crate::message::MessageQueue;
+
+/// Allocate queues in contiguous, nearly equal-sized ranges, identical to `AllocateMessageQueueAveragely` of the Java SDK.
+///
+/// Both `mq_all` and `cid_all` are expected to be sorted so that every client computes the same allocation.
+pub(crate) fn allocate_averagely(
+    client_id: &str,
+    mq_all: &[MessageQueue],
+    cid_all: &[String],
+) -> Vec<MessageQueue> {
+    let index = match cid_all.iter().position(|cid| cid == client_id) {
+        Some(index) => index,
+        None => return vec![],
+    };
+    if mq_all.is_empty() {
+        return vec![];
+    }
+
+    let remainder = mq_all.len() % cid_all.len();
+    let average = if mq_all.len() <= cid_all.len() {
+        1
+    } else if remainder > 0 && index < remainder {
+        mq_all.len() / cid_all.len() + 1
+    } else {
+        mq_all.len() / cid_all.len()
+    };
+    let start = if remainder > 0 && index < remainder {
+        index * average
+    } else {
+        index * average + remainder
+    };
+    let range = average.min(mq_all.len().saturating_sub(start));
+    (0..range)
+        .map(|i| mq_all[(start + i) % mq_all.len()].clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queues(n: i32) -> Vec<MessageQueue> {
+        (0..n)
+            .map(|queue_id| MessageQueue {
+                topic: "T1".to_owned(),
+                broker_name: "b1".to_owned(),
+                queue_id,
+            })
+            .collect()
+    }
+
+    fn ids(allocated: &[MessageQueue]) -> Vec<i32> {
+        allocated.iter().map(|mq| mq.queue_id).collect()
+    }
+
+    #[test]
+    fn test_allocate_averagely() {
+        let mq_all = queues(8);
+        let cid_all: Vec<String> = vec!["c0".to_owned(), "c1".to_owned(), "c2".to_owned()];
+        assert_eq!(
+            ids(&allocate_averagely("c0", &mq_all, &cid_all)),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            ids(&allocate_averagely("c1", &mq_all, &cid_all)),
+            vec![3, 4, 5]
+        );
+        assert_eq!(
+            ids(&allocate_averagely("c2", &mq_all, &cid_all)),
+            vec![6, 7]
+        );
+        assert!(allocate_averagely("c3", &mq_all, &cid_all).is_empty());
+    }
+
+    #[test]
+    fn test_allocate_more_clients_than_queues() {
+        let mq_all = queues(2);
+        let cid_all: Vec<String> = vec!["c0".to_owned(), "c1".to_owned(), "c2".to_owned()];
+        assert_eq!(ids(&allocate_averagely("c0", &mq_all, &cid_all)), vec![0]);
+        assert_eq!(ids(&allocate_averagely("c1", &mq_all, &cid_all)), vec![1]);
+        assert!(allocate_averagely("c2", &mq_all, &cid_all).is_empty());
+    }
+}
@@ -1,33 +1,38 @@
 //!
 //! This module defines RouteManager to dynamically fetch and refresh routes for each topic in use.
 //!
+use crate::connection::{self, ConnectionManager};
 use crate::error::ClientError;
-use crate::protocol;
+use crate::frame::{Frame, RequestCode, ResponseCode};
+use crate::message::MessageQueue;
+use crate::protocol::{self, TopicRouteData};
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 /// RouteManager maintains route entries for each topic.
 pub(crate) struct RouteManager {
-    /// Endpoints remain constant after construction.
-    endpoints: Arc<RwLock<Vec<SocketAddr>>>,
+    /// Endpoints in form of `host:port` remain constant after construction.
+    endpoints: Arc<RwLock<Vec<String>>>,
 
     /// Topic routes are supposed to be refreshed after configured interval.
     topic_routes: Arc<Mutex<HashMap<String, Arc<protocol::TopicRouteData>>>>,
+
+    /// Index of the name server to query next.
+    index: AtomicUsize,
 }
 
 impl RouteManager {
     pub(crate) fn new(addrs: &str) -> Result<Self, ClientError> {
         let endpoints: Vec<_> = addrs
             .split(';')
-            .flat_map(|addr| match addr.parse::<SocketAddr>() {
-                Ok(socket_addr) => Some(socket_addr),
+            .map(str::trim)
+            .filter(|addr| !addr.is_empty())
+            .flat_map(|addr| match connection::split_host_port(addr) {
+                Ok(_) => Some(addr.to_owned()),
                 Err(e) => {
-                    eprintln!(
-                        "Failed to parse name server address {}. Cause: {}",
-                        addr,
-                        e.to_string()
-                    );
+                    eprintln!("Failed to parse name server address {}. Cause: {}", addr, e);
                     None
                 }
             })
@@ -35,40 +40,349 @@ impl RouteManager {
         Ok(Self {
             endpoints: Arc::new(RwLock::new(endpoints)),
             topic_routes: Arc::new(Mutex::new(HashMap::new())),
+            index: AtomicUsize::new(0),
         })
     }
 
+    /// Addresses of all name servers.
+    pub(crate) fn endpoints(&self) -> Vec<String> {
+        self.endpoints
+            .read()
+            .map(|endpoints| endpoints.clone())
+            .unwrap_or_default()
+    }
+
     pub(crate) fn route(
-        &mut self,
+        &self,
         topic: &str,
     ) -> Result<Option<Arc<protocol::TopicRouteData>>, ClientError> {
         {
             let guard = match self.topic_routes.lock() {
                 Ok(map) => map,
                 Err(e) => {
-                    eprintln!("Lock is poisoned. Cause: {}", e.to_string());
+                    eprintln!("Lock is poisoned. Cause: {}", e);
                     return Err(ClientError::Unknown);
                 }
             };
 
-            match guard.get(topic) {
-                Some(value) => return Ok(Some(Arc::clone(value))),
-                None => {}
-            };
+            if let Some(value) = guard.get(topic) {
+                return Ok(Some(Arc::clone(value)));
+            }
         }
 
         Ok(None)
     }
+
+    /// Return cached route of the topic, querying name servers if it is not cached yet.
+    pub(crate) async fn get_or_query(
+        &self,
+        topic: &str,
+        connections: &ConnectionManager,
+        timeout: Duration,
+    ) -> Result<Arc<protocol::TopicRouteData>, ClientError> {
+        if let Some(route) = self.route(topic)? {
+            return Ok(route);
+        }
+        self.query(topic, connections, timeout).await
+    }
+
+    /// Query route of the topic from name servers, trying each of them in turn, and refresh the cache.
+    ///
+    /// # Errors
+    /// Raise ClientError::RouteNotFound if name servers do not know the topic, or ClientError::ServerError if they
+    /// fail otherwise.
+    pub(crate) async fn query(
+        &self,
+        topic: &str,
+        connections: &ConnectionManager,
+        timeout: Duration,
+    ) -> Result<Arc<protocol::TopicRouteData>, ClientError> {
+        let response = self
+            .invoke(connections, timeout, || {
+                Frame::request(
+                    RequestCode::GetRouteInfoByTopic,
+                    protocol::GetRouteInfoRequestHeader::new(topic),
+                )
+            })
+            .await?;
+        if response.code == ResponseCode::TopicNotExist as i32 {
+            return Err(ClientError::RouteNotFound(topic.to_owned()));
+        }
+        response.ensure_success()?;
+
+        let route: Arc<protocol::TopicRouteData> = Arc::new(response.json_body()?);
+        match self.topic_routes.lock() {
+            Ok(mut map) => {
+                map.insert(topic.to_owned(), Arc::clone(&route));
+            }
+            Err(_e) => return Err(ClientError::Unknown),
+        }
+        Ok(route)
+    }
+
+    /// Send the request built by `request` to name servers in turn, until one of them responds.
+    ///
+    /// # Errors
+    /// Raise ClientError::BadAddress if no name server is configured, or the error of the last name server tried
+    /// if none responds.
+    pub(crate) async fn invoke<F>(
+        &self,
+        connections: &ConnectionManager,
+        timeout: Duration,
+        request: F,
+    ) -> Result<Frame, ClientError>
+    where
+        F: Fn() -> Frame,
+    {
+        let endpoints: Vec<String> = match self.endpoints.read() {
+            Ok(endpoints) => endpoints.clone(),
+            Err(_e) => return Err(ClientError::Unknown),
+        };
+        if endpoints.is_empty() {
+            return Err(ClientError::BadAddress(
+                "No name server is available".to_owned(),
+            ));
+        }
+
+        let mut last_error = ClientError::Unknown;
+        for _ in 0..endpoints.len() {
+            let index = self.index.fetch_add(1, Ordering::Relaxed) % endpoints.len();
+            match connections
+                .invoke(&endpoints[index], request(), timeout)
+                .await
+            {
+                Ok(response) => return Ok(response),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+/// Queues of a topic that messages may be published to, as derived from its route.
+#[derive(Debug)]
+pub struct TopicPublishInfo {
+    order_topic: bool,
+    message_queues: Vec<MessageQueue>,
+
+    /// Addresses messages are sent to, keyed by broker name.
+    broker_addrs: HashMap<String, String>,
+
+    /// Round-robin index of the queue to publish to next.
+    index: AtomicUsize,
+}
+
+impl TopicPublishInfo {
+    /// List writable queues of brokers with a master, or, for ordered topics, those `order_topic_conf` of the route
+    /// lists in form of `broker-a:4;broker-b:4`.
+    pub fn new(topic: &str, route: &TopicRouteData) -> Self {
+        Self::with_options(topic, route, i32::MAX, true)
+    }
+
+    /// Build publish info listing at most `max_queue_nums` queues of each broker, as brokers create when topics are
+    /// created automatically through the route of the default topic.
+    ///
+    /// Unless `send_to_master_only`, brokers whose master is down are published to through the slave with the
+    /// lowest id, which is expected to act as master.
+    pub(crate) fn with_options(
+        topic: &str,
+        route: &TopicRouteData,
+        max_queue_nums: i32,
+        send_to_master_only: bool,
+    ) -> Self {
+        let broker_addrs: HashMap<String, String> = route
+            .broker_datas
+            .iter()
+            .filter_map(|broker_data| {
+                let addr = if send_to_master_only {
+                    broker_data.master_addr()
+                } else {
+                    broker_data
+                        .select_addr(protocol::MASTER_ID)
+                        .map(|(addr, _)| addr)
+                };
+                addr.map(|addr| (broker_data.broker_name.clone(), addr.to_owned()))
+            })
+            .collect();
+        let queues = |broker_name: &str, queue_nums: i32| {
+            (0..queue_nums.min(max_queue_nums))
+                .map(|queue_id| MessageQueue {
+                    topic: topic.to_owned(),
+                    broker_name: broker_name.to_owned(),
+                    queue_id,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let order_topic_conf = route.order_topic_conf.as_deref().unwrap_or_default();
+        let message_queues = if order_topic_conf.is_empty() {
+            route
+                .queue_datas
+                .iter()
+                .filter(|queue_data| {
+                    queue_data.writable() && broker_addrs.contains_key(&queue_data.broker_name)
+                })
+                .flat_map(|queue_data| queues(&queue_data.broker_name, queue_data.write_queue_nums))
+                .collect()
+        } else {
+            order_topic_conf
+                .split(';')
+                .filter_map(|item| item.split_once(':'))
+                .filter_map(|(broker_name, nums)| Some((broker_name, nums.trim().parse().ok()?)))
+                .flat_map(|(broker_name, queue_nums)| queues(broker_name.trim(), queue_nums))
+                .collect()
+        };
+        Self {
+            order_topic: !order_topic_conf.is_empty(),
+            message_queues,
+            broker_addrs,
+            index: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether the topic is ordered, so that messages must be published to queues selected by the application.
+    pub fn is_order_topic(&self) -> bool {
+        self.order_topic
+    }
+
+    pub fn message_queues(&self) -> &[MessageQueue] {
+        &self.message_queues
+    }
+
+    /// Address messages to queues of the named broker are sent to.
+    pub fn broker_addr(&self, broker_name: &str) -> Option<&str> {
+        self.broker_addrs.get(broker_name).map(String::as_str)
+    }
+
+    /// Select the next queue in round-robin fashion, safe to call from multiple threads.
+    pub fn select_queue(&self) -> Option<&MessageQueue> {
+        if self.message_queues.is_empty() {
+            return None;
+        }
+        let index = self.index.fetch_add(1, Ordering::Relaxed);
+        self.message_queues.get(index % self.message_queues.len())
+    }
+}
+
+/// Queues of a topic that messages may be subscribed from, as derived from its route.
+#[derive(Debug, Clone)]
+pub struct TopicSubscribeInfo {
+    message_queues: Vec<MessageQueue>,
+}
+
+impl TopicSubscribeInfo {
+    /// List readable queues of brokers with a master, sorted so that all members of a group see them in the same
+    /// order.
+    pub fn new(topic: &str, route: &TopicRouteData) -> Self {
+        let mut message_queues: Vec<MessageQueue> = route
+            .queue_datas
+            .iter()
+            .filter(|queue_data| {
+                queue_data.readable() && route.master_addr(&queue_data.broker_name).is_some()
+            })
+            .flat_map(|queue_data| {
+                (0..queue_data.read_queue_nums).map(|queue_id| MessageQueue {
+                    topic: topic.to_owned(),
+                    broker_name: queue_data.broker_name.clone(),
+                    queue_id,
+                })
+            })
+            .collect();
+        message_queues.sort();
+        Self { message_queues }
+    }
+
+    pub fn message_queues(&self) -> &[MessageQueue] {
+        &self.message_queues
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::RouteManager;
+    use super::*;
 
     #[test]
     fn test_route_manager_new() -> Result<(), Box<dyn std::error::Error>> {
         let addrs = "8.8.8.8:80;4.4.4.4.3:80";
-        let manager = RouteManager::new(addrs)?;
+        let _manager = RouteManager::new(addrs)?;
+
+        let manager = RouteManager::new("ns.svc:9876; [::1]:9876;localhost;")?;
+        assert_eq!(
+            *manager.endpoints.read().unwrap(),
+            vec!["ns.svc:9876".to_owned(), "[::1]:9876".to_owned()]
+        );
+        Ok(())
+    }
+
+    /// Broker `b1` serves 2 queues, `b2` has no master, and `b3` is read-only.
+    const ROUTE: &str = r#"{"brokerDatas":[{"brokerAddrs":{"0":"10.0.0.1:10911","1":"10.0.0.2:10911"},"brokerName":"b1","cluster":"C1"},{"brokerAddrs":{"1":"10.0.0.3:10911"},"brokerName":"b2","cluster":"C1"},{"brokerAddrs":{"0":"10.0.0.4:10911"},"brokerName":"b3","cluster":"C1"}],"filterServerTable":{},"queueDatas":[{"brokerName":"b1","perm":6,"readQueueNums":2,"topicSynFlag":0,"writeQueueNums":2},{"brokerName":"b2","perm":6,"readQueueNums":4,"topicSynFlag":0,"writeQueueNums":4},{"brokerName":"b3","perm":4,"readQueueNums":1,"topicSynFlag":0,"writeQueueNums":1}]}"#;
+
+    fn mq(broker_name: &str, queue_id: i32) -> MessageQueue {
+        MessageQueue {
+            topic: "T1".to_owned(),
+            broker_name: broker_name.to_owned(),
+            queue_id,
+        }
+    }
+
+    #[test]
+    fn test_topic_publish_info() -> Result<(), serde_json::Error> {
+        let route: TopicRouteData = serde_json::from_str(ROUTE)?;
+        let info = TopicPublishInfo::new("T1", &route);
+        assert!(!info.is_order_topic());
+        assert_eq!(info.message_queues(), &[mq("b1", 0), mq("b1", 1)]);
+        assert_eq!(info.broker_addr("b1"), Some("10.0.0.1:10911"));
+        assert_eq!(info.broker_addr("b2"), None);
+
+        // Selection is evenly spread among threads.
+        let info = Arc::new(info);
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let info = Arc::clone(&info);
+                std::thread::spawn(move || {
+                    (0..50)
+                        .map(|_| info.select_queue().unwrap().queue_id)
+                        .sum::<i32>()
+                })
+            })
+            .collect();
+        let total: i32 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(total, 100);
+
+        let capped = TopicPublishInfo::with_options("T1", &route, 1, true);
+        assert_eq!(capped.message_queues(), &[mq("b1", 0)]);
+
+        // The slave of `b2` acts as master.
+        let info = TopicPublishInfo::with_options("T1", &route, 1, false);
+        assert_eq!(info.message_queues(), &[mq("b1", 0), mq("b2", 0)]);
+        assert_eq!(info.broker_addr("b1"), Some("10.0.0.1:10911"));
+        assert_eq!(info.broker_addr("b2"), Some("10.0.0.3:10911"));
+
+        let mut route = route;
+        route.order_topic_conf = Some("b1:1;b3:2".to_owned());
+        let info = TopicPublishInfo::new("T1", &route);
+        assert!(info.is_order_topic());
+        assert_eq!(
+            info.message_queues(),
+            &[mq("b1", 0), mq("b3", 0), mq("b3", 1)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_topic_subscribe_info() -> Result<(), serde_json::Error> {
+        let route: TopicRouteData = serde_json::from_str(ROUTE)?;
+        let info = TopicSubscribeInfo::new("T1", &route);
+        assert_eq!(
+            info.message_queues(),
+            &[mq("b1", 0), mq("b1", 1), mq("b3", 0)]
+        );
+        assert!(route.queue_datas.iter().all(|q| !q.inheritable()));
+        assert!(mq("b1", 1) < mq("b3", 0));
+        assert_eq!(
+            mq("b1", 1).to_string(),
+            "MessageQueue [topic=T1, brokerName=b1, queueId=1]"
+        );
         Ok(())
     }
 }
@@ -0,0 +1,607 @@
+//!
+//! Define `SimpleConsumer`, which pops messages from brokers on demand without client side rebalance, as supported
+//! by RocketMQ 5 brokers.
+//!
+use crate::client::{current_millis, Client, ClientOption};
+use crate::error::ClientError;
+use crate::filter::{self, MessageSelector};
+use crate::frame::{Frame, RequestCode, ResponseCode};
+use crate::message::{self, property, MessageExt};
+use crate::protocol;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Groups popping a queue for the first time start from its max offset.
+const INIT_MODE_MAX: i32 = 1;
+
+/// Options of a simple consumer.
+#[derive(Debug, Clone)]
+pub struct SimpleConsumerOption {
+    /// Consumer group, whose members share messages of subscribed topics.
+    pub group: String,
+
+    /// How long brokers hold a receive request if no message is available.
+    pub await_duration: Duration,
+}
+
+impl Default for SimpleConsumerOption {
+    fn default() -> Self {
+        Self {
+            group: "DEFAULT_CONSUMER".to_owned(),
+            await_duration: Duration::from_secs(20),
+        }
+    }
+}
+
+/// Receipt of a message received by `SimpleConsumer`, with which it is acknowledged or kept invisible for longer.
+///
+/// Brokers describe each popped message by an extra info string, `startOffset popTime invisibleTime reviveQid
+/// retryFlag brokerName queueId queueOffset`, carried by the message as property `POP_CK`. Its `Display` form is
+/// that string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiptHandle {
+    /// Topic of the message, which is not the retry topic of the group even if the message is redelivered.
+    pub topic: String,
+
+    /// Topic the message is actually popped from and acknowledged to, such as `%RETRY%{group}_{topic}` for
+    /// redelivered messages.
+    popped_topic: String,
+    start_offset: i64,
+    pop_time: i64,
+    invisible_time: i64,
+    revive_qid: i32,
+
+    /// Whether the message is popped from the retry topic of the group.
+    retry: bool,
+    broker_name: String,
+    queue_id: i32,
+    queue_offset: i64,
+}
+
+impl ReceiptHandle {
+    /// Parse the extra info of a message of the topic, popped from `popped_topic`.
+    ///
+    /// # Errors
+    /// Raise ClientError::InvalidReceiptHandle if the extra info is malformed.
+    pub(crate) fn parse(
+        topic: &str,
+        popped_topic: &str,
+        extra_info: &str,
+    ) -> Result<Self, ClientError> {
+        let invalid = || ClientError::InvalidReceiptHandle(extra_info.to_owned());
+        let fields: Vec<&str> = extra_info.split(' ').collect();
+        if fields.len() < 8 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            topic: topic.to_owned(),
+            popped_topic: popped_topic.to_owned(),
+            start_offset: fields[0].parse().map_err(|_e| invalid())?,
+            pop_time: fields[1].parse().map_err(|_e| invalid())?,
+            invisible_time: fields[2].parse().map_err(|_e| invalid())?,
+            revive_qid: fields[3].parse().map_err(|_e| invalid())?,
+            retry: fields[4] != "0",
+            broker_name: fields[5].to_owned(),
+            queue_id: fields[6].parse().map_err(|_e| invalid())?,
+            queue_offset: fields[7].parse().map_err(|_e| invalid())?,
+        })
+    }
+
+    /// When the message becomes visible again and is redelivered unless acknowledged, in milliseconds since the Unix
+    /// epoch.
+    pub fn next_visible_time(&self) -> i64 {
+        self.pop_time + self.invisible_time
+    }
+}
+
+impl fmt::Display for ReceiptHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} {} {}",
+            self.start_offset,
+            self.pop_time,
+            self.invisible_time,
+            self.revive_qid,
+            if self.retry { 1 } else { 0 },
+            self.broker_name,
+            self.queue_id,
+            self.queue_offset
+        )
+    }
+}
+
+/// Consumer that receives messages on demand through POP requests. Brokers pick the queues to pop from, so members
+/// of the group need no rebalance and may come and go freely.
+///
+/// Each received message stays invisible to the group for the given duration. Unless acknowledged in time, it is
+/// redelivered through the retry topic of the group.
+pub struct SimpleConsumer {
+    client: Arc<Client>,
+    option: SimpleConsumerOption,
+    subscriptions: Arc<RwLock<HashMap<String, protocol::SubscriptionData>>>,
+
+    /// Round-robin index of the topic and broker to receive from next.
+    index: AtomicUsize,
+    started: AtomicBool,
+    shutdown_tx: watch::Sender<bool>,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl SimpleConsumer {
+    pub fn new(
+        client_option: ClientOption,
+        option: SimpleConsumerOption,
+    ) -> Result<Self, ClientError> {
+        let (tx, rx) = watch::channel(false);
+        Ok(Self {
+            client: Arc::new(Client::new(client_option)?),
+            option,
+            subscriptions: Arc::new(RwLock::new(HashMap::new())),
+            index: AtomicUsize::new(0),
+            started: AtomicBool::new(false),
+            shutdown_tx: tx,
+            shutdown: rx,
+        })
+    }
+
+    /// Subscribe messages of the topic whose tag matches the expression, such as `TagA || TagB`, or `*` for all.
+    ///
+    /// # Errors
+    /// Raise ClientError::InvalidExpression if the expression names no tag.
+    pub fn subscribe(&self, topic: &str, expression: &str) -> Result<(), ClientError> {
+        self.subscribe_with_selector(topic, &MessageSelector::by_tag(expression))
+    }
+
+    /// Subscribe messages of the topic selected by a tag or SQL92 expression.
+    ///
+    /// # Errors
+    /// Raise ClientError::InvalidExpression if the expression is malformed.
+    pub fn subscribe_with_selector(
+        &self,
+        topic: &str,
+        selector: &MessageSelector,
+    ) -> Result<(), ClientError> {
+        let subscription = filter::build_subscription(topic, selector, current_millis())?;
+        self.subscriptions
+            .write()
+            .map_err(|_e| ClientError::Unknown)?
+            .insert(topic.to_owned(), subscription);
+        Ok(())
+    }
+
+    pub fn unsubscribe(&self, topic: &str) -> Result<(), ClientError> {
+        self.subscriptions
+            .write()
+            .map_err(|_e| ClientError::Unknown)?
+            .remove(topic);
+        Ok(())
+    }
+
+    /// Announce subscriptions to brokers, periodically until shutdown.
+    ///
+    /// # Errors
+    /// Raise ClientError::IllegalState if the consumer is started twice or after shutdown.
+    pub async fn start(&self) -> Result<(), ClientError> {
+        if *self.shutdown.borrow() {
+            return Err(ClientError::IllegalState(
+                "Consumer has already been shut down".to_owned(),
+            ));
+        }
+        if self.started.swap(true, Ordering::SeqCst) {
+            return Err(ClientError::IllegalState(
+                "Consumer has already been started".to_owned(),
+            ));
+        }
+        self.client
+            .send_heartbeat(consumer_data(&self.option.group, &self.subscriptions))
+            .await;
+
+        let client = Arc::clone(&self.client);
+        let group = self.option.group.clone();
+        let subscriptions = Arc::clone(&self.subscriptions);
+        let mut shutdown = self.shutdown.clone();
+        let period = self.client.option.heartbeat_interval;
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        client.send_heartbeat(consumer_data(&group, &subscriptions)).await
+                    }
+                    _ = shutdown.changed() => break,
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Unregister from brokers, after which the consumer cannot be started again. Messages received but not
+    /// acknowledged are redelivered once invisible durations elapse.
+    pub async fn shutdown(&self) {
+        if !self.started.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let _ = self.shutdown_tx.send(true);
+        let topics: Vec<String> = self
+            .subscriptions()
+            .into_iter()
+            .map(|subscription| subscription.topic)
+            .collect();
+        self.client
+            .unregister_consumer(&self.option.group, &topics)
+            .await;
+    }
+
+    /// Receive up to `max_num` messages from one of the brokers serving subscribed topics, waiting up to
+    /// `await_duration` for some to arrive. Received messages stay invisible to the group for `invisible_duration`.
+    ///
+    /// Return an empty list if no message arrives in time.
+    ///
+    /// # Errors
+    /// Raise ClientError::IllegalState if the consumer is not started or subscribes no topic.
+    pub async fn receive(
+        &self,
+        max_num: i32,
+        invisible_duration: Duration,
+    ) -> Result<Vec<MessageExt>, ClientError> {
+        if !self.started.load(Ordering::SeqCst) {
+            return Err(ClientError::IllegalState(
+                "Consumer is not started".to_owned(),
+            ));
+        }
+        let mut subscriptions = self.subscriptions();
+        if subscriptions.is_empty() {
+            return Err(ClientError::IllegalState(
+                "No topic is subscribed".to_owned(),
+            ));
+        }
+        subscriptions.sort_by(|a, b| a.topic.cmp(&b.topic));
+
+        // Each broker serving a subscribed topic is a candidate, as brokers pick the queue to pop from.
+        let mut candidates = vec![];
+        for subscription in &subscriptions {
+            let route = self.client.route(&subscription.topic).await?;
+            for queue_data in route.queue_datas.iter().filter(|q| q.readable()) {
+                if let Some(addr) = route.master_addr(&queue_data.broker_name) {
+                    candidates.push((
+                        subscription,
+                        queue_data.broker_name.clone(),
+                        addr.to_owned(),
+                    ));
+                }
+            }
+        }
+        if candidates.is_empty() {
+            return Err(ClientError::RouteNotFound(subscriptions[0].topic.clone()));
+        }
+        let (subscription, broker_name, addr) =
+            candidates[self.index.fetch_add(1, Ordering::Relaxed) % candidates.len()].clone();
+        self.pop(
+            &addr,
+            &broker_name,
+            subscription,
+            max_num,
+            invisible_duration,
+        )
+        .await
+    }
+
+    /// Acknowledge that the message of the receipt handle is consumed, so that it is never redelivered.
+    ///
+    /// # Errors
+    /// Raise ClientError::ServerError if the message became visible again before being acknowledged.
+    pub async fn ack(&self, receipt_handle: &ReceiptHandle) -> Result<(), ClientError> {
+        let addr = self
+            .client
+            .find_master(&receipt_handle.topic, &receipt_handle.broker_name)
+            .await?;
+        let header = protocol::AckMessageRequestHeader {
+            consumer_group: self.option.group.clone(),
+            topic: receipt_handle.popped_topic.clone(),
+            queue_id: receipt_handle.queue_id,
+            extra_info: receipt_handle.to_string(),
+            offset: receipt_handle.queue_offset,
+        };
+        let frame = Frame::request(RequestCode::AckMessage, header);
+        self.client.invoke(&addr, frame).await?.ensure_success()
+    }
+
+    /// Keep the message of the receipt handle invisible for `invisible_duration` from now on, returning the receipt
+    /// handle that replaces the given one.
+    pub async fn change_invisible_duration(
+        &self,
+        receipt_handle: &ReceiptHandle,
+        invisible_duration: Duration,
+    ) -> Result<ReceiptHandle, ClientError> {
+        let addr = self
+            .client
+            .find_master(&receipt_handle.topic, &receipt_handle.broker_name)
+            .await?;
+        let header = protocol::ChangeInvisibleTimeRequestHeader {
+            consumer_group: self.option.group.clone(),
+            topic: receipt_handle.popped_topic.clone(),
+            queue_id: receipt_handle.queue_id,
+            extra_info: receipt_handle.to_string(),
+            offset: receipt_handle.queue_offset,
+            invisible_time: invisible_duration.as_millis() as i64,
+        };
+        let frame = Frame::request(RequestCode::ChangeMessageInvisibleTime, header);
+        let response = self.client.invoke(&addr, frame).await?;
+        response.ensure_success()?;
+        let header = protocol::ChangeInvisibleTimeResponseHeader::try_from(&response.ext_fields)?;
+        Ok(ReceiptHandle {
+            start_offset: receipt_handle.queue_offset,
+            pop_time: header.pop_time,
+            invisible_time: header.invisible_time,
+            revive_qid: header.revive_qid,
+            ..receipt_handle.clone()
+        })
+    }
+
+    fn subscriptions(&self) -> Vec<protocol::SubscriptionData> {
+        self.subscriptions
+            .read()
+            .map(|map| map.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    async fn pop(
+        &self,
+        addr: &str,
+        broker_name: &str,
+        subscription: &protocol::SubscriptionData,
+        max_num: i32,
+        invisible_duration: Duration,
+    ) -> Result<Vec<MessageExt>, ClientError> {
+        let header = protocol::PopMessageRequestHeader {
+            consumer_group: self.option.group.clone(),
+            topic: subscription.topic.clone(),
+            queue_id: -1,
+            max_msg_nums: max_num,
+            invisible_time: invisible_duration.as_millis() as i64,
+            poll_time: self.option.await_duration.as_millis() as i64,
+            born_time: current_millis(),
+            init_mode: INIT_MODE_MAX,
+            exp_type: subscription.expression_type.clone(),
+            exp: subscription.sub_string.clone(),
+        };
+        let frame = Frame::request(RequestCode::PopMessage, header);
+        let timeout = self.option.await_duration + self.client.option.request_timeout;
+        let mut shutdown = self.shutdown.clone();
+        let response = tokio::select! {
+            response = self.client.connections.invoke(addr, frame, timeout) => response?,
+            _ = shutdown.changed() => return Ok(vec![]),
+        };
+
+        let code = response.code;
+        if code == ResponseCode::PullNotFound as i32
+            || code == ResponseCode::PollingFull as i32
+            || code == ResponseCode::PollingTimeout as i32
+        {
+            return Ok(vec![]);
+        }
+        response.ensure_success()?;
+
+        let header = protocol::PopMessageResponseHeader::try_from(&response.ext_fields)?;
+        let mut messages = vec![];
+        for mut message in MessageExt::decode_batch(response.body())? {
+            // Redelivered messages are popped from a retry topic of the group, named `%RETRY%{group}` or
+            // `%RETRY%{group}_{topic}` depending on the broker version, and carry their topic as a property.
+            let popped_topic = message.topic.clone();
+            let retry = popped_topic.starts_with(message::RETRY_GROUP_TOPIC_PREFIX);
+            if let Some(topic) = message
+                .properties
+                .get(property::RETRY_TOPIC)
+                .filter(|_| retry)
+            {
+                message.topic = topic.clone();
+            }
+            let handle = ReceiptHandle {
+                topic: message.topic.clone(),
+                popped_topic: popped_topic.clone(),
+                start_offset: header
+                    .start_offset(retry, message.queue_id)
+                    .unwrap_or(message.queue_offset),
+                pop_time: header.pop_time,
+                invisible_time: header.invisible_time,
+                revive_qid: header.revive_qid,
+                retry,
+                broker_name: broker_name.to_owned(),
+                queue_id: message.queue_id,
+                queue_offset: message.queue_offset,
+            };
+            message
+                .properties
+                .insert(property::POP_CK.to_owned(), handle.to_string());
+            message
+                .properties
+                .insert(property::POP_TOPIC.to_owned(), popped_topic);
+            message
+                .properties
+                .entry(property::FIRST_POP_TIME.to_owned())
+                .or_insert_with(|| header.pop_time.to_string());
+            message.broker_name = broker_name.to_owned();
+
+            // Brokers filter by tag hash codes only; messages whose tags merely collide are done with.
+            if !filter::is_matched(subscription, &message) {
+                if let Err(e) = self.ack(&handle).await {
+                    eprintln!(
+                        "Failed to ack filtered message {}. Cause: {}",
+                        message.msg_id, e
+                    );
+                }
+                continue;
+            }
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+}
+
+fn consumer_data(
+    group: &str,
+    subscriptions: &RwLock<HashMap<String, protocol::SubscriptionData>>,
+) -> protocol::ConsumerData {
+    protocol::ConsumerData {
+        group_name: group.to_owned(),
+        consume_type: "CONSUME_POP".to_owned(),
+        message_model: "CLUSTERING".to_owned(),
+        consume_from_where: "CONSUME_FROM_LAST_OFFSET".to_owned(),
+        subscription_data_set: subscriptions
+            .read()
+            .map(|map| map.values().cloned().collect())
+            .unwrap_or_default(),
+        unit_mode: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::tests::mock_cluster;
+    use crate::message::tests::encode_stored;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_parse_receipt_handle() -> Result<(), ClientError> {
+        let handle =
+            ReceiptHandle::parse("T1", "%RETRY%G1_T1", "100 1700000000000 30000 2 1 b1 3 105")?;
+        assert!(handle.retry);
+        assert_eq!(handle.queue_id, 3);
+        assert_eq!(handle.queue_offset, 105);
+        assert_eq!(handle.next_visible_time(), 1700000030000);
+        assert_eq!(handle.popped_topic, "%RETRY%G1_T1");
+        assert_eq!(handle.to_string(), "100 1700000000000 30000 2 1 b1 3 105");
+
+        assert!(ReceiptHandle::parse("T1", "T1", "100 1700000000000 30000").is_err());
+        assert!(ReceiptHandle::parse("T1", "T1", "x 1700000000000 30000 2 0 b1 3 105").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_receive_ack_and_change_invisible_duration() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let server = mock_cluster(2, Arc::clone(&requests), |request, response| {
+            match request.code {
+                200050 => {
+                    let mut body = bytes::BytesMut::new();
+                    body.extend(encode_stored("T1", 7, &[("TAGS", "TagA")], b"body"));
+                    body.extend(encode_stored("T1", 8, &[("TAGS", "TagB")], b"body"));
+                    response.body = body.freeze();
+                    response.put_ext_field("popTime", "1700000000000");
+                    response.put_ext_field("invisibleTime", "30000");
+                    response.put_ext_field("reviveQid", "2");
+                    response.put_ext_field("startOffsetInfo", "0 1 7");
+                }
+                200053 => {
+                    response.put_ext_field("popTime", "1700000010000");
+                    response.put_ext_field("invisibleTime", "60000");
+                    response.put_ext_field("reviveQid", "3");
+                }
+                _ => {}
+            }
+            true
+        })
+        .await;
+
+        let client_option = ClientOption {
+            name_server: server,
+            ..Default::default()
+        };
+        let option = SimpleConsumerOption {
+            group: "G1".to_owned(),
+            ..Default::default()
+        };
+        let consumer = SimpleConsumer::new(client_option, option)?;
+        consumer.subscribe("T1", "TagA")?;
+        assert!(consumer.receive(16, Duration::from_secs(30)).await.is_err());
+        consumer.start().await?;
+
+        let messages = consumer.receive(16, Duration::from_secs(30)).await?;
+        assert_eq!(messages.len(), 1);
+        let handle = messages[0].receipt_handle().unwrap();
+        assert_eq!(handle.to_string(), "7 1700000000000 30000 2 0 b1 1 7");
+        assert_eq!(handle.next_visible_time(), 1700000030000);
+
+        let renewed = consumer
+            .change_invisible_duration(&handle, Duration::from_secs(60))
+            .await?;
+        assert_eq!(renewed.next_visible_time(), 1700000070000);
+        consumer.ack(&renewed).await?;
+        consumer.shutdown().await;
+        assert!(matches!(
+            consumer.start().await,
+            Err(ClientError::IllegalState(_))
+        ));
+
+        let requests = requests.lock().unwrap();
+        let pop = requests.iter().find(|r| r.code == 200050).unwrap();
+        assert_eq!(pop.ext_fields["queueId"], "-1");
+        assert_eq!(pop.ext_fields["invisibleTime"], "30000");
+        assert_eq!(pop.ext_fields["exp"], "TagA");
+        let heartbeat = requests.iter().find(|r| r.code == 34).unwrap();
+        assert!(String::from_utf8_lossy(&heartbeat.body).contains("CONSUME_POP"));
+        let change = requests.iter().find(|r| r.code == 200053).unwrap();
+        assert_eq!(change.ext_fields["invisibleTime"], "60000");
+        // The message tagged `TagB` is acknowledged as filtered out, before the received one.
+        let acks: Vec<&Frame> = requests.iter().filter(|r| r.code == 200051).collect();
+        assert_eq!(acks.len(), 2);
+        assert_eq!(acks[0].ext_fields["offset"], "8");
+        assert_eq!(acks[1].ext_fields["topic"], "T1");
+        assert_eq!(acks[1].ext_fields["offset"], "7");
+        assert_eq!(
+            acks[1].ext_fields["extraInfo"],
+            "7 1700000010000 60000 3 0 b1 1 7"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ack_redelivered_message() -> Result<(), ClientError> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let server = mock_cluster(2, Arc::clone(&requests), |request, response| {
+            if request.code == 200050 {
+                let properties = [("TAGS", "TagA"), ("RETRY_TOPIC", "T1")];
+                response.body = encode_stored("%RETRY%G1_T1", 4, &properties, b"body");
+                response.put_ext_field("popTime", "1700000000000");
+                response.put_ext_field("invisibleTime", "30000");
+                response.put_ext_field("reviveQid", "2");
+                response.put_ext_field("startOffsetInfo", "1 1 4");
+            }
+            true
+        })
+        .await;
+
+        let client_option = ClientOption {
+            name_server: server,
+            ..Default::default()
+        };
+        let option = SimpleConsumerOption {
+            group: "G1".to_owned(),
+            ..Default::default()
+        };
+        let consumer = SimpleConsumer::new(client_option, option)?;
+        consumer.subscribe("T1", "TagA")?;
+        consumer.start().await?;
+
+        let messages = consumer.receive(16, Duration::from_secs(30)).await?;
+        assert_eq!(messages[0].topic, "T1");
+        let handle = messages[0].receipt_handle().unwrap();
+        assert_eq!(handle.topic, "T1");
+        assert_eq!(handle.to_string(), "4 1700000000000 30000 2 1 b1 1 4");
+        consumer.ack(&handle).await?;
+        consumer.shutdown().await;
+
+        // The message is acknowledged to the retry topic it is popped from.
+        let requests = requests.lock().unwrap();
+        let ack = requests.iter().find(|r| r.code == 200051).unwrap();
+        assert_eq!(ack.ext_fields["topic"], "%RETRY%G1_T1");
+        Ok(())
+    }
+}
@@ -0,0 +1,641 @@
+//!
+//! Parse and evaluate SQL92 filter expressions over message properties, in the dialect supported by brokers, for
+//! example `region = 'eu' AND amount > 100`.
+//!
+//! Supported are comparisons (`=`, `<>`, `<`, `<=`, `>`, `>=`), `[NOT] BETWEEN .. AND ..`, `[NOT] IN (..)`,
+//! `IS [NOT] NULL`, `[NOT] CONTAINS`, `[NOT] STARTSWITH`, `[NOT] ENDSWITH`, combined with `AND`, `OR`, `NOT` and
+//! parentheses. Operands are property names, numbers, quoted strings, `TRUE`, `FALSE` and `NULL`.
+//!
+use crate::error::ClientError;
+use crate::message::MessageExt;
+use std::cmp::Ordering;
+
+/// A parsed SQL92 filter expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    root: Node,
+}
+
+impl Expression {
+    /// Parse and validate the expression.
+    ///
+    /// # Errors
+    /// Raise ClientError::InvalidExpression describing the first offending token and its position.
+    pub fn parse(expression: &str) -> Result<Self, ClientError> {
+        let error = |reason: String| ClientError::InvalidExpression {
+            expression: expression.to_owned(),
+            reason,
+        };
+        let tokens = tokenize(expression).map_err(error)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let root = parser.parse_or().map_err(error)?;
+        if let Some((token, pos)) = parser.tokens.get(parser.pos) {
+            return Err(error(format!(
+                "unexpected {} at position {}",
+                token.describe(),
+                pos
+            )));
+        }
+        Ok(Self { root })
+    }
+
+    /// Evaluate the expression against user attributes and system properties of the message.
+    ///
+    /// Conditions involving absent properties are unknown, which, as in SQL, does not match.
+    pub fn evaluate(&self, message: &MessageExt) -> bool {
+        self.root.evaluate(message) == Value::Bool(true)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    Keyword(Keyword),
+    Op(CompareOp),
+    LeftParen,
+    RightParen,
+    Comma,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(name) => format!("identifier `{}`", name),
+            Token::Number(n) => format!("number `{}`", n),
+            Token::Str(s) => format!("string '{}'", s),
+            Token::Keyword(k) => format!("keyword `{}`", format!("{:?}", k).to_uppercase()),
+            Token::Op(op) => format!("operator `{}`", op.as_str()),
+            Token::LeftParen => "`(`".to_owned(),
+            Token::RightParen => "`)`".to_owned(),
+            Token::Comma => "`,`".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Keyword {
+    And,
+    Or,
+    Not,
+    Between,
+    In,
+    Is,
+    Null,
+    True,
+    False,
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+impl Keyword {
+    fn from_word(word: &str) -> Option<Self> {
+        let keyword = match word.to_ascii_uppercase().as_str() {
+            "AND" => Keyword::And,
+            "OR" => Keyword::Or,
+            "NOT" => Keyword::Not,
+            "BETWEEN" => Keyword::Between,
+            "IN" => Keyword::In,
+            "IS" => Keyword::Is,
+            "NULL" => Keyword::Null,
+            "TRUE" => Keyword::True,
+            "FALSE" => Keyword::False,
+            "CONTAINS" => Keyword::Contains,
+            "STARTSWITH" => Keyword::StartsWith,
+            "ENDSWITH" => Keyword::EndsWith,
+            _ => return None,
+        };
+        Some(keyword)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CompareOp::Eq => "=",
+            CompareOp::Ne => "<>",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+        }
+    }
+}
+
+/// Split the expression into tokens, each paired with its character position.
+fn tokenize(expression: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => {
+                i += 1;
+                Token::LeftParen
+            }
+            ')' => {
+                i += 1;
+                Token::RightParen
+            }
+            ',' => {
+                i += 1;
+                Token::Comma
+            }
+            '=' => {
+                i += 1;
+                Token::Op(CompareOp::Eq)
+            }
+            '<' | '>' => {
+                i += 1;
+                let next = chars.get(i).copied();
+                let op = match (c, next) {
+                    ('<', Some('=')) => CompareOp::Le,
+                    ('<', Some('>')) => CompareOp::Ne,
+                    ('>', Some('=')) => CompareOp::Ge,
+                    ('<', _) => CompareOp::Lt,
+                    _ => CompareOp::Gt,
+                };
+                if matches!(op, CompareOp::Le | CompareOp::Ne | CompareOp::Ge) {
+                    i += 1;
+                }
+                Token::Op(op)
+            }
+            '\'' => {
+                // Quotes inside strings are escaped by doubling them.
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some('\'') if chars.get(i + 1) == Some(&'\'') => {
+                            s.push('\'');
+                            i += 2;
+                        }
+                        Some('\'') => {
+                            i += 1;
+                            break;
+                        }
+                        Some(c) => {
+                            s.push(*c);
+                            i += 1;
+                        }
+                        None => return Err(format!("unterminated string at position {}", start)),
+                    }
+                }
+                Token::Str(s)
+            }
+            c if c.is_ascii_digit() || c == '.' || c == '-' => {
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                    i += 1;
+                }
+                let literal: String = chars[start..i].iter().collect();
+                let number = literal
+                    .trim_end_matches(['L', 'l'])
+                    .parse::<f64>()
+                    .map_err(|_e| {
+                        format!("malformed number `{}` at position {}", literal, start)
+                    })?;
+                Token::Number(number)
+            }
+            c if c.is_alphabetic() || c == '_' || c == '$' => {
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '$' | '.'))
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                match Keyword::from_word(&word) {
+                    Some(keyword) => Token::Keyword(keyword),
+                    None => Token::Ident(word),
+                }
+            }
+            c => {
+                return Err(format!(
+                    "unexpected character `{}` at position {}",
+                    c, start
+                ))
+            }
+        };
+        tokens.push((token, start));
+    }
+    if tokens.is_empty() {
+        return Err("expression is empty".to_owned());
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Literal(Value),
+    Property(String),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Compare(CompareOp, Box<Node>, Box<Node>),
+    Between(Box<Node>, Box<Node>, Box<Node>),
+    In(Box<Node>, Vec<Value>),
+    IsNull(Box<Node>),
+    Contains(Box<Node>, String),
+    StartsWith(Box<Node>, String),
+    EndsWith(Box<Node>, String),
+}
+
+impl Node {
+    /// Whether the node yields a boolean, as opposed to an operand of a comparison.
+    fn is_boolean(&self) -> bool {
+        match self {
+            Node::Literal(value) => matches!(value, Value::Bool(_)),
+            Node::Property(_) => false,
+            _ => true,
+        }
+    }
+
+    fn evaluate(&self, message: &MessageExt) -> Value {
+        match self {
+            Node::Literal(value) => value.clone(),
+            Node::Property(name) => message
+                .attributes
+                .get(name)
+                .or_else(|| message.properties.get(name))
+                .map(|value| Value::Str(value.clone()))
+                .unwrap_or(Value::Null),
+            Node::And(left, right) => match (left.evaluate(message), right.evaluate(message)) {
+                (Value::Bool(false), _) | (_, Value::Bool(false)) => Value::Bool(false),
+                (Value::Bool(true), Value::Bool(true)) => Value::Bool(true),
+                _ => Value::Null,
+            },
+            Node::Or(left, right) => match (left.evaluate(message), right.evaluate(message)) {
+                (Value::Bool(true), _) | (_, Value::Bool(true)) => Value::Bool(true),
+                (Value::Bool(false), Value::Bool(false)) => Value::Bool(false),
+                _ => Value::Null,
+            },
+            Node::Not(node) => match node.evaluate(message) {
+                Value::Bool(b) => Value::Bool(!b),
+                _ => Value::Null,
+            },
+            Node::Compare(op, left, right) => {
+                let ordering = left.evaluate(message).compare(&right.evaluate(message));
+                match ordering {
+                    Some(ordering) => Value::Bool(match op {
+                        CompareOp::Eq => ordering == Ordering::Equal,
+                        CompareOp::Ne => ordering != Ordering::Equal,
+                        CompareOp::Lt => ordering == Ordering::Less,
+                        CompareOp::Le => ordering != Ordering::Greater,
+                        CompareOp::Gt => ordering == Ordering::Greater,
+                        CompareOp::Ge => ordering != Ordering::Less,
+                    }),
+                    None => Value::Null,
+                }
+            }
+            Node::Between(node, low, high) => {
+                let value = node.evaluate(message);
+                match (
+                    value.compare(&low.evaluate(message)),
+                    value.compare(&high.evaluate(message)),
+                ) {
+                    (Some(low), Some(high)) => {
+                        Value::Bool(low != Ordering::Less && high != Ordering::Greater)
+                    }
+                    _ => Value::Null,
+                }
+            }
+            Node::In(node, values) => match node.evaluate(message) {
+                Value::Null => Value::Null,
+                value => Value::Bool(
+                    values
+                        .iter()
+                        .any(|v| value.compare(v) == Some(Ordering::Equal)),
+                ),
+            },
+            Node::IsNull(node) => Value::Bool(node.evaluate(message) == Value::Null),
+            Node::Contains(node, s) => node.evaluate(message).test_str(|v| v.contains(s.as_str())),
+            Node::StartsWith(node, s) => node
+                .evaluate(message)
+                .test_str(|v| v.starts_with(s.as_str())),
+            Node::EndsWith(node, s) => node.evaluate(message).test_str(|v| v.ends_with(s.as_str())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+}
+
+impl Value {
+    /// Compare two values. Property values are strings, which are converted when compared with numbers or booleans.
+    fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Str(s), Value::Number(n)) => s.trim().parse::<f64>().ok()?.partial_cmp(n),
+            (Value::Number(n), Value::Str(s)) => n.partial_cmp(&s.trim().parse::<f64>().ok()?),
+            (Value::Str(s), Value::Bool(b)) => s.parse::<bool>().ok().map(|s| s.cmp(b)),
+            (Value::Bool(b), Value::Str(s)) => s.parse::<bool>().ok().map(|s| b.cmp(&s)),
+            _ => None,
+        }
+    }
+
+    fn test_str(&self, f: impl Fn(&str) -> bool) -> Value {
+        match self {
+            Value::Str(s) => Value::Bool(f(s)),
+            _ => Value::Null,
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .map(|(token, _)| token.clone())
+            .ok_or_else(|| "unexpected end of expression".to_owned())?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn unexpected(&self, expected: &str) -> String {
+        match self.tokens.get(self.pos) {
+            Some((token, pos)) => format!(
+                "expected {} but found {} at position {}",
+                expected,
+                token.describe(),
+                pos
+            ),
+            None => format!("expected {} but reached end of expression", expected),
+        }
+    }
+
+    fn accept(&mut self, keyword: Keyword) -> bool {
+        if self.peek() == Some(&Token::Keyword(keyword)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token, expected: &str) -> Result<(), String> {
+        if self.peek() == Some(&token) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    /// Parse a condition, rejecting bare operands such as a property name that are not followed by a comparison.
+    fn parse_condition(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Node, String>,
+    ) -> Result<Node, String> {
+        let node = parse(self)?;
+        if node.is_boolean() {
+            Ok(node)
+        } else {
+            Err(self.unexpected("a comparison"))
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Node, String> {
+        let mut node = self.parse_condition(Self::parse_and)?;
+        while self.accept(Keyword::Or) {
+            let right = self.parse_condition(Self::parse_and)?;
+            node = Node::Or(Box::new(node), Box::new(right));
+        }
+        Ok(node)
+    }
+
+    fn parse_and(&mut self) -> Result<Node, String> {
+        let mut node = self.parse_condition(Self::parse_not)?;
+        while self.accept(Keyword::And) {
+            let right = self.parse_condition(Self::parse_not)?;
+            node = Node::And(Box::new(node), Box::new(right));
+        }
+        Ok(node)
+    }
+
+    fn parse_not(&mut self) -> Result<Node, String> {
+        if self.accept(Keyword::Not) {
+            let node = self.parse_condition(Self::parse_not)?;
+            return Ok(Node::Not(Box::new(node)));
+        }
+        self.parse_predicate()
+    }
+
+    fn parse_predicate(&mut self) -> Result<Node, String> {
+        if self.peek() == Some(&Token::LeftParen) {
+            self.pos += 1;
+            let node = self.parse_or()?;
+            self.expect(Token::RightParen, "`)`")?;
+            return Ok(node);
+        }
+
+        let operand = self.parse_operand()?;
+        let negated = self.accept(Keyword::Not);
+        let node = match self.peek().cloned() {
+            Some(Token::Op(op)) if !negated => {
+                self.pos += 1;
+                let right = self.parse_operand()?;
+                return Ok(Node::Compare(op, Box::new(operand), Box::new(right)));
+            }
+            Some(Token::Keyword(Keyword::Between)) => {
+                self.pos += 1;
+                let low = self.parse_operand()?;
+                if !self.accept(Keyword::And) {
+                    return Err(self.unexpected("AND"));
+                }
+                let high = self.parse_operand()?;
+                Node::Between(Box::new(operand), Box::new(low), Box::new(high))
+            }
+            Some(Token::Keyword(Keyword::In)) => {
+                self.pos += 1;
+                self.expect(Token::LeftParen, "`(`")?;
+                let mut values = vec![self.parse_literal()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.pos += 1;
+                    values.push(self.parse_literal()?);
+                }
+                self.expect(Token::RightParen, "`)` or `,`")?;
+                Node::In(Box::new(operand), values)
+            }
+            Some(Token::Keyword(Keyword::Contains)) => {
+                self.pos += 1;
+                Node::Contains(Box::new(operand), self.parse_string()?)
+            }
+            Some(Token::Keyword(Keyword::StartsWith)) => {
+                self.pos += 1;
+                Node::StartsWith(Box::new(operand), self.parse_string()?)
+            }
+            Some(Token::Keyword(Keyword::EndsWith)) => {
+                self.pos += 1;
+                Node::EndsWith(Box::new(operand), self.parse_string()?)
+            }
+            Some(Token::Keyword(Keyword::Is)) if !negated => {
+                self.pos += 1;
+                let not_null = self.accept(Keyword::Not);
+                if !self.accept(Keyword::Null) {
+                    return Err(self.unexpected("NULL"));
+                }
+                let node = Node::IsNull(Box::new(operand));
+                return Ok(if not_null {
+                    Node::Not(Box::new(node))
+                } else {
+                    node
+                });
+            }
+            _ if negated => {
+                return Err(self.unexpected("BETWEEN, IN, CONTAINS, STARTSWITH or ENDSWITH"))
+            }
+            _ => return Ok(operand),
+        };
+        Ok(if negated {
+            Node::Not(Box::new(node))
+        } else {
+            node
+        })
+    }
+
+    fn parse_operand(&mut self) -> Result<Node, String> {
+        match self.peek() {
+            Some(Token::Ident(name)) => {
+                let node = Node::Property(name.clone());
+                self.pos += 1;
+                Ok(node)
+            }
+            Some(Token::LeftParen) => {
+                self.pos += 1;
+                let node = self.parse_or()?;
+                self.expect(Token::RightParen, "`)`")?;
+                Ok(node)
+            }
+            _ => self.parse_literal().map(Node::Literal),
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Value, String> {
+        let value = match self.peek() {
+            Some(Token::Number(n)) => Value::Number(*n),
+            Some(Token::Str(s)) => Value::Str(s.clone()),
+            Some(Token::Keyword(Keyword::True)) => Value::Bool(true),
+            Some(Token::Keyword(Keyword::False)) => Value::Bool(false),
+            Some(Token::Keyword(Keyword::Null)) => Value::Null,
+            _ => return Err(self.unexpected("a property, number, string or boolean")),
+        };
+        self.next()?;
+        Ok(value)
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Str(s)) => {
+                let s = s.clone();
+                self.pos += 1;
+                Ok(s)
+            }
+            _ => Err(self.unexpected("a string")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::tests::encode_stored;
+
+    fn message(properties: &[(&str, &str)]) -> MessageExt {
+        MessageExt::decode_batch(encode_stored("T1", 0, properties, b"body"))
+            .unwrap()
+            .remove(0)
+    }
+
+    fn matches(expression: &str, message: &MessageExt) -> bool {
+        Expression::parse(expression).unwrap().evaluate(message)
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let m = message(&[("region", "eu"), ("amount", "150"), ("TAGS", "TagA")]);
+        assert!(matches("region = 'eu' AND amount > 100", &m));
+        assert!(!matches("region = 'eu' AND amount > 200", &m));
+        assert!(matches("region = 'us' OR amount >= 150", &m));
+        assert!(matches("NOT (region <> 'eu')", &m));
+        assert!(matches("amount BETWEEN 100 AND 200", &m));
+        assert!(matches("amount NOT BETWEEN 1 AND 2", &m));
+        assert!(matches("region IN ('eu', 'us')", &m));
+        assert!(!matches("region NOT IN ('eu', 'us')", &m));
+        assert!(matches("missing IS NULL AND region IS NOT NULL", &m));
+        assert!(matches("region STARTSWITH 'e' AND region ENDSWITH 'u'", &m));
+        assert!(matches("TAGS CONTAINS 'Tag'", &m));
+        assert!(matches("TRUE", &m));
+    }
+
+    #[test]
+    fn test_absent_property_does_not_match() {
+        let m = message(&[("region", "eu")]);
+        assert!(!matches("amount > 100", &m));
+        assert!(!matches("NOT (amount > 100)", &m));
+        assert!(matches("amount > 100 OR region = 'eu'", &m));
+        assert!(!matches("region = 1", &m));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        let reason = |expression: &str| match Expression::parse(expression) {
+            Err(ClientError::InvalidExpression { reason, .. }) => reason,
+            other => panic!("{:?} should be rejected, got {:?}", expression, other),
+        };
+        assert_eq!(reason(""), "expression is empty");
+        assert_eq!(reason("region = 'eu"), "unterminated string at position 9");
+        assert_eq!(
+            reason("region = 'eu' AND"),
+            "expected a property, number, string or boolean but reached end of expression"
+        );
+        assert_eq!(
+            reason("region"),
+            "expected a comparison but reached end of expression"
+        );
+        assert_eq!(
+            reason("region AND a = 1"),
+            "expected a comparison but found keyword `AND` at position 7"
+        );
+        assert_eq!(reason("a > 1 b"), "unexpected identifier `b` at position 6");
+        assert_eq!(
+            reason("a IN (1 2)"),
+            "expected `)` or `,` but found number `2` at position 8"
+        );
+        assert_eq!(reason("a # 1"), "unexpected character `#` at position 2");
+    }
+}
@@ -0,0 +1,141 @@
+//!
+//! Validate topics, groups and messages before they reach brokers, following the rules of the Java client.
+//!
+use crate::error::ClientError;
+use crate::message::{self, Message};
+
+/// Longest topic name accepted by brokers.
+pub const TOPIC_MAX_LENGTH: usize = 127;
+
+/// Longest group name accepted by brokers.
+pub const GROUP_MAX_LENGTH: usize = 255;
+
+/// Largest message accepted by brokers with the default `maxMessageSize`.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Topic whose route brokers use as template when creating topics automatically.
+pub(crate) const AUTO_CREATE_TOPIC_KEY_TOPIC: &str = "TBW102";
+
+/// Prefix of topics brokers use internally, such as those holding scheduled and half transactional messages.
+const SYSTEM_TOPIC_PREFIX: &str = "RMQ_SYS_";
+
+fn is_valid_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '%' | '|' | '_' | '-')
+}
+
+/// Check the topic name is non-empty, short enough and made of letters, digits and `%|_-` only.
+///
+/// # Errors
+/// Raise ClientError::IllegalTopic or ClientError::TopicTooLong otherwise.
+pub(crate) fn check_topic(topic: &str) -> Result<(), ClientError> {
+    if topic.is_empty() || !topic.chars().all(is_valid_char) {
+        return Err(ClientError::IllegalTopic(topic.to_owned()));
+    }
+    if topic.len() > TOPIC_MAX_LENGTH {
+        return Err(ClientError::TopicTooLong {
+            topic: topic.to_owned(),
+            max: TOPIC_MAX_LENGTH,
+        });
+    }
+    Ok(())
+}
+
+/// Check the group name is non-empty, short enough and made of letters, digits and `%|_-` only.
+///
+/// # Errors
+/// Raise ClientError::IllegalGroup or ClientError::GroupTooLong otherwise.
+pub(crate) fn check_group(group: &str) -> Result<(), ClientError> {
+    if group.is_empty() || !group.chars().all(is_valid_char) {
+        return Err(ClientError::IllegalGroup(group.to_owned()));
+    }
+    if group.len() > GROUP_MAX_LENGTH {
+        return Err(ClientError::GroupTooLong {
+            group: group.to_owned(),
+            max: GROUP_MAX_LENGTH,
+        });
+    }
+    Ok(())
+}
+
+/// Whether applications are barred from publishing to the topic, which brokers reserve for themselves.
+pub(crate) fn is_reserved_topic(topic: &str) -> bool {
+    topic == AUTO_CREATE_TOPIC_KEY_TOPIC
+        || topic.starts_with(SYSTEM_TOPIC_PREFIX)
+        || topic.starts_with(message::RETRY_GROUP_TOPIC_PREFIX)
+        || topic.starts_with(message::DLQ_GROUP_TOPIC_PREFIX)
+}
+
+/// Check the message may be published: its topic is valid and not reserved, and its body is neither empty nor
+/// larger than `max_message_size`.
+///
+/// # Errors
+/// Raise the ClientError variant of the first rule broken.
+pub(crate) fn check_message(message: &Message, max_message_size: usize) -> Result<(), ClientError> {
+    check_topic(&message.topic)?;
+    if is_reserved_topic(&message.topic) {
+        return Err(ClientError::ReservedTopic(message.topic.clone()));
+    }
+    if message.body.is_empty() {
+        return Err(ClientError::EmptyBody);
+    }
+    check_message_size(message.body.len(), max_message_size)
+}
+
+/// # Errors
+/// Raise ClientError::MessageTooLarge if `size` exceeds `max_message_size`.
+pub(crate) fn check_message_size(size: usize, max_message_size: usize) -> Result<(), ClientError> {
+    if size > max_message_size {
+        return Err(ClientError::MessageTooLarge {
+            size,
+            max: max_message_size,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_topic_and_group() {
+        assert!(check_topic("Topic_1-a%b|c").is_ok());
+        assert!(matches!(check_topic(""), Err(ClientError::IllegalTopic(_))));
+        assert!(matches!(
+            check_topic("a.b"),
+            Err(ClientError::IllegalTopic(_))
+        ));
+        assert!(matches!(
+            check_topic(&"t".repeat(128)),
+            Err(ClientError::TopicTooLong { max: 127, .. })
+        ));
+        assert!(check_group(&"g".repeat(255)).is_ok());
+        assert!(matches!(
+            check_group(&"g".repeat(256)),
+            Err(ClientError::GroupTooLong { max: 255, .. })
+        ));
+        assert!(matches!(
+            check_group("group 1"),
+            Err(ClientError::IllegalGroup(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_message() {
+        assert!(check_message(&Message::new("T1", "body"), 4).is_ok());
+        for topic in ["TBW102", "RMQ_SYS_TRACE_TOPIC", "%RETRY%G1", "%DLQ%G1"] {
+            assert!(matches!(
+                check_message(&Message::new(topic, "body"), 4),
+                Err(ClientError::ReservedTopic(_))
+            ));
+        }
+        assert!(matches!(
+            check_message(&Message::new("T1", ""), 4),
+            Err(ClientError::EmptyBody)
+        ));
+        assert!(matches!(
+            check_message(&Message::new("T1", "bodies"), 4),
+            Err(ClientError::MessageTooLarge { size: 6, max: 4 })
+        ));
+    }
+}